@@ -0,0 +1,134 @@
+// Compares the owned (`Packet`) and borrowed (`PacketRef`) parsing paths.
+//
+// Run with `cargo bench`. A counting global allocator reports how many
+// allocations each path makes, which is what actually hurts at high packet
+// rates (the parsing itself is a handful of byte reads either way).
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use segmented_file_system_client::{
+    file_manager::FileManager,
+    packet::{Packet, PacketRef},
+};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const PACKET_COUNT: u16 = 60_000;
+// Every packet is received this many times, as happens with retransmits
+const DUPLICATES: usize = 2;
+
+struct Measurement {
+    elapsed: Duration,
+    allocations: usize,
+    bytes: usize,
+}
+
+fn measure(f: impl FnOnce()) -> Measurement {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    f();
+    Measurement {
+        elapsed: start.elapsed(),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        bytes: ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes,
+    }
+}
+
+fn report(name: &str, m: &Measurement, packets: usize) {
+    let secs = m.elapsed.as_secs_f64();
+    #[allow(clippy::cast_precision_loss)]
+    let rate = packets as f64 / secs;
+    println!(
+        "{name:<28} {:>10.2?} {:>12.0} pkt/s {:>8} allocs {:>12} bytes",
+        m.elapsed, rate, m.allocations, m.bytes
+    );
+}
+
+fn datagrams() -> Vec<Vec<u8>> {
+    let mut datagrams = vec![b"\x00\x07bench.bin".to_vec()];
+    for packet_number in 0..PACKET_COUNT {
+        let status = if packet_number == PACKET_COUNT - 1 {
+            3
+        } else {
+            1
+        };
+        let mut datagram = vec![status, 7];
+        datagram.extend_from_slice(&packet_number.to_be_bytes());
+        datagram.resize(1028, b'x');
+        datagrams.push(datagram);
+    }
+    datagrams
+}
+
+fn main() {
+    let datagrams = datagrams();
+    let received: Vec<&[u8]> = (0..DUPLICATES)
+        .flat_map(|_| datagrams.iter().map(Vec::as_slice))
+        .collect();
+
+    println!(
+        "{} datagrams ({} unique)\n",
+        received.len(),
+        datagrams.len()
+    );
+
+    let m = measure(|| {
+        for datagram in &received {
+            black_box(Packet::try_from(*datagram).unwrap());
+        }
+    });
+    report("parse Packet", &m, received.len());
+
+    let m = measure(|| {
+        for datagram in &received {
+            black_box(PacketRef::try_from(*datagram).unwrap());
+        }
+    });
+    report("parse PacketRef", &m, received.len());
+
+    let mut file_manager = FileManager::default();
+    let m = measure(|| {
+        for datagram in &received {
+            file_manager
+                .process_packet(Packet::try_from(*datagram).unwrap())
+                .unwrap();
+        }
+    });
+    assert!(file_manager.received_all_packets());
+    report("assemble Packet", &m, received.len());
+
+    let mut file_manager = FileManager::default();
+    let m = measure(|| {
+        for datagram in &received {
+            file_manager
+                .process_packet_ref(PacketRef::try_from(*datagram).unwrap())
+                .unwrap();
+        }
+    });
+    assert!(file_manager.received_all_packets());
+    report("assemble PacketRef", &m, received.len());
+}
@@ -0,0 +1,133 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use segmented_file_system_client::{
+    file_manager::FileManager,
+    packet::{Data, FileId, Header, Manifest, Packet, Parity},
+};
+
+// A packet the server could send. IDs and packet numbers are kept small so
+// the fuzzer hits the same files and chunks often enough to finish them.
+#[derive(Arbitrary, Debug)]
+enum FuzzPacket {
+    Header {
+        file_id: u8,
+        file_name: String,
+    },
+    Data {
+        file_id: u8,
+        packet_number: u8,
+        is_last_packet: bool,
+        payload: Vec<u8>,
+    },
+    Manifest {
+        file_ids: Vec<u8>,
+        total: Option<u8>,
+    },
+    Parity {
+        file_id: u8,
+        first_packet_number: u8,
+        count: u8,
+        is_last_group: bool,
+        length_xor: u16,
+        payload: Vec<u8>,
+    },
+}
+
+impl From<FuzzPacket> for Packet {
+    fn from(packet: FuzzPacket) -> Self {
+        match packet {
+            FuzzPacket::Header { file_id, file_name } => Packet::Header(Header {
+                file_id: FileId::from(file_id % 4),
+                file_name: file_name.into(),
+                expected_packet_count: 0,
+                metadata: None,
+            }),
+            FuzzPacket::Data {
+                file_id,
+                packet_number,
+                is_last_packet,
+                payload,
+            } => Packet::Data(Data {
+                file_id: FileId::from(file_id % 4),
+                packet_number: u32::from(packet_number % 32),
+                is_last_packet,
+                payload,
+            }),
+            FuzzPacket::Manifest { file_ids, total } => Packet::Manifest(Manifest {
+                file_ids: file_ids
+                    .into_iter()
+                    .map(|file_id| FileId::from(file_id % 4))
+                    .collect(),
+                total: total.map(|total| u32::from(total % 5)),
+            }),
+            FuzzPacket::Parity {
+                file_id,
+                first_packet_number,
+                count,
+                is_last_group,
+                length_xor,
+                payload,
+            } => Packet::Parity(Parity {
+                file_id: FileId::from(file_id % 4),
+                first_packet_number: u32::from(first_packet_number % 32),
+                count: count % 8,
+                is_last_group,
+                length_xor,
+                payload,
+            }),
+        }
+    }
+}
+
+// Feeding any sequence of packets must never panic, and whenever the
+// FileManager says it is done every file must have a name and every chunk
+// from 0 up to its last packet (only the files a manifest lists, once one has
+// arrived). Lenient mode never rejects a packet; strict
+// mode may, but then every finished file must also have valid chunk sizes.
+fuzz_target!(|input: (bool, Vec<FuzzPacket>)| {
+    let (strict_chunks, packets) = input;
+    let mut file_manager = FileManager {
+        strict_chunks,
+        ..Default::default()
+    };
+
+    for packet in packets {
+        let result = file_manager.process_packet(packet.into());
+        assert!(strict_chunks || result.is_ok());
+
+        if file_manager.received_all_packets() {
+            for file_id in 0..4 {
+                if !file_manager.is_expected(file_id) {
+                    continue;
+                }
+                let Some(group) = file_manager.get_packet_group(file_id) else {
+                    assert!(
+                        file_manager.manifest.is_none(),
+                        "manifest file {file_id} never arrived"
+                    );
+                    continue;
+                };
+                assert!(group.file_name.is_some(), "file {file_id} has no name");
+                if strict_chunks {
+                    assert!(
+                        group.validate().is_valid(),
+                        "file {file_id}: {}",
+                        group.validate()
+                    );
+                }
+                let expected = group
+                    .expected_packet_count
+                    .expect("complete file without a last packet");
+                for packet_number in 0..expected {
+                    let packet_number = u32::try_from(packet_number).unwrap();
+                    assert!(
+                        group.packets.contains_key(&packet_number),
+                        "file {file_id} reported complete without packet {packet_number}"
+                    );
+                }
+            }
+        }
+    }
+});
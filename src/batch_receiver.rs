@@ -0,0 +1,126 @@
+use std::{io, net::UdpSocket};
+
+use crate::packet::MAX_DATAGRAM_SIZE;
+
+// One spare byte so an oversized datagram shows up as too long rather than
+// being silently cut down to a size that parses
+const SLOT_SIZE: usize = MAX_DATAGRAM_SIZE + 1;
+
+// BatchReceiver owns a pool of datagram-sized buffers that is reused for every
+// receive. On Linux one `recvmmsg` call can fill the whole pool; everywhere
+// else it falls back to a single `recv` per call, same as the original loop.
+pub struct BatchReceiver {
+    buffers: Vec<u8>,
+    lengths: Vec<usize>,
+    received: usize,
+}
+
+impl BatchReceiver {
+    /// Creates a receiver that can pull up to `batch_size` datagrams per call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is zero.
+    #[must_use]
+    pub fn new(batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be at least 1");
+        BatchReceiver {
+            buffers: vec![0; batch_size * SLOT_SIZE],
+            lengths: vec![0; batch_size],
+            received: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn batch_size(&self) -> usize {
+        self.lengths.len()
+    }
+
+    /// Blocks until at least one datagram arrives, then takes as many more as
+    /// are already queued (up to the batch size) without blocking again.
+    /// Returns the number of datagrams now available from `datagrams`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the underlying receive fails.
+    /// Interrupted system calls are retried.
+    pub fn recv(&mut self, sock: &UdpSocket) -> io::Result<usize> {
+        self.received = 0;
+        loop {
+            match self.recv_batch(sock) {
+                Ok(count) => {
+                    self.received = count;
+                    return Ok(count);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // The datagrams filled in by the last call to `recv`, in arrival order
+    pub fn datagrams(&self) -> impl Iterator<Item = &[u8]> {
+        self.buffers
+            .chunks_exact(SLOT_SIZE)
+            .zip(&self.lengths)
+            .take(self.received)
+            .map(|(buffer, &len)| &buffer[..len])
+    }
+
+    #[cfg(target_os = "linux")]
+    fn recv_batch(&mut self, sock: &UdpSocket) -> io::Result<usize> {
+        use std::os::fd::AsRawFd;
+
+        if self.batch_size() == 1 {
+            return self.recv_single(sock);
+        }
+
+        let mut iovecs: Vec<libc::iovec> = self
+            .buffers
+            .chunks_exact_mut(SLOT_SIZE)
+            .map(|buffer| libc::iovec {
+                iov_base: buffer.as_mut_ptr().cast(),
+                iov_len: buffer.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iovec| {
+                // SAFETY: mmsghdr is a plain C struct for which all zeroes is valid
+                let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+
+        let vlen = u32::try_from(headers.len()).unwrap_or(u32::MAX);
+        // SAFETY: every header points at exactly one iovec, and every iovec at a
+        // distinct SLOT_SIZE slice of `self.buffers`; all of them outlive the call.
+        let count = unsafe {
+            libc::recvmmsg(
+                sock.as_raw_fd(),
+                headers.as_mut_ptr(),
+                vlen,
+                libc::MSG_WAITFORONE,
+                std::ptr::null_mut(),
+            )
+        };
+        let count = usize::try_from(count).map_err(|_| io::Error::last_os_error())?;
+
+        for (length, header) in self.lengths.iter_mut().zip(&headers).take(count) {
+            *length = header.msg_len as usize;
+        }
+        Ok(count)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn recv_batch(&mut self, sock: &UdpSocket) -> io::Result<usize> {
+        self.recv_single(sock)
+    }
+
+    fn recv_single(&mut self, sock: &UdpSocket) -> io::Result<usize> {
+        self.lengths[0] = sock.recv(&mut self.buffers[..SLOT_SIZE])?;
+        Ok(1)
+    }
+}
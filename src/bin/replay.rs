@@ -0,0 +1,150 @@
+#![warn(clippy::style)]
+#![warn(clippy::perf)]
+#![warn(clippy::complexity)]
+#![warn(clippy::correctness)]
+#![warn(clippy::pedantic)]
+
+use std::net::UdpSocket;
+
+use segmented_file_system_client::{
+    errors::{self, ClientError},
+    file_manager::FileManager,
+    packet::PacketParser,
+    pcap::{self, PcapWriter},
+    replay,
+};
+
+const USAGE: &str = "usage: replay <capture or pcap file> [--server-port PORT] \
+                     [--serve ADDR [--realtime] | --export-pcap FILE] [--strict-chunks] [--stream] [--write]";
+
+// What to do with a capture
+#[allow(clippy::struct_excessive_bools)] // they're independent command line flags
+struct ReplayConfig {
+    capture_file: String,
+    // re-serve the capture over UDP on this address instead of assembling it
+    serve_addr: Option<String>,
+    realtime: bool,
+    // convert the capture to pcap instead of assembling it
+    export_pcap: Option<String>,
+    // which side of a pcap conversation is the server
+    server_port: u16,
+    // reject chunks that break the protocol's size rules, like the client's option
+    strict_chunks: bool,
+    // write chunks to disk as they arrive, like the client's option
+    streaming: bool,
+    // write assembled files to disk like the client would
+    write: bool,
+}
+
+impl ReplayConfig {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ClientError> {
+        let mut capture_file = None;
+        let mut serve_addr = None;
+        let mut realtime = false;
+        let mut export_pcap = None;
+        let mut server_port = pcap::SERVER_PORT;
+        let mut strict_chunks = false;
+        let mut streaming = false;
+        let mut write = false;
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ClientError::InvalidArgument(format!("{arg} needs a value")))
+            };
+            match arg.as_str() {
+                "--serve" => serve_addr = Some(value()?),
+                "--export-pcap" => export_pcap = Some(value()?),
+                "--server-port" => {
+                    let port = value()?;
+                    server_port = port.parse().map_err(|_| {
+                        ClientError::InvalidArgument(format!("invalid value for {arg}: {port}"))
+                    })?;
+                }
+                "--realtime" => realtime = true,
+                "--strict-chunks" => strict_chunks = true,
+                "--stream" => streaming = true,
+                "--write" => write = true,
+                _ if !arg.starts_with("--") && capture_file.is_none() => capture_file = Some(arg),
+                _ => return Err(ClientError::InvalidArgument(format!("{arg}\n{USAGE}"))),
+            }
+        }
+
+        let capture_file =
+            capture_file.ok_or_else(|| ClientError::InvalidArgument(USAGE.to_string()))?;
+        Ok(ReplayConfig {
+            capture_file,
+            serve_addr,
+            realtime,
+            export_pcap,
+            server_port,
+            strict_chunks,
+            streaming,
+            write,
+        })
+    }
+}
+
+fn run(config: &ReplayConfig) -> Result<(), ClientError> {
+    let capture = replay::open_capture(&config.capture_file, config.server_port)?;
+
+    if let Some(addr) = &config.serve_addr {
+        let sock = UdpSocket::bind(addr).map_err(|source| ClientError::Socket {
+            addr: addr.clone(),
+            source,
+        })?;
+        println!("Waiting for a hello on {}", sock.local_addr()?);
+        let sent = replay::serve(&sock, capture, config.realtime)?;
+        println!("Sent {sent} datagrams");
+        return Ok(());
+    }
+
+    if let Some(path) = &config.export_pcap {
+        let mut writer = PcapWriter::create(
+            path,
+            pcap::default_server_addr(),
+            pcap::default_client_addr(),
+        )?;
+        let mut exported = 0;
+        for datagram in capture {
+            let datagram = datagram?;
+            writer.record_at(datagram.offset, &datagram.data)?;
+            exported += 1;
+        }
+        writer.flush()?;
+        println!("Exported {exported} datagrams to {path}");
+        return Ok(());
+    }
+
+    let mut file_manager = FileManager {
+        strict_chunks: config.strict_chunks,
+        streaming: config.streaming,
+        ..Default::default()
+    };
+    let report = replay::replay_offline(capture, &PacketParser::default(), &mut file_manager)?;
+    println!(
+        "Replayed {} datagrams ({} failed to parse, {} rejected), files {}",
+        report.datagrams,
+        report.parse_errors,
+        report.rejected_packets,
+        if report.complete {
+            "complete"
+        } else {
+            "incomplete"
+        }
+    );
+
+    if config.write {
+        file_manager.write_all_files()?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let result = ReplayConfig::from_args(std::env::args().skip(1)).and_then(|config| run(&config));
+
+    if let Err(e) = result {
+        eprintln!("{}", errors::report(&e));
+        std::process::exit(1);
+    }
+}
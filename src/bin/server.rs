@@ -0,0 +1,134 @@
+#![warn(clippy::style)]
+#![warn(clippy::perf)]
+#![warn(clippy::complexity)]
+#![warn(clippy::correctness)]
+#![warn(clippy::pedantic)]
+
+use std::{net::UdpSocket, path::PathBuf, str::FromStr, time::Duration};
+
+use segmented_file_system_client::{
+    config,
+    errors::{self, ClientError},
+    server::{self, ServedFile, ServerOptions},
+};
+
+const USAGE: &str = "usage: server [--bind ADDR] [--once] [--capabilities LIST] [--window N] \
+                     [--rto MS] [--idle-timeout SECS] [--parity K] [--loss FRACTION] [--seed N] FILE|DIR...";
+
+// What to serve, and how
+struct ServerConfig {
+    bind_addr: String,
+    // stop after one session instead of waiting for the next client
+    once: bool,
+    files: Vec<PathBuf>,
+    options: ServerOptions,
+}
+
+impl ServerConfig {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ClientError> {
+        let mut config = ServerConfig {
+            bind_addr: config::REMOTE_ADDR.to_string(),
+            once: false,
+            files: Vec::new(),
+            options: ServerOptions::default(),
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ClientError::InvalidArgument(format!("{arg} needs a value")))
+            };
+            let options = &mut config.options;
+            match arg.as_str() {
+                "--bind" => config.bind_addr = value()?,
+                "--once" => config.once = true,
+                "--capabilities" => options.capabilities = parse(&arg, &value()?)?,
+                "--window" => options.window = parse(&arg, &value()?)?,
+                "--rto" => {
+                    options.retransmit_timeout = Duration::from_millis(parse(&arg, &value()?)?);
+                }
+                "--idle-timeout" => {
+                    options.idle_timeout = Duration::from_secs(parse(&arg, &value()?)?);
+                }
+                "--parity" => options.parity_group = Some(parse(&arg, &value()?)?),
+                "--loss" => options.loss = parse(&arg, &value()?)?,
+                "--seed" => options.seed = parse(&arg, &value()?)?,
+                _ if !arg.starts_with("--") => config.files.push(PathBuf::from(arg)),
+                _ => return Err(ClientError::InvalidArgument(format!("{arg}\n{USAGE}"))),
+            }
+        }
+
+        if config.files.is_empty() {
+            return Err(ClientError::InvalidArgument(USAGE.to_string()));
+        }
+        if config.options.window == 0 || !(0.0..1.0).contains(&config.options.loss) {
+            return Err(ClientError::InvalidArgument(format!(
+                "--window must be at least 1 and --loss in [0, 1)\n{USAGE}"
+            )));
+        }
+        Ok(config)
+    }
+}
+
+fn parse<T: FromStr>(arg: &str, value: &str) -> Result<T, ClientError> {
+    value
+        .parse()
+        .map_err(|_| ClientError::InvalidArgument(format!("invalid value for {arg}: {value}")))
+}
+
+fn run(config: &ServerConfig) -> Result<(), ClientError> {
+    // a directory is served as the tree of files under it
+    let mut files = Vec::new();
+    for path in &config.files {
+        if path.is_dir() {
+            files.extend(ServedFile::open_tree(path)?);
+        } else {
+            files.push(ServedFile::open(path)?);
+        }
+    }
+    let sock = UdpSocket::bind(&config.bind_addr).map_err(|source| ClientError::Socket {
+        addr: config.bind_addr.clone(),
+        source,
+    })?;
+
+    loop {
+        println!("Waiting for a hello on {}", sock.local_addr()?);
+        match server::serve_session(&sock, &files, &config.options) {
+            Ok(report) => {
+                println!(
+                    "Served {} packets ({} parity) to {} with capabilities: {} \
+                     ({} sent, {} retransmitted, {} dropped on purpose)",
+                    report.packets,
+                    report.parity,
+                    report
+                        .client
+                        .map_or_else(|| "?".to_string(), |client| client.to_string()),
+                    report.capabilities,
+                    report.sent,
+                    report.retransmitted,
+                    report.dropped
+                );
+                if let Some(rate) = report.rate {
+                    println!(
+                        "Paced by {} feedback packets, ending at {rate} packets/s",
+                        report.feedback
+                    );
+                }
+            }
+            // one client giving up shouldn't stop the server
+            Err(e) => eprintln!("Session failed: {e}"),
+        }
+        if config.once {
+            return Ok(());
+        }
+    }
+}
+
+fn main() {
+    let result = ServerConfig::from_args(std::env::args().skip(1)).and_then(|config| run(&config));
+
+    if let Err(e) = result {
+        eprintln!("{}", errors::report(&e));
+        std::process::exit(1);
+    }
+}
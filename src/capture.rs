@@ -0,0 +1,248 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{packet::MAX_DATAGRAM_SIZE, pcap::PcapWriter};
+
+// Every capture file starts with these 8 bytes
+pub const CAPTURE_MAGIC: &[u8; 8] = b"SFSCAP01";
+
+// The client receives into buffers one byte bigger than the largest datagram,
+// so nothing longer was ever recorded; a longer length means a corrupt file
+const MAX_RECORD_LEN: usize = MAX_DATAGRAM_SIZE + 1;
+
+// A capture file is the magic followed by one record per datagram:
+//
+// | offset from start | length  | datagram      |
+// |:------------------|:--------|:--------------|
+// | 8 bytes (µs, BE)  | 4 bytes | `length` bytes |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedDatagram {
+    // time since the capture started
+    pub offset: Duration,
+    pub data: Vec<u8>,
+}
+
+// CaptureWriter records datagrams with timestamps relative to its creation
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+    started: Instant,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    /// Creates (or truncates) a capture file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be created.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        CaptureWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Starts a capture on `writer`, writing the file magic straight away.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the magic can't be written.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(CAPTURE_MAGIC)?;
+        Ok(CaptureWriter {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    /// Records a datagram received just now.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the record can't be written.
+    pub fn record(&mut self, datagram: &[u8]) -> io::Result<()> {
+        self.record_at(self.started.elapsed(), datagram)
+    }
+
+    /// Records a datagram at an explicit offset from the start of the capture.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the datagram is larger than
+    /// 4 GiB or the record can't be written.
+    pub fn record_at(&mut self, offset: Duration, datagram: &[u8]) -> io::Result<()> {
+        let micros = u64::try_from(offset.as_micros()).unwrap_or(u64::MAX);
+        let len = u32::try_from(datagram.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "datagram too large"))?;
+        self.writer.write_all(&micros.to_be_bytes())?;
+        self.writer.write_all(&len.to_be_bytes())?;
+        self.writer.write_all(datagram)
+    }
+
+    /// Flushes buffered records to the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the flush fails.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    // gives back the underlying writer, without flushing it
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// The file formats the client can record to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaptureFormat {
+    // our own format, see CapturedDatagram
+    #[default]
+    Native,
+    // classic pcap with synthetic Ethernet/IPv4/UDP headers, for Wireshark
+    Pcap,
+}
+
+impl FromStr for CaptureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(CaptureFormat::Native),
+            "pcap" => Ok(CaptureFormat::Pcap),
+            _ => Err(format!("unknown capture format: {s}")),
+        }
+    }
+}
+
+// Recorder is anything received datagrams can be recorded to
+pub trait Recorder {
+    /// Records a datagram received just now.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the datagram can't be recorded.
+    fn record(&mut self, datagram: &[u8]) -> io::Result<()>;
+
+    /// Flushes anything buffered.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the flush fails.
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+impl<W: Write> Recorder for CaptureWriter<W> {
+    fn record(&mut self, datagram: &[u8]) -> io::Result<()> {
+        CaptureWriter::record(self, datagram)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        CaptureWriter::flush(self)
+    }
+}
+
+impl<W: Write> Recorder for PcapWriter<W> {
+    fn record(&mut self, datagram: &[u8]) -> io::Result<()> {
+        PcapWriter::record(self, datagram)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        PcapWriter::flush(self)
+    }
+}
+
+/// Creates a capture file at `path` in the given format. `server` and
+/// `client` are only used by pcap, to fill in the synthetic headers.
+///
+/// # Errors
+///
+/// This function will return an error if the file can't be created.
+pub fn create_recorder(
+    path: impl AsRef<Path>,
+    format: CaptureFormat,
+    server: SocketAddr,
+    client: SocketAddr,
+) -> io::Result<Box<dyn Recorder>> {
+    Ok(match format {
+        CaptureFormat::Native => Box::new(CaptureWriter::create(path)?),
+        CaptureFormat::Pcap => Box::new(PcapWriter::create(path, server, client)?),
+    })
+}
+
+// CaptureReader iterates over the datagrams in a capture, in recorded order
+pub struct CaptureReader<R: Read> {
+    reader: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    /// Opens the capture file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be opened or
+    /// doesn't start with the capture magic.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Starts reading a capture from `reader`, checking the file magic. A
+    /// record longer than any datagram the client can receive is an
+    /// `InvalidData` error.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an `InvalidData` error if the magic is wrong.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; CAPTURE_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a segmented file system capture",
+            ));
+        }
+        Ok(CaptureReader { reader })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<CapturedDatagram>> {
+        let mut micros = [0; 8];
+        // a clean end of file can only happen between records; anything
+        // shorter than a whole record is an UnexpectedEof error
+        if self.reader.read(&mut micros[..1])? == 0 {
+            return Ok(None);
+        }
+        self.reader.read_exact(&mut micros[1..])?;
+        let mut len = [0; 4];
+        self.reader.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("capture record of {len} bytes is longer than any datagram"),
+            ));
+        }
+        let mut data = vec![0; len];
+        self.reader.read_exact(&mut data)?;
+
+        Ok(Some(CapturedDatagram {
+            offset: Duration::from_micros(u64::from_be_bytes(micros)),
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CapturedDatagram>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
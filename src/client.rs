@@ -0,0 +1,599 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    batch_receiver::BatchReceiver,
+    capture::{self, Recorder},
+    config::ClientConfig,
+    errors::{self, ClientError},
+    file_manager::FileManager,
+    flow_control::RateController,
+    hello::{Arrival, Capabilities, Handshake, HelloRetry},
+    hex,
+    packet::{Feedback, FileId},
+    quarantine::Quarantine,
+    socket,
+};
+
+/// Says hello to the server, then receives packets until every file is
+/// complete and writes them all out. If the server's name resolves to more
+/// than one address, the next one is tried whenever a hello goes unanswered.
+///
+/// # Errors
+///
+/// This function will return an error if the name can't be resolved, the
+/// socket can't be set up, no address answers, a receive fails, or a file
+/// can't be written.
+pub fn run_client(config: &ClientConfig) -> Result<(), ClientError> {
+    let addrs = socket::server_addresses(
+        &config.remote_addr,
+        config.local_addr.as_deref(),
+        config.local_port,
+    )
+    .map_err(|source| ClientError::Socket {
+        addr: config.remote_addr.clone(),
+        source,
+    })?;
+    run_client_with_addrs(config, &addrs)
+}
+
+/// Like [`run_client`], but tries the given `(local, remote)` address pairs
+/// in turn instead of resolving `config.remote_addr`. While later pairs are
+/// left, a refused hello moves on straight away rather than waiting out
+/// `config.connect_timeout`.
+///
+/// # Errors
+///
+/// This function will return an error if no pair can be connected or
+/// answers, a receive fails, or a file can't be written.
+pub fn run_client_with_addrs(
+    config: &ClientConfig,
+    addrs: &[(SocketAddr, SocketAddr)],
+) -> Result<(), ClientError> {
+    let mut last_error = None;
+    for (i, &(local, remote)) in addrs.iter().enumerate() {
+        let fall_back = i + 1 < addrs.len();
+        let result =
+            connect(config, local, remote).and_then(|sock| run_session(config, &sock, fall_back));
+        match result {
+            Err(err @ (ClientError::Socket { .. } | ClientError::ServerNotResponding { .. }))
+                if fall_back =>
+            {
+                eprintln!("{}; trying the next address", errors::report(&err));
+                last_error = Some(err);
+            }
+            result => return result,
+        }
+    }
+    Err(last_error.unwrap_or_else(|| ClientError::Socket {
+        addr: config.remote_addr.clone(),
+        source: io::ErrorKind::AddrNotAvailable.into(),
+    }))
+}
+
+// One attempt at the whole download from the server `sock` is connected to
+fn run_session(
+    config: &ClientConfig,
+    sock: &UdpSocket,
+    fall_back: bool,
+) -> Result<(), ClientError> {
+    let peer = sock.peer_addr().ok();
+    println!("Connected to {}", sock.peer_addr()?);
+    println!("Waiting for packets...");
+
+    let quarantine = match &config.quarantine_dir {
+        Some(dir) => Some(Quarantine::open(dir)?),
+        None => None,
+    };
+    // opened before the hello so capture timestamps count from when we asked
+    let mut capture = match (&config.capture_file, peer) {
+        (Some(path), Some(server)) => Some(capture::create_recorder(
+            path,
+            config.capture_format,
+            server,
+            sock.local_addr()?,
+        )?),
+        _ => None,
+    };
+
+    // a legacy server doesn't reply and just starts sending, in which case
+    // the parser stays on the legacy format (see resend_hello)
+    let hello = config.hello().to_bytes();
+    let mut retry = Some(HelloRetry::new(
+        config.hello_interval,
+        config.connect_timeout,
+        Instant::now(),
+    ));
+    if let (Err(e), Some(retry)) = (sock.send(&hello), &mut retry) {
+        retry.record_error(e);
+    }
+    sock.set_read_timeout(retry.as_ref().map(HelloRetry::interval))?;
+
+    let mut receiver = BatchReceiver::new(config.batch_size);
+    let mut session = Session::new(config, peer, quarantine);
+
+    // keep looping until all packets have been received
+    while !session.file_manager.received_all_packets() {
+        // with more addresses to try, a refused hello moves on to the next
+        // one, unless this server has been sending all along
+        let fall_back = fall_back && !session.handshake.is_holding();
+        receive(sock, &mut receiver, retry.as_mut(), peer, config, fall_back)?;
+
+        for datagram in receiver.datagrams() {
+            if let Some(capture) = &mut capture {
+                capture.record(datagram)?;
+            }
+            session.handle_datagram(datagram)?;
+        }
+
+        if let (Some(pending), false) = (&mut retry, session.handshake.is_settled()) {
+            resend_hello(sock, pending, &hello, &mut session)?;
+        }
+        // the server has answered, so stop resending the hello
+        if session.handshake.is_settled() && retry.take().is_some() {
+            sock.set_read_timeout(None)?;
+        }
+        if retry.is_none() && (session.acks.enabled || session.flow.is_some()) {
+            let now = Instant::now();
+            session.check_idle(now)?;
+            session.acks.send_due(sock, &session.file_manager, now);
+            session.send_feedback(sock, now);
+            // wake up for acks and feedback that aren't due yet, or the
+            // server would retransmit the end of a burst
+            sock.set_read_timeout(session.wait(now))?;
+        }
+        print_progress(session.packets_received)?;
+    }
+    linger(sock, &mut receiver, &mut session, &mut capture)?;
+    if let Some(flow) = &session.flow {
+        println!("\nReceive rate at the end: {} packets/s", flow.rate());
+    }
+
+    if let Some(capture) = &mut capture {
+        capture.flush()?;
+    }
+
+    if let Some(quarantine) = &session.quarantine {
+        if quarantine.saved() > 0 {
+            println!(
+                "\nQuarantined {} unparseable datagrams in {}",
+                quarantine.saved(),
+                quarantine.dir().display()
+            );
+        }
+    }
+
+    write_files(&session.file_manager)
+}
+
+// How long the server must stay quiet after the final acks before the client
+// stops answering its retransmissions
+const LINGER: Duration = Duration::from_millis(500);
+
+// Session is everything the receive loop keeps track of between datagrams
+struct Session<'a> {
+    config: &'a ClientConfig,
+    peer: Option<SocketAddr>,
+    // the format the server's datagrams are in, and any that arrived before
+    // it was settled
+    handshake: Handshake,
+    // when the last datagram arrived
+    last_heard: Instant,
+    packets_received: usize, // Counter for received packets
+    file_manager: FileManager,
+    quarantine: Option<Quarantine>,
+    acks: AckScheduler,
+    // set when the server agrees to flow control
+    flow: Option<RateController>,
+}
+
+impl<'a> Session<'a> {
+    fn new(
+        config: &'a ClientConfig,
+        peer: Option<SocketAddr>,
+        quarantine: Option<Quarantine>,
+    ) -> Self {
+        Session {
+            config,
+            peer,
+            handshake: Handshake::new(config.parser),
+            last_heard: Instant::now(),
+            packets_received: 0,
+            file_manager: FileManager {
+                strict_chunks: config.strict_chunks,
+                streaming: config.streaming,
+                expected_files: config.expected_files,
+                ..Default::default()
+            },
+            quarantine,
+            acks: AckScheduler::new(config.ack_interval),
+            flow: None,
+        }
+    }
+
+    fn handle_datagram(&mut self, datagram: &[u8]) -> Result<(), ClientError> {
+        self.last_heard = Instant::now();
+        match self.handshake.arrive(datagram) {
+            Arrival::Reply(reply) => {
+                let capabilities = reply.accept(self.config.capabilities)?;
+                self.acks.enabled = capabilities.contains(Capabilities::RETRANSMIT);
+                self.file_manager.wait_for_manifest = self.acks.enabled;
+                println!(
+                    "Server speaks protocol version {} with capabilities: {capabilities}",
+                    reply.version
+                );
+                if capabilities.contains(Capabilities::FLOW_CONTROL) {
+                    self.flow = Some(RateController::new(
+                        self.config.rate,
+                        self.config.feedback_interval,
+                        Instant::now(),
+                    ));
+                }
+                for datagram in self.handshake.negotiate(capabilities) {
+                    self.handle_packet(&datagram);
+                }
+            }
+            Arrival::Full => self.settle_legacy(),
+            Arrival::Ready => self.handle_packet(datagram),
+            Arrival::Held | Arrival::LateReply => {}
+        }
+        Ok(())
+    }
+
+    // The server has been sending without replying, for as long as the hello
+    // gave it or for MAX_HELD datagrams, so it's a legacy one
+    fn settle_legacy(&mut self) {
+        println!("\nNo hello reply, so the server speaks the legacy protocol");
+        for datagram in self.handshake.settle_legacy() {
+            self.handle_packet(&datagram);
+        }
+    }
+
+    fn handle_packet(&mut self, datagram: &[u8]) {
+        let packet = match self.handshake.parser().parse(datagram) {
+            Ok(packet) => packet,
+            Err(source) => {
+                if let Some(quarantine) = &mut self.quarantine {
+                    // losing a diagnostic copy isn't worth aborting the download
+                    if let Err(e) = quarantine.save(datagram, &source) {
+                        eprintln!("\nCould not quarantine datagram: {e}");
+                    }
+                }
+                let err = ClientError::Parse {
+                    peer: self.peer,
+                    len: datagram.len(),
+                    source,
+                };
+                eprint!("\n{}\n{}", errors::report(&err), hex::hex_dump(datagram));
+                return;
+            }
+        };
+
+        self.packets_received += 1; // Increment the counter
+        if let Some(file_id) = packet.file_id() {
+            self.acks.note(file_id);
+        }
+        if let Err(err) = self.file_manager.process_packet_ref(packet) {
+            eprint!("\nRejected packet: {}\n", errors::report(&err));
+        }
+    }
+
+    // with flow control, adjusts the rate once it's due and tells the server
+    fn send_feedback(&mut self, sock: &UdpSocket, now: Instant) {
+        let skipped = self.file_manager.skipped();
+        if let Some(rate) = self
+            .flow
+            .as_mut()
+            .and_then(|flow| flow.update(skipped, now))
+        {
+            // like a lost ack, lost feedback is made up for by the next one
+            let _ = sock.send(&Feedback { rate }.to_bytes());
+        }
+    }
+
+    // how long until acks or feedback are due, or (in reliable mode) the
+    // server counts as gone; None if none of them ever is
+    fn wait(&self, now: Instant) -> Option<Duration> {
+        let feedback = self.flow.as_ref().map(|flow| {
+            flow.due()
+                .saturating_duration_since(now)
+                .max(Duration::from_millis(1))
+        });
+        let idle = self.acks.enabled.then(|| {
+            (self.last_heard + self.config.idle_timeout)
+                .saturating_duration_since(now)
+                .max(Duration::from_millis(1))
+        });
+        self.acks
+            .wait(now)
+            .into_iter()
+            .chain(feedback)
+            .chain(idle)
+            .min()
+    }
+
+    // In reliable mode the server keeps sending until everything is
+    // acknowledged, so a silence this long means it has gone away
+    fn check_idle(&self, now: Instant) -> Result<(), ClientError> {
+        let idle = now.saturating_duration_since(self.last_heard);
+        if self.acks.enabled && idle >= self.config.idle_timeout {
+            return Err(ClientError::Receive {
+                peer: self.peer,
+                source: io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("nothing arrived for {:.1}s", idle.as_secs_f64()),
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+// AckScheduler sends acks in reliable mode: for every file that got a packet
+// since the last round, at most once per `interval`, and for every file once
+// the download is complete
+struct AckScheduler {
+    // set when the server agrees to retransmit
+    enabled: bool,
+    interval: Duration,
+    last_sent: Option<Instant>,
+    pending: BTreeSet<FileId>,
+}
+
+impl AckScheduler {
+    fn new(interval: Duration) -> Self {
+        AckScheduler {
+            enabled: false,
+            interval,
+            last_sent: None,
+            pending: BTreeSet::new(),
+        }
+    }
+
+    fn note(&mut self, file_id: FileId) {
+        if self.enabled {
+            self.pending.insert(file_id);
+        }
+    }
+
+    // how long until the pending acks are due; None if there aren't any
+    fn wait(&self, now: Instant) -> Option<Duration> {
+        let last_sent = self.last_sent?;
+        (!self.pending.is_empty()).then(|| {
+            (last_sent + self.interval)
+                .saturating_duration_since(now)
+                .max(Duration::from_millis(1))
+        })
+    }
+
+    fn send_due(&mut self, sock: &UdpSocket, file_manager: &FileManager, now: Instant) {
+        if self.pending.is_empty()
+            || self
+                .last_sent
+                .is_some_and(|last_sent| now.duration_since(last_sent) < self.interval)
+        {
+            return;
+        }
+        for file_id in std::mem::take(&mut self.pending) {
+            send_ack(sock, file_manager, file_id);
+        }
+        self.last_sent = Some(now);
+    }
+
+    fn send_all(&mut self, sock: &UdpSocket, file_manager: &FileManager) {
+        if self.enabled {
+            // the files with news first: with thousands of files the burst
+            // can overflow the server's receive buffer, and then only
+            // repeats are lost
+            let pending = std::mem::take(&mut self.pending);
+            let rest = file_manager
+                .files
+                .keys()
+                .filter(|file_id| !pending.contains(file_id));
+            for &file_id in pending.iter().chain(rest) {
+                send_ack(sock, file_manager, file_id);
+            }
+        }
+    }
+}
+
+// Sends the final acks, then answers every retransmission with them again
+// until the server has been quiet for LINGER. The server retransmits until
+// it hears everything arrived, so a lost final ack would otherwise leave it
+// going until its idle timeout.
+fn linger(
+    sock: &UdpSocket,
+    receiver: &mut BatchReceiver,
+    session: &mut Session,
+    capture: &mut Option<Box<dyn Recorder>>,
+) -> Result<(), ClientError> {
+    if !session.acks.enabled {
+        return Ok(());
+    }
+    session.acks.send_all(sock, &session.file_manager);
+    let mut last_sent = Instant::now();
+    sock.set_read_timeout(Some(LINGER))?;
+    loop {
+        match receiver.recv(sock) {
+            Ok(_) => {
+                for datagram in receiver.datagrams() {
+                    if let Some(capture) = capture {
+                        capture.record(datagram)?;
+                    }
+                }
+                // a window's worth of retransmissions needs only one answer
+                if last_sent.elapsed() >= session.acks.interval {
+                    session.acks.send_all(sock, &session.file_manager);
+                    last_sent = Instant::now();
+                }
+            }
+            // quiet for long enough, or the server has gone away
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                return Ok(());
+            }
+            Err(source) => {
+                return Err(ClientError::Receive {
+                    peer: session.peer,
+                    source,
+                })
+            }
+        }
+    }
+}
+
+// a lost ack only costs a retransmission, so send errors are ignored
+fn send_ack(sock: &UdpSocket, file_manager: &FileManager, file_id: FileId) {
+    if let Some(ack) = file_manager.ack(file_id) {
+        let _ = sock.send(&ack.to_bytes());
+    }
+}
+
+// Sets up the socket and says what the kernel gave us
+fn connect(
+    config: &ClientConfig,
+    local: SocketAddr,
+    remote: SocketAddr,
+) -> Result<UdpSocket, ClientError> {
+    let sock = socket::connect_pair(local, remote, &config.socket).map_err(|source| {
+        ClientError::Socket {
+            addr: remote.to_string(),
+            source,
+        }
+    })?;
+    println!("Listening on {}", sock.local_addr()?);
+
+    let granted = socket::effective_recv_buffer_size(&sock)?;
+    match config.socket.recv_buffer_size {
+        Some(requested) => {
+            println!("Receive buffer: requested {requested} bytes, kernel granted {granted} bytes");
+        }
+        None => println!("Receive buffer: {granted} bytes"),
+    }
+    Ok(sock)
+}
+
+// Receives the next batch. A timeout means acks or (while `retry` is set)
+// the hello are due, and the batch is empty. Until the server has answered,
+// a refused connection waits until the hello is due too, unless there's
+// another address to `fall_back` to.
+fn receive(
+    sock: &UdpSocket,
+    receiver: &mut BatchReceiver,
+    retry: Option<&mut HelloRetry>,
+    peer: Option<SocketAddr>,
+    config: &ClientConfig,
+    fall_back: bool,
+) -> Result<(), ClientError> {
+    let Err(source) = receiver.recv(sock) else {
+        return Ok(());
+    };
+    match retry {
+        Some(pending) if HelloRetry::is_retryable(&source) => {
+            let refused = source.kind() == io::ErrorKind::ConnectionRefused;
+            pending.record_error(source);
+            if refused && fall_back {
+                return Err(pending.give_up(addr_name(peer, config), Instant::now()));
+            }
+            thread::sleep(pending.due().saturating_duration_since(Instant::now()));
+            Ok(())
+        }
+        None if matches!(
+            source.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ) =>
+        {
+            Ok(())
+        }
+        _ => Err(ClientError::Receive { peer, source }),
+    }
+}
+
+// Until the server has answered: once the latest hello is due, resends it,
+// or gives up on a silent server at the deadline. A server that is sending
+// but hasn't replied by then is a legacy one, so its datagrams are let
+// through instead. It never gets another hello, which a legacy server would
+// take as a new request and answer by sending everything again.
+fn resend_hello(
+    sock: &UdpSocket,
+    pending: &mut HelloRetry,
+    hello: &[u8],
+    session: &mut Session,
+) -> Result<(), ClientError> {
+    let now = Instant::now();
+    if now >= pending.due() {
+        if session.handshake.is_holding() {
+            session.settle_legacy();
+            return Ok(());
+        }
+        if pending.next_interval(now).is_none() {
+            return Err(pending.give_up(addr_name(session.peer, session.config), now));
+        }
+        if let Err(e) = sock.send(hello) {
+            pending.record_error(e);
+        }
+    }
+    // wake up when the hello is due even if datagrams keep arriving
+    let wait = pending.due().saturating_duration_since(Instant::now());
+    sock.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+    Ok(())
+}
+
+// the address actually tried, which says more than the name when it resolves
+// to several
+fn addr_name(peer: Option<SocketAddr>, config: &ClientConfig) -> String {
+    peer.map_or_else(|| config.remote_addr.clone(), |peer| peer.to_string())
+}
+
+fn print_progress(packets_received: usize) -> io::Result<()> {
+    // Dynamically calculate the width of the counter based on the number of digits
+    let width = packets_received.to_string().len();
+    #[allow(clippy::uninlined_format_args)] // Can't find solution that compiles
+    {
+        print!(
+            "\rPackets received: [{:>width$}]",
+            packets_received, // Use the counter here
+            width = width,
+        ); // Dynamic counter
+    }
+    io::stdout().flush()
+}
+
+// Warns about anything odd in the reassembled files, then writes them out
+fn write_files(file_manager: &FileManager) -> Result<(), ClientError> {
+    for (file_id, report) in file_manager.reassembly_reports() {
+        if !file_manager.is_expected(file_id) {
+            eprintln!("\nWarning: file {file_id} isn't in the manifest, skipping it");
+        } else if !report.is_valid() {
+            eprintln!("\nWarning: file {file_id}: {report}");
+        }
+    }
+
+    if file_manager.recovered() > 0 {
+        println!(
+            "\nRecovered {} lost chunks from parity packets",
+            file_manager.recovered()
+        );
+    }
+
+    if file_manager.received_all_packets() {
+        println!("\nAll packets received. Writing files...");
+        file_manager.write_all_files()?;
+        println!("Files written successfully.");
+    } else {
+        eprintln!("Error: Failed to receive all packets.");
+    }
+
+    Ok(())
+}
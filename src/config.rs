@@ -0,0 +1,224 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    capture::CaptureFormat,
+    errors::ClientError,
+    flow_control::{FEEDBACK_INTERVAL, INITIAL_RATE, MIN_RATE},
+    hello::{Capabilities, Hello},
+    packet::PacketParser,
+    socket::SocketOptions,
+};
+
+pub const LOCAL_PORT: u16 = 7077;
+pub const REMOTE_ADDR: &str = "127.0.0.1:6014";
+pub const HELLO_INTERVAL: Duration = Duration::from_millis(250);
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const ACK_INTERVAL: Duration = Duration::from_millis(20);
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ClientConfig holds everything that can be changed from the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    // when unset we bind the unspecified address of the remote's family
+    pub local_addr: Option<String>,
+    pub local_port: u16,
+    pub remote_addr: String,
+    // how many datagrams to pull per receive call (recvmmsg on Linux)
+    pub batch_size: usize,
+    pub socket: SocketOptions,
+    pub parser: PacketParser,
+    // what to offer in the hello; the parser uses whatever the server selects
+    pub capabilities: Capabilities,
+    // how long to wait for an answer to the first hello before resending it;
+    // the wait doubles with every hello after that
+    pub hello_interval: Duration,
+    // give up if the server hasn't answered this long after the first hello
+    pub connect_timeout: Duration,
+    // in reliable mode, the most often acks for a file are sent
+    pub ack_interval: Duration,
+    // in reliable mode, give up if nothing arrives from the server for this
+    // long
+    pub idle_timeout: Duration,
+    // with flow control, the packets per second to ask for at first
+    pub rate: u32,
+    // with flow control, how often the rate is adjusted and sent to the server
+    pub feedback_interval: Duration,
+    // where to save datagrams that fail to parse, if anywhere
+    pub quarantine_dir: Option<PathBuf>,
+    // record every received datagram here, for replaying later
+    pub capture_file: Option<PathBuf>,
+    pub capture_format: CaptureFormat,
+    // reject chunks that break the protocol's size rules; see PacketGroup
+    pub strict_chunks: bool,
+    // write chunks to disk as they arrive; see PacketGroup::streaming
+    pub streaming: bool,
+    // don't finish until this many files are complete; a manifest packet
+    // from the server overrides it
+    pub expected_files: Option<usize>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            local_addr: None,
+            local_port: LOCAL_PORT,
+            remote_addr: REMOTE_ADDR.to_string(),
+            batch_size: 1,
+            socket: SocketOptions::default(),
+            parser: PacketParser::default(),
+            capabilities: Capabilities::SUPPORTED,
+            hello_interval: HELLO_INTERVAL,
+            connect_timeout: CONNECT_TIMEOUT,
+            ack_interval: ACK_INTERVAL,
+            idle_timeout: IDLE_TIMEOUT,
+            rate: INITIAL_RATE,
+            feedback_interval: FEEDBACK_INTERVAL,
+            quarantine_dir: None,
+            capture_file: None,
+            capture_format: CaptureFormat::default(),
+            strict_chunks: false,
+            streaming: false,
+            expected_files: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Builds a configuration from command line arguments (not including the
+    /// program name). Anything not given keeps its default value.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `ClientError::InvalidArgument` if an option
+    /// is unknown, is missing its value, or has a value that can't be parsed.
+    pub fn from_args<I>(args: I) -> Result<Self, ClientError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = ClientConfig::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--local" => config.local_addr = Some(required_value(&arg, args.next())?),
+                "--local-port" => config.local_port = parse_value(&arg, args.next())?,
+                "--remote" => config.remote_addr = required_value(&arg, args.next())?,
+                "--batch-size" => {
+                    config.batch_size = parse_value(&arg, args.next())?;
+                    if config.batch_size == 0 {
+                        return Err(ClientError::InvalidArgument(
+                            "--batch-size must be at least 1".to_string(),
+                        ));
+                    }
+                }
+                "--expect-files" => {
+                    let expected_files = parse_value(&arg, args.next())?;
+                    if expected_files == 0 {
+                        return Err(ClientError::InvalidArgument(
+                            "--expect-files must be at least 1".to_string(),
+                        ));
+                    }
+                    config.expected_files = Some(expected_files);
+                }
+                "--recv-buffer" => {
+                    config.socket.recv_buffer_size = Some(parse_value(&arg, args.next())?);
+                }
+                "--reuse-address" => config.socket.reuse_address = true,
+                "--reuse-port" => config.socket.reuse_port = true,
+                "--dual-stack" => config.socket.dual_stack = Some(true),
+                "--ipv6-only" => config.socket.dual_stack = Some(false),
+                "--non-utf8-names" => {
+                    config.parser.file_name_fallback = parse_value(&arg, args.next())?;
+                }
+                "--capabilities" => {
+                    config.capabilities = parse_value(&arg, args.next())?;
+                    if !Capabilities::SUPPORTED.contains(config.capabilities) {
+                        return Err(ClientError::InvalidArgument(format!(
+                            "--capabilities: only {} supported",
+                            Capabilities::SUPPORTED
+                        )));
+                    }
+                }
+                "--hello-interval" => config.hello_interval = parse_seconds(&arg, args.next())?,
+                "--connect-timeout" => {
+                    config.connect_timeout = parse_seconds(&arg, args.next())?;
+                }
+                "--ack-interval" => config.ack_interval = parse_seconds(&arg, args.next())?,
+                "--idle-timeout" => config.idle_timeout = parse_seconds(&arg, args.next())?,
+                "--rate" => {
+                    config.rate = parse_value(&arg, args.next())?;
+                    if config.rate < MIN_RATE {
+                        return Err(ClientError::InvalidArgument(format!(
+                            "--rate must be at least {MIN_RATE}"
+                        )));
+                    }
+                }
+                "--feedback-interval" => {
+                    config.feedback_interval = parse_seconds(&arg, args.next())?;
+                }
+                "--quarantine" => {
+                    config.quarantine_dir = Some(required_value(&arg, args.next())?.into());
+                }
+                "--capture" => {
+                    config.capture_file = Some(required_value(&arg, args.next())?.into());
+                }
+                "--capture-format" => config.capture_format = parse_value(&arg, args.next())?,
+                "--strict-chunks" => config.strict_chunks = true,
+                // streaming relies on the chunk rules for its offsets
+                "--stream" => {
+                    config.streaming = true;
+                    config.strict_chunks = true;
+                }
+                _ => {
+                    return Err(ClientError::InvalidArgument(format!(
+                        "unknown option: {arg}"
+                    )))
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    // The hello to start a session with: the capabilities to offer, and with
+    // flow control the rate to start at
+    #[must_use]
+    pub fn hello(&self) -> Hello {
+        Hello {
+            rate: self
+                .capabilities
+                .contains(Capabilities::FLOW_CONTROL)
+                .then_some(self.rate),
+            ..Hello::new(self.capabilities)
+        }
+    }
+}
+
+fn required_value(option: &str, value: Option<String>) -> Result<String, ClientError> {
+    value.ok_or_else(|| ClientError::InvalidArgument(format!("{option} requires a value")))
+}
+
+// a positive number of seconds, fractions allowed
+fn parse_seconds(option: &str, value: Option<String>) -> Result<Duration, ClientError> {
+    let value = required_value(option, value)?;
+    value
+        .parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| {
+            ClientError::InvalidArgument(format!(
+                "invalid value for {option}: {value} (expected a positive number of seconds)"
+            ))
+        })
+}
+
+fn parse_value<T: std::str::FromStr>(
+    option: &str,
+    value: Option<String>,
+) -> Result<T, ClientError> {
+    let value = required_value(option, value)?;
+    value
+        .parse()
+        .map_err(|_| ClientError::InvalidArgument(format!("invalid value for {option}: {value}")))
+}
@@ -1,19 +1,188 @@
-// Custom error types for parsing
+// Custom error types for parsing, assembling and writing files
 
-#[derive(Debug)]
+use std::{error::Error, ffi::OsString, fmt, io, net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::hello::Capabilities;
+use crate::packet::{FileId, MAX_PAYLOAD_SIZE};
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum PacketParseError {
+    // not even a status byte and file ID
     TooShort,
-    InvalidPacketFormat,
+    // one of the status bits we don't understand is set; holds the status byte
+    ReservedBitsSet(u8),
+    // a header packet with nothing after the file ID
+    EmptyFileName,
+    // the file name can't be decoded; `offset` is from the start of the datagram
+    InvalidEncoding { offset: usize },
+    // a data packet without its full 4 bytes of bookkeeping
+    DataTooShort { len: usize },
+    // longer than the `max` bytes its kind allows under the negotiated
+    // capabilities (the receive buffer may cut `len` short)
+    Oversized { len: usize, max: usize },
+    // with the checksums capability: the checksum at the end of the datagram
+    // (`expected`) doesn't match the one computed over the rest (`actual`)
+    BadChecksum { expected: u16, actual: u16 },
+    // a part of a wide manifest that ends partway through a 4 byte file ID
+    PartialFileId { len: usize },
+}
+
+impl fmt::Display for PacketParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketParseError::TooShort => write!(f, "packet is too short"),
+            PacketParseError::ReservedBitsSet(status_byte) => {
+                write!(f, "reserved bits set in status byte {status_byte:#04x}")
+            }
+            PacketParseError::EmptyFileName => write!(f, "header packet has an empty file name"),
+            PacketParseError::InvalidEncoding { offset } => {
+                write!(
+                    f,
+                    "file name has an invalid encoding at byte offset {offset}"
+                )
+            }
+            PacketParseError::DataTooShort { len } => {
+                write!(
+                    f,
+                    "data packet is {len} bytes, shorter than the 4 byte minimum"
+                )
+            }
+            PacketParseError::Oversized { len, max } => write!(
+                f,
+                "datagram is {len} bytes, longer than the {max} byte maximum"
+            ),
+            PacketParseError::BadChecksum { expected, actual } => write!(
+                f,
+                "checksum mismatch: datagram says {expected:#06x}, contents give {actual:#06x}"
+            ),
+            PacketParseError::PartialFileId { len } => write!(
+                f,
+                "manifest is {len} bytes, which ends partway through a 4 byte file ID"
+            ),
+        }
+    }
 }
 
+impl Error for PacketParseError {}
+
+// ClientError is the top level error; the context variants say where a lower
+// level error happened and keep it available through `source()`
 #[derive(Debug)]
 pub enum ClientError {
-    IoError(std::io::Error),
+    IoError(io::Error),
     PacketParseError(PacketParseError),
+    InvalidArgument(String),
+    // setting up the socket to `addr` failed
+    Socket {
+        addr: String,
+        source: io::Error,
+    },
+    // a receive from `peer` failed
+    Receive {
+        peer: Option<SocketAddr>,
+        source: io::Error,
+    },
+    // a datagram from `peer` couldn't be parsed
+    Parse {
+        peer: Option<SocketAddr>,
+        len: usize,
+        source: PacketParseError,
+    },
+    // nothing valid came back from `addr` before the connect deadline; `source`
+    // is the last error seen while trying, if any
+    ServerNotResponding {
+        addr: String,
+        hellos: u32,
+        waited: Duration,
+        source: Option<io::Error>,
+    },
+    // the server's hello reply selected capabilities the client didn't offer
+    Negotiation {
+        offered: Capabilities,
+        selected: Capabilities,
+    },
+    // assembling or writing the file with this ID failed
+    File {
+        file_id: FileId,
+        file_name: Option<OsString>,
+        source: PacketGroupError,
+    },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::IoError(_) => write!(f, "IO error"),
+            ClientError::PacketParseError(_) => write!(f, "Packet parse error"),
+            ClientError::InvalidArgument(msg) => write!(f, "Invalid argument: {msg}"),
+            ClientError::Socket { addr, .. } => write!(f, "Could not set up socket for {addr}"),
+            ClientError::Receive { peer, .. } => match peer {
+                Some(peer) => write!(f, "Receive from {peer} failed"),
+                None => write!(f, "Receive failed"),
+            },
+            ClientError::Parse { peer, len, .. } => {
+                write!(f, "Could not parse {len} byte datagram")?;
+                match peer {
+                    Some(peer) => write!(f, " from {peer}"),
+                    None => Ok(()),
+                }
+            }
+            ClientError::ServerNotResponding {
+                addr,
+                hellos,
+                waited,
+                source,
+            } => {
+                write!(
+                    f,
+                    "Server {addr} not responding: sent {hellos} hello{} over {:.1}s",
+                    if *hellos == 1 { "" } else { "s" },
+                    waited.as_secs_f64()
+                )?;
+                match source {
+                    Some(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        write!(f, " (nothing is listening there)")
+                    }
+                    _ => Ok(()),
+                }
+            }
+            ClientError::Negotiation { offered, selected } => write!(
+                f,
+                "Server selected capabilities ({selected}) beyond the ones offered ({offered})"
+            ),
+            ClientError::File {
+                file_id, file_name, ..
+            } => {
+                write!(f, "File {file_id}")?;
+                match file_name {
+                    Some(name) => write!(f, " ({})", name.to_string_lossy()),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
 }
 
-impl From<std::io::Error> for ClientError {
-    fn from(e: std::io::Error) -> Self {
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::IoError(err)
+            | ClientError::Socket { source: err, .. }
+            | ClientError::Receive { source: err, .. } => Some(err),
+            ClientError::PacketParseError(err) | ClientError::Parse { source: err, .. } => {
+                Some(err)
+            }
+            ClientError::File { source, .. } => Some(source),
+            ClientError::ServerNotResponding { source, .. } => {
+                source.as_ref().map(|e| e as &(dyn Error + 'static))
+            }
+            ClientError::InvalidArgument(_) | ClientError::Negotiation { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
         ClientError::IoError(e)
     }
 }
@@ -26,45 +195,181 @@ impl From<PacketParseError> for ClientError {
 
 #[derive(Debug)]
 pub enum PacketGroupError {
-    MissingPacket(u16),
-    IoError(std::io::Error),
+    MissingPacket(u32),
+    IoError(io::Error),
     MissingFileName,
     MissingPacketCount,
+    // the last packet number doesn't fit the 32 bit packet numbers
+    TooManyPackets(usize),
+    // creating or writing the output file failed `offset` bytes in
+    Write {
+        path: PathBuf,
+        offset: u64,
+        source: io::Error,
+    },
+    // strict chunk checks: a chunk before the last isn't a full 1024 bytes
+    ShortChunk {
+        packet_number: u32,
+        len: usize,
+    },
+    // strict chunk checks: a chunk longer than 1024 bytes
+    OversizedChunk {
+        packet_number: u32,
+        len: usize,
+    },
+    // strict chunk checks: a packet numbered after the last packet
+    PastLastPacket {
+        packet_number: u32,
+        last: u32,
+    },
+    // strict chunk checks: marked last, but `conflicts_with` already is (or
+    // is a higher packet number that has already arrived)
+    ConflictingLastPacket {
+        packet_number: u32,
+        conflicts_with: u32,
+    },
+    // strict chunk checks: the written file isn't the size the chunks add up to
+    SizeMismatch {
+        path: PathBuf,
+        expected: u64,
+        written: u64,
+    },
+    // the chunks don't add up to the size the header's metadata gave
+    WrongSize {
+        expected: u64,
+        received: u64,
+    },
+    // the file was written, but its modification time or permissions
+    // couldn't be set from the header's metadata
+    Metadata {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // the file name would put the file outside the output directory
+    UnsafePath(OsString),
 }
 
-impl std::fmt::Display for PacketGroupError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for PacketGroupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             PacketGroupError::MissingPacket(packet_number) => {
                 write!(f, "Missing packet: {packet_number}")
             }
             PacketGroupError::MissingFileName => write!(f, "Missing file name"),
-            PacketGroupError::IoError(err) => write!(f, "IO error: {err}"),
+            PacketGroupError::IoError(_) => write!(f, "IO error"),
             PacketGroupError::MissingPacketCount => write!(f, "Missing packet count"),
+            PacketGroupError::TooManyPackets(count) => {
+                write!(f, "Too many packets: {count}")
+            }
+            PacketGroupError::Write { path, offset, .. } => {
+                write!(f, "Could not write {} at byte {offset}", path.display())
+            }
+            PacketGroupError::ShortChunk { packet_number, len } => write!(
+                f,
+                "Packet {packet_number} has {len} bytes, but only the last packet may be shorter than {MAX_PAYLOAD_SIZE}"
+            ),
+            PacketGroupError::OversizedChunk { packet_number, len } => write!(
+                f,
+                "Packet {packet_number} has {len} bytes, more than the {MAX_PAYLOAD_SIZE} byte maximum"
+            ),
+            PacketGroupError::PastLastPacket {
+                packet_number,
+                last,
+            } => write!(
+                f,
+                "Packet {packet_number} comes after the last packet, {last}"
+            ),
+            PacketGroupError::ConflictingLastPacket {
+                packet_number,
+                conflicts_with,
+            } => write!(
+                f,
+                "Packet {packet_number} is marked last, but packet {conflicts_with} comes after it or is also marked last"
+            ),
+            PacketGroupError::SizeMismatch {
+                path,
+                expected,
+                written,
+            } => write!(
+                f,
+                "Wrote {written} bytes to {}, expected {expected}",
+                path.display()
+            ),
+            PacketGroupError::WrongSize { expected, received } => write!(
+                f,
+                "The header says the file is {expected} bytes, but its chunks add up to {received}"
+            ),
+            PacketGroupError::Metadata { path, .. } => write!(
+                f,
+                "Could not restore the modification time and permissions of {}",
+                path.display()
+            ),
+            PacketGroupError::UnsafePath(file_name) => write!(
+                f,
+                "File name {} isn't a relative path inside the output directory",
+                file_name.to_string_lossy()
+            ),
         }
     }
 }
 
-impl std::error::Error for PacketGroupError {}
+impl Error for PacketGroupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PacketGroupError::IoError(err)
+            | PacketGroupError::Write { source: err, .. }
+            | PacketGroupError::Metadata { source: err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
 
-impl From<std::io::Error> for PacketGroupError {
-    fn from(err: std::io::Error) -> Self {
+impl From<io::Error> for PacketGroupError {
+    fn from(err: io::Error) -> Self {
         PacketGroupError::IoError(err)
     }
 }
-impl From<PacketGroupError> for std::io::Error {
+impl From<PacketGroupError> for io::Error {
     fn from(err: PacketGroupError) -> Self {
         match err {
-            PacketGroupError::IoError(io_err) => io_err,
-            PacketGroupError::MissingPacket(_) => {
-                std::io::Error::new(std::io::ErrorKind::Other, "Missing packet error")
-            }
+            PacketGroupError::IoError(io_err)
+            | PacketGroupError::Write { source: io_err, .. }
+            | PacketGroupError::Metadata { source: io_err, .. } => io_err,
+            PacketGroupError::MissingPacket(_) => io::Error::other("Missing packet error"),
             PacketGroupError::MissingFileName => {
-                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Missing file name")
+                io::Error::new(io::ErrorKind::InvalidInput, "Missing file name")
             }
             PacketGroupError::MissingPacketCount => {
-                std::io::Error::new(std::io::ErrorKind::InvalidData, "Missing packet count")
+                io::Error::new(io::ErrorKind::InvalidData, "Missing packet count")
+            }
+            PacketGroupError::TooManyPackets(_) => {
+                io::Error::new(io::ErrorKind::InvalidData, "Too many packets")
+            }
+            unsafe_path @ PacketGroupError::UnsafePath(_) => {
+                io::Error::new(io::ErrorKind::InvalidData, unsafe_path.to_string())
+            }
+            chunk_error @ (PacketGroupError::ShortChunk { .. }
+            | PacketGroupError::OversizedChunk { .. }
+            | PacketGroupError::PastLastPacket { .. }
+            | PacketGroupError::ConflictingLastPacket { .. }
+            | PacketGroupError::SizeMismatch { .. }
+            | PacketGroupError::WrongSize { .. }) => {
+                io::Error::new(io::ErrorKind::InvalidData, chunk_error.to_string())
             }
         }
     }
 }
+
+/// Formats an error followed by every error in its `source()` chain, e.g.
+/// `File 3 (a.txt): Could not write src/a.txt at byte 0: Permission denied (os error 13)`.
+#[must_use]
+pub fn report(err: &dyn Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+    message
+}
@@ -1,47 +1,202 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
-use crate::{packet::Packet, packet_group::PacketGroup};
+use crate::{
+    errors::ClientError,
+    packet::{Ack, FileId, Packet, PacketRef},
+    packet_group::{PacketGroup, ReassemblyReport},
+};
 
 // FileManager manages the files being received
 #[derive(Default)]
 pub struct FileManager {
-    pub files: HashMap<u8, PacketGroup>,
+    pub files: HashMap<FileId, PacketGroup>,
+    // passed on to every PacketGroup; see PacketGroup::strict_chunks
+    pub strict_chunks: bool,
+    // passed on to every PacketGroup; see PacketGroup::streaming
+    pub streaming: bool,
+    // how many files the session has, if known from the command line
+    pub expected_files: Option<usize>,
+    // every file ID in the session, once a manifest packet has arrived. When
+    // set (and complete), completion and writing only look at these files.
+    pub manifest: Option<BTreeSet<FileId>>,
+    // how many files a wide manifest says the session has; until `manifest`
+    // holds that many, parts of it are still to come
+    pub manifest_total: Option<usize>,
+    // a manifest is on its way (a reliable server always sends one), so
+    // nothing is complete until it has arrived
+    pub wait_for_manifest: bool,
 }
 
 impl FileManager {
-    // checks if all packets are received for all files
+    // checks if all packets are received for all files. Without a manifest or
+    // `expected_files` that means every file seen so far, which can be true
+    // before the other files have sent anything.
     #[must_use]
     pub fn received_all_packets(&self) -> bool {
+        let complete = |file: &PacketGroup| file.all_packets_received() && file.file_name.is_some();
+
+        if let Some(manifest) = &self.manifest {
+            return self.complete_manifest().is_some()
+                && manifest
+                    .iter()
+                    .all(|file_id| self.files.get(file_id).is_some_and(complete));
+        }
+        if self.wait_for_manifest {
+            return false;
+        }
         !self.files.is_empty()
-            && self.files.values().all(PacketGroup::all_packets_received)
-            && self.files.values().all(|file| file.file_name.is_some())
+            && self.files.len() >= self.expected_files.unwrap_or(0)
+            && self.files.values().all(complete)
     }
 
-    // routes packets to the correct PacketGroup
-    pub fn process_packet(&mut self, packet: Packet) {
-        // println!("Processing packet: {:?}", packet);
+    // whether a file is part of the session as far as we know: with a
+    // manifest, only the files it lists are
+    #[must_use]
+    pub fn is_expected(&self, file_id: FileId) -> bool {
+        self.complete_manifest()
+            .is_none_or(|manifest| manifest.contains(&file_id))
+    }
+
+    // the manifest, once every part of it has arrived
+    #[must_use]
+    pub fn complete_manifest(&self) -> Option<&BTreeSet<FileId>> {
+        self.manifest.as_ref().filter(|manifest| {
+            self.manifest_total
+                .is_none_or(|total| manifest.len() >= total)
+        })
+    }
 
-        // set file_id based on the packet type
-        let file_id = match &packet {
-            Packet::Header(header) => header.file_id,
-            Packet::Data(data) => data.file_id,
+    /// Routes packets to the correct `PacketGroup`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `ClientError::File` if the `PacketGroup`
+    /// rejects the packet (only possible with `strict_chunks` or `streaming`).
+    /// When streaming, that includes failing to write the chunk to disk.
+    pub fn process_packet(&mut self, packet: Packet) -> Result<(), ClientError> {
+        // println!("Processing packet: {:?}", packet);
+        let Some(file_id) = packet.file_id() else {
+            if let Packet::Manifest(manifest) = packet {
+                self.add_to_manifest(manifest.file_ids, manifest.total);
+            }
+            return Ok(());
         };
 
         // Find the file group for the packet and process it
-        let file_group = self.files.entry(file_id).or_default(); // Create a new PacketGroup if it doesn't exist
-        file_group.process_packet(packet); // This is the PacketGroup process_packet method
+        let file_group = self.file_group(file_id); // Create a new PacketGroup if it doesn't exist
+        file_group
+            .process_packet(packet) // This is the PacketGroup process_packet method
+            .map_err(|source| ClientError::File {
+                file_id,
+                file_name: file_group.file_name.clone(),
+                source,
+            })
+    }
+
+    /// Routes a borrowed packet to the correct `PacketGroup` without copying
+    /// it first.
+    ///
+    /// # Errors
+    ///
+    /// The same as `process_packet`.
+    pub fn process_packet_ref(&mut self, packet: PacketRef<'_>) -> Result<(), ClientError> {
+        let Some(file_id) = packet.file_id() else {
+            if let PacketRef::Manifest(manifest) = packet {
+                self.add_to_manifest(manifest.file_ids(), manifest.total);
+            }
+            return Ok(());
+        };
+        let file_group = self.file_group(file_id);
+        file_group
+            .process_packet_ref(packet)
+            .map_err(|source| ClientError::File {
+                file_id,
+                file_name: file_group.file_name.clone(),
+                source,
+            })
+    }
+
+    // the server may repeat the manifest in case one is lost, and split a
+    // wide one into parts; every copy adds to the set rather than replacing it
+    fn add_to_manifest(&mut self, file_ids: impl IntoIterator<Item = FileId>, total: Option<u32>) {
+        self.manifest
+            .get_or_insert_with(BTreeSet::new)
+            .extend(file_ids);
+        if let Some(total) = total {
+            self.manifest_total = Some(usize::try_from(total).unwrap_or(usize::MAX));
+        }
+    }
+
+    fn file_group(&mut self, file_id: FileId) -> &mut PacketGroup {
+        let (strict_chunks, streaming) = (self.strict_chunks, self.streaming);
+        self.files.entry(file_id).or_insert_with(|| PacketGroup {
+            strict_chunks,
+            streaming,
+            ..Default::default()
+        })
+    }
+
+    // an ack for one file, for reliable mode; None if nothing of it has
+    // arrived
+    #[must_use]
+    pub fn ack(&self, file_id: FileId) -> Option<Ack> {
+        self.files.get(&file_id).map(|file_group| Ack {
+            has_manifest: self.complete_manifest().is_some(),
+            ..file_group.ack(file_id)
+        })
+    }
+
+    // how many chunks were rebuilt from parity packets, over every file
+    #[must_use]
+    pub fn recovered(&self) -> usize {
+        self.files
+            .values()
+            .map(|file_group| file_group.recovered)
+            .sum()
+    }
+
+    // how many packets were skipped over on arrival, over every file; see
+    // PacketGroup::skipped
+    #[must_use]
+    pub fn skipped(&self) -> usize {
+        self.files
+            .values()
+            .map(|file_group| file_group.skipped)
+            .sum()
+    }
+
+    // validates every file, in file ID order
+    #[must_use]
+    pub fn reassembly_reports(&self) -> Vec<(FileId, ReassemblyReport)> {
+        let mut reports: Vec<(FileId, ReassemblyReport)> = self
+            .files
+            .iter()
+            .map(|(&file_id, file_group)| (file_id, file_group.validate()))
+            .collect();
+        reports.sort_unstable_by_key(|&(file_id, _)| file_id);
+        reports
     }
 
-    /// Writes all the files that are ready to be written.
+    /// Writes all the files that are ready to be written. Files a manifest
+    /// doesn't list are left out.
     ///
     /// # Errors
     ///
-    /// This function will return an error if:
-    /// - There is an I/O error while writing a file.
-    /// - A `PacketGroup` fails to write its file.
-    pub fn write_all_files(&self) -> Result<(), std::io::Error> {
-        for file_group in self.files.values() {
-            file_group.write_file()?; // delegate writing to the PacketGroup
+    /// This function will return `ClientError::File`, naming the file ID and
+    /// file name, if a `PacketGroup` fails to write its file.
+    pub fn write_all_files(&self) -> Result<(), ClientError> {
+        for (&file_id, file_group) in &self.files {
+            if !self.is_expected(file_id) {
+                continue;
+            }
+            // delegate writing to the PacketGroup
+            file_group
+                .write_file()
+                .map_err(|source| ClientError::File {
+                    file_id,
+                    file_name: file_group.file_name.clone(),
+                    source,
+                })?;
         }
         Ok(())
     }
@@ -50,11 +205,11 @@ impl FileManager {
 // methods used for testing, worried about their security implications
 #[allow(unused)]
 impl FileManager {
-    pub fn insert_packet_group(&mut self, file_id: u8, packet_group: PacketGroup) {
+    pub fn insert_packet_group(&mut self, file_id: FileId, packet_group: PacketGroup) {
         self.files.insert(file_id, packet_group);
     }
     #[must_use]
-    pub fn get_packet_group(&self, file_id: u8) -> Option<&PacketGroup> {
+    pub fn get_packet_group(&self, file_id: FileId) -> Option<&PacketGroup> {
         self.files.get(&file_id)
     }
 }
@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+
+// The rate the client asks for in its hello, in packets per second
+pub const INITIAL_RATE: u32 = 4000;
+// How often the client reconsiders its rate and tells the server
+pub const FEEDBACK_INTERVAL: Duration = Duration::from_millis(100);
+// Added to the rate after every interval without loss
+pub const RATE_STEP: u32 = 500;
+// Halving never goes below this, so a lossy link still makes progress
+pub const MIN_RATE: u32 = 100;
+
+// RateController picks the receive rate to advertise, AIMD style: once per
+// interval it adds `RATE_STEP` if no packets went missing since the last
+// time and halves the rate if some did. Loss is counted from the packet
+// numbers the file manager saw skipped over (see PacketGroup::skipped).
+#[derive(Debug, Clone)]
+pub struct RateController {
+    rate: u32,
+    interval: Duration,
+    last_update: Instant,
+    // the skipped count at the last update
+    skipped: usize,
+}
+
+impl RateController {
+    #[must_use]
+    pub fn new(rate: u32, interval: Duration, now: Instant) -> Self {
+        RateController {
+            rate,
+            interval,
+            last_update: now,
+            skipped: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    // when the next update is due
+    #[must_use]
+    pub fn due(&self) -> Instant {
+        self.last_update + self.interval
+    }
+
+    // Called with the total number of skipped packets so far: once an
+    // interval has passed, adjusts the rate and returns it to send as
+    // feedback; otherwise returns None
+    pub fn update(&mut self, skipped: usize, now: Instant) -> Option<u32> {
+        if now < self.due() {
+            return None;
+        }
+        self.rate = if skipped > self.skipped {
+            (self.rate / 2).max(MIN_RATE)
+        } else {
+            self.rate.saturating_add(RATE_STEP)
+        };
+        self.skipped = skipped;
+        self.last_update = now;
+        Some(self.rate)
+    }
+}
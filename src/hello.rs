@@ -0,0 +1,450 @@
+use std::{
+    fmt, io,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{errors::ClientError, packet::PacketParser};
+
+// The hello starts with these bytes so a server can tell it from the legacy
+// hello (1028 zero bytes), and the reply with these so the client can tell it
+// from a packet: 'S' (0x53) sets the metadata bit together with the data bit,
+// and only headers carry metadata, so no valid packet starts with it.
+pub const HELLO_MAGIC: &[u8; 4] = b"SFSH";
+pub const REPLY_MAGIC: &[u8; 4] = b"SFSR";
+pub const PROTOCOL_VERSION: u8 = 1;
+
+// magic, version, 2 byte capability bitmap
+const HELLO_SIZE: usize = 7;
+// a hello offering flow control adds the 4 byte starting rate
+const HELLO_WITH_RATE_SIZE: usize = HELLO_SIZE + 4;
+
+// the backoff between hellos stops doubling here
+pub const MAX_HELLO_INTERVAL: Duration = Duration::from_secs(4);
+// how many datagrams are held while waiting for a reply; a structured server
+// in best-effort mode repeats its reply after every burst of 64, so this is
+// room for a few
+pub const MAX_HELD: usize = 256;
+
+// Capabilities is the bitmap of optional protocol features. The client
+// offers some in its hello and the server's reply selects the ones it will
+// use, which must be a subset of the offer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u16);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    // the server resends packets the client reports missing
+    pub const RETRANSMIT: Capabilities = Capabilities(1 << 0);
+    // every packet ends with a 2 byte internet checksum of the rest
+    pub const CHECKSUMS: Capabilities = Capabilities(1 << 1);
+    // headers carry the file's size, modification time and permissions too
+    pub const EXTENDED_HEADER: Capabilities = Capabilities(1 << 2);
+    // payloads are compressed
+    pub const COMPRESSION: Capabilities = Capabilities(1 << 3);
+    // the server sends parity packets the client can rebuild a lost chunk from
+    pub const PARITY: Capabilities = Capabilities(1 << 4);
+    // the server paces its sending to the rate the client asks for
+    pub const FLOW_CONTROL: Capabilities = Capabilities(1 << 5);
+    // packet numbers past 65535 are sent in 4 bytes, for files over 64 MiB
+    pub const LARGE_FILES: Capabilities = Capabilities(1 << 6);
+    // file IDs past 255 are sent in 4 bytes, for sessions of over 256 files
+    pub const WIDE_FILE_IDS: Capabilities = Capabilities(1 << 7);
+    // what this client knows how to handle, and so offers by default
+    pub const SUPPORTED: Capabilities = Capabilities::RETRANSMIT
+        .union(Capabilities::CHECKSUMS)
+        .union(Capabilities::EXTENDED_HEADER)
+        .union(Capabilities::PARITY)
+        .union(Capabilities::FLOW_CONTROL)
+        .union(Capabilities::LARGE_FILES)
+        .union(Capabilities::WIDE_FILE_IDS);
+
+    const NAMES: [(Capabilities, &'static str); 8] = [
+        (Capabilities::RETRANSMIT, "retransmit"),
+        (Capabilities::CHECKSUMS, "checksums"),
+        (Capabilities::EXTENDED_HEADER, "extended-header"),
+        (Capabilities::COMPRESSION, "compression"),
+        (Capabilities::PARITY, "parity"),
+        (Capabilities::FLOW_CONTROL, "flow-control"),
+        (Capabilities::LARGE_FILES, "large-files"),
+        (Capabilities::WIDE_FILE_IDS, "wide-file-ids"),
+    ];
+
+    // Unknown bits are kept, so a reply selecting something we never offered
+    // can be caught
+    #[must_use]
+    pub const fn from_bits(bits: u16) -> Self {
+        Capabilities(bits)
+    }
+
+    #[must_use]
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    #[must_use]
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[must_use]
+    pub const fn union(self, other: Capabilities) -> Self {
+        Capabilities(self.0 | other.0)
+    }
+
+    #[must_use]
+    pub const fn intersection(self, other: Capabilities) -> Self {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+// comma separated names, or "none"
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Capabilities::NONE {
+            return write!(f, "none");
+        }
+        let mut rest = *self;
+        let mut first = true;
+        for (capability, name) in Capabilities::NAMES {
+            if self.contains(capability) {
+                write!(f, "{}{name}", if first { "" } else { ", " })?;
+                rest = Capabilities(rest.0 & !capability.0);
+                first = false;
+            }
+        }
+        if rest != Capabilities::NONE {
+            write!(
+                f,
+                "{}unknown {:#06x}",
+                if first { "" } else { ", " },
+                rest.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Capabilities {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(Capabilities::NONE);
+        }
+        s.split(',')
+            .map(str::trim)
+            .try_fold(Capabilities::NONE, |capabilities, name| {
+                Capabilities::NAMES
+                    .iter()
+                    .find(|&&(_, known)| known == name)
+                    .map(|&(capability, _)| capabilities.union(capability))
+                    .ok_or_else(|| format!("unknown capability: {name}"))
+            })
+    }
+}
+
+// Hello is what the client sends to start a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hello {
+    pub version: u8,
+    pub capabilities: Capabilities,
+    // with flow control, the most packets per second to start sending at
+    pub rate: Option<u32>,
+}
+
+impl Hello {
+    #[must_use]
+    pub fn new(capabilities: Capabilities) -> Self {
+        Hello {
+            version: PROTOCOL_VERSION,
+            capabilities,
+            rate: None,
+        }
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = encode(HELLO_MAGIC, self.version, self.capabilities);
+        if let Some(rate) = self.rate {
+            bytes.extend_from_slice(&rate.to_be_bytes());
+        }
+        bytes
+    }
+
+    // None for anything else, including the legacy all-zero hello
+    #[must_use]
+    pub fn parse(datagram: &[u8]) -> Option<Self> {
+        let (datagram, rate) = match datagram.len() {
+            HELLO_WITH_RATE_SIZE => {
+                let (datagram, rate) = datagram.split_at(HELLO_SIZE);
+                (datagram, Some(u32::from_be_bytes(rate.try_into().ok()?)))
+            }
+            _ => (datagram, None),
+        };
+        decode(HELLO_MAGIC, datagram).map(|(version, capabilities)| Hello {
+            version,
+            capabilities,
+            rate,
+        })
+    }
+
+    // The server's side of the negotiation: the lower of the two versions and
+    // the features both sides support
+    #[must_use]
+    pub fn reply(&self, server_capabilities: Capabilities) -> HelloReply {
+        HelloReply {
+            version: self.version.min(PROTOCOL_VERSION),
+            capabilities: self.capabilities.intersection(server_capabilities),
+        }
+    }
+}
+
+// HelloReply is the server's answer to a Hello. A legacy server doesn't send
+// one and goes straight to sending packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HelloReply {
+    pub version: u8,
+    pub capabilities: Capabilities,
+}
+
+impl HelloReply {
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(REPLY_MAGIC, self.version, self.capabilities)
+    }
+
+    // None for anything else, so every received datagram can be checked
+    #[must_use]
+    pub fn parse(datagram: &[u8]) -> Option<Self> {
+        decode(REPLY_MAGIC, datagram).map(|(version, capabilities)| HelloReply {
+            version,
+            capabilities,
+        })
+    }
+
+    /// The client's side of the negotiation: returns the capabilities to
+    /// configure the parser with.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `ClientError::Negotiation` if the server
+    /// selected anything that wasn't `offered`.
+    pub fn accept(&self, offered: Capabilities) -> Result<Capabilities, ClientError> {
+        if offered.contains(self.capabilities) {
+            Ok(self.capabilities)
+        } else {
+            Err(ClientError::Negotiation {
+                offered,
+                selected: self.capabilities,
+            })
+        }
+    }
+}
+
+// HelloRetry schedules resending the hello, with exponential backoff, until
+// the server answers or the deadline has passed since the first one was sent
+#[derive(Debug)]
+pub struct HelloRetry {
+    started: Instant,
+    // when the latest hello went out
+    last_sent: Instant,
+    // how long to wait for an answer to the latest hello
+    interval: Duration,
+    deadline: Duration,
+    sent: u32,
+    // the last receive or send error, kept for the final report; a server
+    // that isn't up yet shows up as ConnectionRefused (ICMP port unreachable)
+    last_error: Option<io::Error>,
+}
+
+impl HelloRetry {
+    // Starts the schedule for a hello sent at `started`
+    #[must_use]
+    pub fn new(interval: Duration, deadline: Duration, started: Instant) -> Self {
+        HelloRetry {
+            started,
+            last_sent: started,
+            interval: interval.min(deadline),
+            deadline,
+            sent: 1,
+            last_error: None,
+        }
+    }
+
+    #[must_use]
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // When to give up on the latest hello. A refused connection is reported
+    // straight away, so waiting until then keeps a server that isn't up yet
+    // from being flooded with hellos.
+    #[must_use]
+    pub fn due(&self) -> Instant {
+        self.last_sent + self.interval
+    }
+
+    #[must_use]
+    pub fn sent(&self) -> u32 {
+        self.sent
+    }
+
+    // Whether an error while waiting for the server means "try again" rather
+    // than something wrong with our own socket
+    #[must_use]
+    pub fn is_retryable(error: &io::Error) -> bool {
+        matches!(
+            error.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::ConnectionRefused
+        )
+    }
+
+    pub fn record_error(&mut self, error: io::Error) {
+        // a timeout says nothing the final error won't
+        if !matches!(
+            error.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ) {
+            self.last_error = Some(error);
+        }
+    }
+
+    // Called when waiting for an answer gave up at `now`: counts another
+    // hello and returns how long to wait for it, or None once the deadline
+    // has passed
+    pub fn next_interval(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.started);
+        let remaining = self
+            .deadline
+            .checked_sub(elapsed)
+            .filter(|remaining| !remaining.is_zero())?;
+        self.interval = (self.interval * 2).min(MAX_HELLO_INTERVAL).min(remaining);
+        self.last_sent = now;
+        self.sent += 1;
+        Some(self.interval)
+    }
+
+    // The error to stop with once `next_interval` has run out
+    pub fn give_up(&mut self, addr: String, now: Instant) -> ClientError {
+        ClientError::ServerNotResponding {
+            addr,
+            hellos: self.sent,
+            waited: now.saturating_duration_since(self.started),
+            source: self.last_error.take(),
+        }
+    }
+}
+
+// Handshake settles which format a server's datagrams are in: the one its
+// hello reply negotiated, or the legacy one if it sends without replying.
+// Datagrams that arrive first are held rather than parsed, since a lost
+// reply would otherwise leave them read as the legacy format, which a
+// manifest or checksummed packet can pass for. The client and an offline
+// replay of its capture both go through this, so they agree.
+#[derive(Debug, Clone, Default)]
+pub struct Handshake {
+    // starts as the configured parser; the reply sets its capabilities
+    parser: PacketParser,
+    // by a reply, or by deciding there won't be one
+    settled: bool,
+    // at most MAX_HELD, in the order they arrived
+    held: Vec<Vec<u8>>,
+}
+
+// Arrival is what to do with a datagram that went through the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arrival {
+    // the first reply; settle the format with `negotiate`
+    Reply(HelloReply),
+    // a reply after the format was settled (a repeat, or one that came too
+    // late), which changes nothing
+    LateReply,
+    // kept until the format is settled
+    Held,
+    // kept, and the last one there's room for: a server that sends this
+    // much without replying is taken to be a legacy one, so settle with
+    // `settle_legacy`
+    Full,
+    // the format is settled, so parse it with `parser`
+    Ready,
+}
+
+impl Handshake {
+    #[must_use]
+    pub fn new(parser: PacketParser) -> Self {
+        Handshake {
+            parser,
+            settled: false,
+            held: Vec::new(),
+        }
+    }
+
+    // the parser for datagrams once the format is settled
+    #[must_use]
+    pub fn parser(&self) -> &PacketParser {
+        &self.parser
+    }
+
+    #[must_use]
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
+
+    // whether datagrams are waiting for the format to be settled
+    #[must_use]
+    pub fn is_holding(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    // Sorts out a datagram from the server. Once `Full` has been returned
+    // the caller settles, so no more than MAX_HELD are ever held.
+    pub fn arrive(&mut self, datagram: &[u8]) -> Arrival {
+        match (HelloReply::parse(datagram), self.settled) {
+            (Some(reply), false) => Arrival::Reply(reply),
+            (Some(_), true) => Arrival::LateReply,
+            (None, true) => Arrival::Ready,
+            (None, false) => {
+                self.held.push(datagram.to_vec());
+                if self.held.len() >= MAX_HELD {
+                    Arrival::Full
+                } else {
+                    Arrival::Held
+                }
+            }
+        }
+    }
+
+    // Settles on the format a reply negotiated and returns the held
+    // datagrams, now parseable
+    pub fn negotiate(&mut self, capabilities: Capabilities) -> Vec<Vec<u8>> {
+        self.parser.capabilities = capabilities;
+        self.parser.structured = true;
+        self.settled = true;
+        std::mem::take(&mut self.held)
+    }
+
+    // Settles on the legacy format and returns the held datagrams
+    pub fn settle_legacy(&mut self) -> Vec<Vec<u8>> {
+        self.settled = true;
+        std::mem::take(&mut self.held)
+    }
+}
+
+fn encode(magic: &[u8], version: u8, capabilities: Capabilities) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HELLO_SIZE);
+    bytes.extend_from_slice(magic);
+    bytes.push(version);
+    bytes.extend_from_slice(&capabilities.bits().to_be_bytes());
+    bytes
+}
+
+fn decode(magic: &[u8], datagram: &[u8]) -> Option<(u8, Capabilities)> {
+    let &[version, hi, lo] = datagram.strip_prefix(magic)? else {
+        return None;
+    };
+    Some((
+        version,
+        Capabilities::from_bits(u16::from_be_bytes([hi, lo])),
+    ))
+}
@@ -0,0 +1,38 @@
+use std::fmt::Write;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Formats bytes as a classic hex dump for logging, 16 bytes per line:
+///
+/// ```text
+/// 0000  00 01 74 65 73 74                                 |..test|
+/// ```
+#[must_use]
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let _ = write!(dump, "{:04x} ", line * BYTES_PER_LINE);
+        for i in 0..BYTES_PER_LINE {
+            // an extra space between the two groups of 8
+            if i % 8 == 0 {
+                dump.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(dump, "{byte:02x} ");
+                }
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str(" |");
+        dump.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                char::from(byte)
+            } else {
+                '.'
+            }
+        }));
+        dump.push_str("|\n");
+    }
+    dump
+}
@@ -0,0 +1,424 @@
+use std::{
+    fmt::Write as _,
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    batch_receiver::BatchReceiver,
+    capture::CapturedDatagram,
+    config::ClientConfig,
+    errors::{ClientError, PacketParseError},
+    file_manager::FileManager,
+    hello::HelloReply,
+    hex,
+    packet::{FileId, PacketParser, PacketRef},
+    pcap, replay, socket,
+};
+
+// longer file names are cut short (in characters) in the decode
+const NAME_PREVIEW_CHARS: usize = 64;
+// how much of a data packet's payload to show
+const PAYLOAD_PREVIEW_BYTES: usize = 16;
+
+/// Decodes one datagram into a Wireshark-style, indented description:
+///
+/// ```text
+/// Segmented File System, 6 bytes
+///     Status: 0x00 (header)
+///         .... ...0 = Data packet: no
+///         .... ..0. = Last packet: no
+///         .... .0.. = Manifest: no
+///         .... 0... = Parity: no
+///         ...0 .... = Wide packet number: no
+///         ..0. .... = Wide file ID: no
+///         .0.. .... = Metadata: no
+///         0... .... = Reserved: 0x00
+///     File ID: 9
+///     File name: "test" (4 bytes)
+/// ```
+///
+/// Datagrams that don't parse get the parse error, an explanation of what
+/// the parser expected, and a hex dump.
+#[must_use]
+pub fn dissect(datagram: &[u8], parser: &PacketParser) -> String {
+    let mut out = String::new();
+    let len = datagram.len();
+    if let Some(reply) = HelloReply::parse(datagram) {
+        return dissect_hello_reply(reply, len);
+    }
+    let packet = parser.parse(datagram);
+    let _ = write!(
+        out,
+        "Segmented File System, {len} byte{}",
+        if len == 1 { "" } else { "s" }
+    );
+    if packet.is_err() {
+        out.push_str(" [malformed]");
+    }
+    out.push('\n');
+
+    if let Some(&status) = datagram.first() {
+        dissect_status(&mut out, status);
+    }
+    // a manifest's file IDs are listed below; a datagram that doesn't parse
+    // gets its second byte
+    let file_id = match &packet {
+        Ok(packet) => packet.file_id(),
+        Err(_) => datagram.get(1).copied().map(FileId::from),
+    };
+    if let Some(file_id) = file_id {
+        let _ = writeln!(out, "    File ID: {file_id}");
+    }
+
+    match packet {
+        Ok(PacketRef::Header(header)) => {
+            let name = header.file_name.to_string_lossy();
+            let preview: String = name.chars().take(NAME_PREVIEW_CHARS).collect();
+            let cut = if preview.len() < name.len() {
+                "..."
+            } else {
+                ""
+            };
+            let mut prefix = if datagram[0] & 0x20 == 0 { 2 } else { 5 };
+            if let Some(metadata) = header.metadata {
+                prefix += 24;
+                let _ = writeln!(out, "    Size: {} bytes", metadata.size);
+                let _ = writeln!(
+                    out,
+                    "    Modified: {}.{:09} seconds since the epoch",
+                    metadata.mtime, metadata.mtime_nanos
+                );
+                let _ = writeln!(out, "    Mode: {:#o}", metadata.mode);
+            }
+            let _ = writeln!(
+                out,
+                "    File name: {preview:?}{cut} ({} bytes)",
+                len - prefix
+            );
+        }
+        Ok(PacketRef::Data(data)) => {
+            let _ = writeln!(out, "    Packet number: {}", data.packet_number);
+            let _ = write!(out, "    Payload: {} bytes", data.payload.len());
+            if !data.payload.is_empty() {
+                out.push_str(" [");
+                let shown = data.payload.len().min(PAYLOAD_PREVIEW_BYTES);
+                for (i, byte) in data.payload[..shown].iter().enumerate() {
+                    let _ = write!(out, "{}{byte:02x}", if i == 0 { "" } else { " " });
+                }
+                if shown < data.payload.len() {
+                    out.push_str(" ...");
+                }
+                out.push(']');
+            }
+            out.push('\n');
+        }
+        Ok(PacketRef::Manifest(manifest)) => {
+            if let Some(total) = manifest.total {
+                let _ = writeln!(out, "    Files in the session: {total}");
+            }
+            let ids: Vec<String> = manifest.file_ids().map(|id| id.to_string()).collect();
+            let _ = writeln!(out, "    File IDs: {} ({})", ids.len(), ids.join(", "));
+        }
+        Ok(PacketRef::Parity(parity)) => {
+            let members = parity.packet_numbers();
+            let _ = writeln!(
+                out,
+                "    Packets: {} to {} ({} packets{})",
+                members.start(),
+                members.end(),
+                parity.count,
+                if parity.is_last_group {
+                    ", ending the file"
+                } else {
+                    ""
+                }
+            );
+            let _ = writeln!(out, "    Length XOR: {:#06x}", parity.length_xor);
+            let _ = writeln!(out, "    Parity: {} bytes", parity.payload.len());
+        }
+        Err(error) => {
+            let _ = writeln!(out, "    Error: {error}");
+            let _ = writeln!(out, "    Explanation: {}", explain(&error));
+            for line in hex::hex_dump(datagram).lines() {
+                let _ = writeln!(out, "    {line}");
+            }
+        }
+    }
+
+    out
+}
+
+// the status byte and what each of its bits means
+fn dissect_status(out: &mut String, status: u8) {
+    let kind = match (status & 0x01 != 0, status & 0x02 != 0) {
+        _ if status & 0x04 != 0 => "manifest",
+        _ if status & 0x08 != 0 => "parity",
+        (false, _) => "header",
+        (true, false) => "data",
+        (true, true) => "data, last packet",
+    };
+    let _ = writeln!(out, "    Status: {status:#04x} ({kind})");
+    for (bit, name) in [
+        (0, "Data packet"),
+        (1, "Last packet"),
+        (2, "Manifest"),
+        (3, "Parity"),
+        (4, "Wide packet number"),
+        (5, "Wide file ID"),
+        (6, "Metadata"),
+    ] {
+        let set = (status >> bit) & 0x01;
+        let mut pattern = *b"........";
+        pattern[7 - bit] = b'0' + set;
+        let _ = writeln!(
+            out,
+            "        {} {} = {name}: {}",
+            String::from_utf8_lossy(&pattern[..4]),
+            String::from_utf8_lossy(&pattern[4..]),
+            yes_no(set != 0)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "        {}... .... = Reserved: {:#04x}",
+        status >> 7,
+        status & 0x80
+    );
+}
+
+// the server's answer to a structured hello, which isn't a packet
+fn dissect_hello_reply(reply: HelloReply, len: usize) -> String {
+    format!(
+        "Segmented File System hello reply, {len} bytes\n    Version: {}\n    Capabilities: {}\n",
+        reply.version, reply.capabilities
+    )
+}
+
+/// Says, in terms of the wire format, why a datagram failed to parse.
+#[must_use]
+pub fn explain(error: &PacketParseError) -> String {
+    match error {
+        PacketParseError::ReservedBitsSet(status) if status & 0xC0 == 0x40 => format!(
+            "bit 6 (metadata) of the status byte is only defined with the extended header \
+             capability, and then only on header packets, with or without bit 5 (wide file ID); \
+             {status:#04x} has it"
+        ),
+        PacketParseError::TooShort => {
+            "every datagram starts with a status byte and a file ID, so it needs at least 2 bytes, \
+             and a header with bit 6 (metadata) set 24 more for the size, modification time and \
+             mode"
+                .to_string()
+        }
+        PacketParseError::ReservedBitsSet(status) if status & 0xC8 == 0x08 => format!(
+            "bit 3 (parity) of the status byte is only defined with the parity capability, and \
+             then only on its own or with bit 1 (last packet), bit 4 (wide packet number) and \
+             bit 5 (wide file ID); {status:#04x} has it"
+        ),
+        PacketParseError::ReservedBitsSet(status) if status & 0xD0 == 0x10 => format!(
+            "bit 4 (wide packet number) of the status byte is only defined with the large files \
+             capability, and then only on data and parity packets; {status:#04x} has it"
+        ),
+        PacketParseError::ReservedBitsSet(status @ (0x04 | 0x24)) => format!(
+            "bit 2 (manifest) of the status byte is only defined in a structured session, where \
+             the server replied to the hello; a legacy server sends no manifest, so {status:#04x} \
+             is reserved here"
+        ),
+        PacketParseError::ReservedBitsSet(status) if status & 0xD4 == 0x04 => format!(
+            "bit 2 (manifest) of the status byte can't be combined with any other bit but bit 5 \
+             (wide file ID, with the wide file IDs capability), and {status:#04x} combines it"
+        ),
+        PacketParseError::ReservedBitsSet(status) if status & 0xD0 == 0 => format!(
+            "bit 5 (wide file ID) of the status byte is only defined with the wide file IDs \
+             capability; {status:#04x} has it"
+        ),
+        PacketParseError::ReservedBitsSet(status) => format!(
+            "only bit 0 (data packet), bit 1 (last packet), bit 2 (manifest), bit 3 (parity), \
+             bit 4 (wide packet number), bit 5 (wide file ID) and bit 6 (metadata) of the status \
+             byte are defined, the last four only with their capabilities; {:#04x} sets {:#04x} \
+             as well",
+            status,
+            status & 0x80
+        ),
+        PacketParseError::EmptyFileName => {
+            "a header packet (bit 0 of the status byte clear) carries the file name after the \
+             file ID (and its metadata, with bit 6 set), and this one stops right before the name"
+                .to_string()
+        }
+        PacketParseError::InvalidEncoding { offset } => format!(
+            "the file name must be UTF-8 on this platform, and the byte at offset {offset} \
+             isn't part of a valid UTF-8 sequence"
+        ),
+        PacketParseError::DataTooShort { len } => format!(
+            "a data packet (bit 0 of the status byte set) has a status byte, a file ID and a \
+             2 byte packet number (4 with bit 4 set) before its payload, and its file ID takes 4 \
+             bytes with bit 5 set, but only {len} bytes arrived"
+        ),
+        PacketParseError::Oversized { len, max } => format!(
+            "a data packet holds at most 1024 bytes of payload after its 4 byte prefix, and with \
+             the capabilities in use a datagram with this status byte can be at most {max} bytes \
+             (the checksums, parity, large files, wide file IDs and extended header capabilities \
+             each allow a little more, and acks and feedback have their own sizes), but {len} \
+             bytes arrived"
+        ),
+        PacketParseError::PartialFileId { len } => format!(
+            "with bit 5 (wide file ID) set, a manifest's status byte and 4 byte total are \
+             followed by 4 bytes per file ID, and {len} bytes leaves part of one"
+        ),
+        PacketParseError::BadChecksum { .. } => {
+            "with the checksums capability the last 2 bytes are the internet checksum of the \
+             rest of the datagram, so it was corrupted on the way or built with a different \
+             checksum"
+                .to_string()
+        }
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Runs the `inspect` subcommand: prints a decode of every datagram in a
+/// capture file (our own format or pcap), or with `--live`, of every datagram
+/// the server sends after a hello until all files are complete. Other
+/// options are the client's, so `--remote`, `--non-utf8-names` and so on
+/// work as usual.
+///
+/// # Errors
+///
+/// This function will return an error if the arguments are invalid, the
+/// capture can't be read, or the live socket fails.
+pub fn run_inspect<I>(args: I) -> Result<(), ClientError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut capture_file = None;
+    let mut live = false;
+    let mut server_port = pcap::SERVER_PORT;
+    let mut client_args = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--live" => live = true,
+            "--server-port" => {
+                let port = args.next().unwrap_or_default();
+                server_port = port.parse().map_err(|_| {
+                    ClientError::InvalidArgument(format!("invalid value for {arg}: {port}"))
+                })?;
+            }
+            _ if !arg.starts_with("--") && capture_file.is_none() && client_args.is_empty() => {
+                capture_file = Some(arg);
+            }
+            _ => client_args.push(arg),
+        }
+    }
+    let config = ClientConfig::from_args(client_args)?;
+
+    match (capture_file, live) {
+        (Some(path), false) => dissect_capture(
+            replay::open_capture(path, server_port)?,
+            &config.parser,
+            &mut io::stdout().lock(),
+        ),
+        (None, true) => inspect_live(&config),
+        _ => Err(ClientError::InvalidArgument(
+            "usage: inspect <capture or pcap file> [--server-port PORT] | inspect --live \
+             [client options]"
+                .to_string(),
+        )),
+    }
+}
+
+/// Writes a numbered, timestamped decode of every captured datagram to
+/// `out`. A recorded hello reply switches the datagrams after it to the
+/// format it negotiated, as it did for the client.
+///
+/// # Errors
+///
+/// This function will return an error if reading the capture or writing to
+/// `out` fails.
+pub fn dissect_capture<I>(
+    datagrams: I,
+    parser: &PacketParser,
+    out: &mut impl io::Write,
+) -> Result<(), ClientError>
+where
+    I: IntoIterator<Item = io::Result<CapturedDatagram>>,
+{
+    let mut parser = *parser;
+    for (i, datagram) in datagrams.into_iter().enumerate() {
+        let datagram = datagram?;
+        follow_reply(&mut parser, &datagram.data);
+        print_dissection(out, i + 1, datagram.offset, &datagram.data, parser)?;
+    }
+    Ok(())
+}
+
+fn inspect_live(config: &ClientConfig) -> Result<(), ClientError> {
+    let sock = socket::connect_to_server(
+        &config.remote_addr,
+        config.local_addr.as_deref(),
+        config.local_port,
+        &config.socket,
+    )
+    .map_err(|source| ClientError::Socket {
+        addr: config.remote_addr.clone(),
+        source,
+    })?;
+    let peer = sock.peer_addr().ok();
+
+    let started = Instant::now();
+    sock.send(&config.hello().to_bytes())?;
+
+    let mut receiver = BatchReceiver::new(config.batch_size);
+    // only used to know when the server is done
+    let mut file_manager = FileManager::default();
+    let mut parser = config.parser;
+    let mut count = 0;
+    while !file_manager.received_all_packets() {
+        receiver
+            .recv(&sock)
+            .map_err(|source| ClientError::Receive { peer, source })?;
+        for datagram in receiver.datagrams() {
+            count += 1;
+            follow_reply(&mut parser, datagram);
+            print_dissection(
+                &mut io::stdout().lock(),
+                count,
+                started.elapsed(),
+                datagram,
+                parser,
+            )?;
+            if let Ok(packet) = parser.parse(datagram) {
+                // lenient, so this can't fail
+                let _ = file_manager.process_packet_ref(packet);
+            }
+        }
+    }
+    Ok(())
+}
+
+// A hello reply switches the datagrams after it to the format it
+// negotiated, as it did for the client
+fn follow_reply(parser: &mut PacketParser, datagram: &[u8]) {
+    if let Some(reply) = HelloReply::parse(datagram) {
+        parser.capabilities = reply.capabilities;
+        parser.structured = true;
+    }
+}
+
+fn print_dissection(
+    out: &mut impl io::Write,
+    number: usize,
+    offset: Duration,
+    datagram: &[u8],
+    parser: PacketParser,
+) -> io::Result<()> {
+    writeln!(out, "#{number} +{:.6}s", offset.as_secs_f64())?;
+    writeln!(out, "{}", dissect(datagram, &parser))
+}
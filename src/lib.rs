@@ -4,7 +4,21 @@
 #![warn(clippy::complexity)]
 #![warn(clippy::correctness)]
 
-pub mod packet;
+pub mod batch_receiver;
+pub mod capture;
+pub mod client;
+pub mod config;
 pub mod errors;
+pub mod file_manager;
+pub mod flow_control;
+pub mod hello;
+pub mod hex;
+pub mod inspect;
+pub mod output;
+pub mod packet;
 pub mod packet_group;
-pub mod file_manager;
\ No newline at end of file
+pub mod pcap;
+pub mod quarantine;
+pub mod replay;
+pub mod server;
+pub mod socket;
@@ -0,0 +1,351 @@
+use std::{
+    ffi::OsStr,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::packet::{FileId, FileMetadata, MAX_PAYLOAD_SIZE};
+
+// Where finished files go - appeasing the bats test gods
+pub const OUTPUT_DIR: &str = "src";
+
+// keeps temporary names unique between files (and tests) in one process
+static NEXT_TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+// TempFile is a hidden file in the output directory that a file is written
+// to before it is renamed into place. A half-written file never shows up
+// under its real name, and whatever is already there (a symlink, or a
+// read-only copy from an earlier run) is replaced rather than written
+// through. Dropping it before then deletes it.
+#[derive(Debug)]
+pub struct TempFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Creates a new temporary file in `dir`, with `label` in its name.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be created.
+    pub fn create(dir: impl AsRef<Path>, label: impl fmt::Display) -> io::Result<Self> {
+        let path = dir.as_ref().join(format!(
+            ".{label}.{}-{}.part",
+            std::process::id(),
+            NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        // create_new never follows a symlink that's in the way
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(TempFile { file, path })
+    }
+
+    #[must_use]
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Renames the file to `path`, replacing anything already there.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the rename fails.
+    pub fn persist(&self, path: &Path) -> io::Result<()> {
+        fs::rename(&self.path, path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // once persisted the file has been renamed, so this is a no-op
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// StreamingOutput writes a file's chunks to disk as they arrive, each at its
+// final offset, instead of holding them in memory until the file is
+// complete. Chunks go to a TempFile, which is renamed into place once every
+// chunk is there.
+#[derive(Debug)]
+pub struct StreamingOutput {
+    temp: TempFile,
+    // length of the last chunk, once it has arrived
+    last_chunk_len: Option<usize>,
+}
+
+impl StreamingOutput {
+    /// Creates the temporary file for `file_id` in `dir`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be created.
+    pub fn create(dir: impl AsRef<Path>, file_id: FileId) -> io::Result<Self> {
+        Ok(StreamingOutput {
+            temp: TempFile::create(dir, file_id)?,
+            last_chunk_len: None,
+        })
+    }
+
+    #[must_use]
+    pub fn temp(&self) -> &TempFile {
+        &self.temp
+    }
+
+    #[must_use]
+    pub fn temp_path(&self) -> &Path {
+        self.temp.path()
+    }
+
+    #[must_use]
+    pub fn last_chunk_len(&self) -> Option<usize> {
+        self.last_chunk_len
+    }
+
+    /// Writes chunk `packet_number` at its offset in the file. Every chunk
+    /// before the last must be exactly 1024 bytes for the offsets to work,
+    /// which is what strict chunk checks guarantee.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the write fails.
+    pub fn write_chunk(&mut self, packet_number: u32, chunk: &[u8]) -> io::Result<()> {
+        write_all_at(self.temp.file(), chunk, chunk_offset(packet_number))
+    }
+
+    /// Reads back `len` bytes of chunk `packet_number`, for rebuilding a
+    /// lost chunk from parity.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the read fails.
+    pub fn read_chunk(&self, packet_number: u32, len: usize) -> io::Result<Vec<u8>> {
+        let mut chunk = vec![0; len];
+        read_exact_at(self.temp.file(), &mut chunk, chunk_offset(packet_number))?;
+        Ok(chunk)
+    }
+
+    /// Records the last chunk's length, which makes the exact file size
+    /// known, and reserves that much space. This is tighter than the
+    /// `(last + 1) * 1024` upper bound because strict chunks leave only the
+    /// last one short.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the space can't be reserved.
+    pub fn set_last_chunk(&mut self, packet_number: u32, len: usize) -> io::Result<()> {
+        self.last_chunk_len = Some(len);
+        preallocate(self.temp.file(), chunk_offset(packet_number) + len as u64)
+    }
+
+    /// Trims the file to `len` bytes, dropping whatever was reserved past
+    /// the end.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be resized.
+    pub fn trim(&self, len: u64) -> io::Result<()> {
+        self.temp.file().set_len(len)
+    }
+}
+
+/// Turns a header's file name into a path relative to the output directory.
+/// Names may hold directories, separated by `/` or `\`; empty and `.` parts
+/// are dropped, so `./docs//a.txt` is `docs/a.txt`. Returns `None` for a name
+/// that could land outside the output directory: one that is absolute, has a
+/// `..` part or a drive prefix, or has no parts left at all.
+#[must_use]
+pub fn relative_path(file_name: &OsStr) -> Option<PathBuf> {
+    let bytes = file_name.as_encoded_bytes();
+    if bytes.first().is_some_and(|&byte| is_separator(byte)) {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+    for part in name_parts(file_name)? {
+        if part.is_empty() || part == "." {
+            continue;
+        }
+        // anything but a plain name (`..`, or `C:` on Windows) is refused
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(part)), None) => path.push(part),
+            _ => return None,
+        }
+    }
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// Creates the directories `relative` (from `relative_path`) needs under
+/// `root`, one at a time. A part that already exists must be a real
+/// directory, not a symlink, so a file can't be written through one to
+/// somewhere outside `root`.
+///
+/// # Errors
+///
+/// This function will return an error if a directory can't be created, or
+/// something other than a directory is in the way.
+pub fn create_parent_dirs(root: &Path, relative: &Path) -> io::Result<()> {
+    let Some(parent) = relative.parent() else {
+        return Ok(());
+    };
+    let mut dir = root.to_path_buf();
+    for part in parent.components() {
+        dir.push(part);
+        match fs::symlink_metadata(&dir) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is in the way of a directory", dir.display()),
+                ))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => fs::create_dir(&dir)?,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+fn is_separator(byte: u8) -> bool {
+    byte == b'/' || byte == b'\\'
+}
+
+// a file name split at either separator
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)] // fallible on other platforms
+fn name_parts(file_name: &OsStr) -> Option<Vec<&OsStr>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Some(
+        file_name
+            .as_bytes()
+            .split(|&byte| is_separator(byte))
+            .map(OsStr::from_bytes)
+            .collect(),
+    )
+}
+
+// names that weren't UTF-8 were already re-encoded by the parser, so this
+// only fails for names made some other way
+#[cfg(not(unix))]
+fn name_parts(file_name: &OsStr) -> Option<Vec<&OsStr>> {
+    Some(
+        file_name
+            .to_str()?
+            .split(['/', '\\'])
+            .map(OsStr::new)
+            .collect(),
+    )
+}
+
+fn chunk_offset(packet_number: u32) -> u64 {
+    u64::from(packet_number) * MAX_PAYLOAD_SIZE as u64
+}
+
+/// Reserves `len` bytes for `file` up front, so running out of disk space
+/// shows up before the data is written and the file isn't grown piece by
+/// piece. Uses `fallocate` on Linux and falls back to `set_len` (which only
+/// makes a sparse file) where that isn't supported. Never shrinks the file.
+///
+/// # Errors
+///
+/// This function will return an error if the space can't be reserved.
+pub fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let len = libc::off_t::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))?;
+        // SAFETY: the descriptor belongs to `file`, which outlives the call
+        if unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EOPNOTSUPP) {
+            return Err(err);
+        }
+    }
+
+    if file.metadata()?.len() < len {
+        file.set_len(len)?;
+    }
+    Ok(())
+}
+
+/// Gives the finished `file` the modification time and permissions from its
+/// header. Only the read, write and execute bits are applied, never setuid,
+/// setgid or sticky; without Unix permissions only whether the owner may
+/// write is. Both are set through the open file, which must be writable, so
+/// no path is followed; its size must be final, since resizing it changes
+/// the time again.
+///
+/// # Errors
+///
+/// This function will return an error if either can't be set.
+pub fn restore_metadata(file: &File, metadata: &FileMetadata) -> io::Result<()> {
+    if let Some(modified) = metadata.modified() {
+        file.set_modified(modified)?;
+    }
+
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::Permissions::from_mode(metadata.mode & 0o777)
+    };
+    #[cfg(not(unix))]
+    let permissions = {
+        let mut permissions = file.metadata()?.permissions();
+        permissions.set_readonly(metadata.mode & 0o200 == 0);
+        permissions
+    };
+    file.set_permissions(permissions)
+}
+
+// pwrite on Unix, so the file position is never touched
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    file.write_all_at(buf, offset)
+}
+
+#[cfg(not(unix))]
+fn write_all_at(mut file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(not(unix))]
+fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
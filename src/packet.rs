@@ -1,105 +1,1118 @@
-use std::{convert::TryFrom, ffi::OsString};
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    ffi::{OsStr, OsString},
+    fmt::Write,
+    fs,
+    ops::RangeInclusive,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::errors::PacketParseError;
+use crate::hello::Capabilities;
+use crate::pcap::internet_checksum;
 
-const HEADER_PACKET_SIZE: usize = 3;
+// status byte + file ID; every packet has at least these
+const PACKET_PREFIX_SIZE: usize = 2;
 const DATA_PACKET_SIZE: usize = 4;
+// a data packet with a 4 byte packet number
+const WIDE_DATA_PACKET_SIZE: usize = 6;
 
-#[derive(Debug)]
+// status bits; a manifest has only MANIFEST_BIT set (and maybe
+// WIDE_FILE_ID_BIT), and a parity packet PARITY_BIT and maybe
+// LAST_PACKET_BIT. WIDE_BIT (data and parity packets
+// only) means the packet number takes 4 bytes instead of 2,
+// WIDE_FILE_ID_BIT (any packet) that the file ID takes 4 bytes instead of 1,
+// and METADATA_BIT (headers only) that FileMetadata follows the file ID.
+const DATA_BIT: u8 = 0x01;
+const LAST_PACKET_BIT: u8 = 0x02;
+const MANIFEST_BIT: u8 = 0x04;
+const PARITY_BIT: u8 = 0x08;
+const WIDE_BIT: u8 = 0x10;
+const WIDE_FILE_ID_BIT: u8 = 0x20;
+const METADATA_BIT: u8 = 0x40;
+const RESERVED_BITS: u8 = 0x80;
+// how much longer any packet is with a 4 byte file ID
+const WIDE_FILE_ID_EXTRA: usize = 3;
+// status byte and the 4 byte total of files in the session
+const WIDE_MANIFEST_PREFIX_SIZE: usize = 5;
+// 8 byte size, 8 byte modification time in seconds, 4 byte nanoseconds and
+// 4 byte mode
+const METADATA_SIZE: usize = 24;
+// status byte, file ID, 2 byte first packet number, count, 2 byte length XOR
+const PARITY_PREFIX_SIZE: usize = 7;
+// the same with a 4 byte first packet number
+const WIDE_PARITY_PREFIX_SIZE: usize = 9;
+
+// Client to server: an ack, and its flags
+const ACK_STATUS: u8 = 0x80;
+const ACK_HAS_HEADER: u8 = 0x01;
+const ACK_HAS_MANIFEST: u8 = 0x02;
+// status byte, file ID, flags, 4 byte cumulative packet number
+const ACK_PREFIX_SIZE: usize = 7;
+// the same with a 4 byte file ID
+const WIDE_ACK_STATUS: u8 = ACK_STATUS | WIDE_FILE_ID_BIT;
+const WIDE_ACK_PREFIX_SIZE: usize = ACK_PREFIX_SIZE + WIDE_FILE_ID_EXTRA;
+// Client to server: flow control feedback, the status byte and a 4 byte rate
+const FEEDBACK_STATUS: u8 = 0x81;
+const FEEDBACK_SIZE: usize = 5;
+// An ack describes at most this many bytes' worth of packets past the
+// cumulative point; anything further is left for a later ack
+pub const MAX_ACK_BITMAP_SIZE: usize = 128;
+
+// A full data packet: 4 bytes of bookkeeping and 1024 bytes of data
+pub const MAX_PACKET_SIZE: usize = 1028;
+// Every data packet but the last carries exactly this much of the file
+pub const MAX_PAYLOAD_SIZE: usize = MAX_PACKET_SIZE - DATA_PACKET_SIZE;
+// A parity packet covering full chunks; longer than a data packet, so only
+// allowed with the parity capability
+pub const MAX_PARITY_PACKET_SIZE: usize = PARITY_PREFIX_SIZE + MAX_PAYLOAD_SIZE;
+// A 4 byte packet number (the large files capability) makes either 2 bytes
+// longer
+pub const MAX_WIDE_PACKET_SIZE: usize = WIDE_DATA_PACKET_SIZE + MAX_PAYLOAD_SIZE;
+pub const MAX_WIDE_PARITY_PACKET_SIZE: usize = WIDE_PARITY_PREFIX_SIZE + MAX_PAYLOAD_SIZE;
+// With the checksums capability every packet is followed by one
+pub const CHECKSUM_SIZE: usize = 2;
+// A 4 byte file ID (the wide file IDs capability) makes any packet 3 bytes
+// longer, so a part of a wide manifest holds this many file IDs
+pub const MAX_MANIFEST_PART_IDS: usize =
+    (MAX_PACKET_SIZE + WIDE_FILE_ID_EXTRA - WIDE_MANIFEST_PREFIX_SIZE) / 4;
+// Metadata (the extended header capability) lets a header run this long,
+// the longest packet of all
+pub const MAX_METADATA_HEADER_SIZE: usize = MAX_PACKET_SIZE + METADATA_SIZE;
+// The longest datagram any negotiated format allows
+pub const MAX_DATAGRAM_SIZE: usize = MAX_METADATA_HEADER_SIZE + WIDE_FILE_ID_EXTRA + CHECKSUM_SIZE;
+
+// Files are told apart by this. It's sent in 1 byte when it fits, and in 4
+// (the wide file IDs capability) when it doesn't.
+pub type FileId = u32;
+
+#[derive(Debug, PartialEq)]
 pub enum Packet {
     Header(Header),
     Data(Data),
+    Manifest(Manifest),
+    Parity(Parity),
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Header {
-    pub file_id: u8,
+    pub file_id: FileId,
     pub file_name: OsString,
     pub expected_packet_count: usize,
+    // sent between the file ID and the name, with the extended header
+    // capability
+    pub metadata: Option<FileMetadata>,
+}
+
+// What a header can carry besides the name (the extended header capability),
+// so the client can check the file's size and restore its modification time
+// and permissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMetadata {
+    // the exact size in bytes
+    pub size: u64,
+    // the modification time, as seconds and nanoseconds since the Unix epoch
+    // (the seconds are negative before it)
+    pub mtime: i64,
+    pub mtime_nanos: u32,
+    // Unix permission bits; only the lowest 9 are ever applied
+    pub mode: u32,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Data {
-    pub file_id: u8,
-    pub packet_number: u16,
+    pub file_id: FileId,
+    // sent in 2 bytes when it fits, and in 4 (the large files capability)
+    // when it doesn't
+    pub packet_number: u32,
     pub is_last_packet: bool,
     pub payload: Vec<u8>, // Renamed from 'data' to 'payload'
 }
 
-// TryFrom implementation for Packet (Top level packet type)
-impl TryFrom<&[u8]> for Packet {
-    type Error = PacketParseError;
+// A manifest lists every file ID in the session, so the client knows when
+// it has all of them. It's optional: the status byte is 0x04 and the rest of
+// the datagram is one file ID per byte. With wide file IDs (status 0x24) it
+// can be split into parts, each starting with the number of files in the
+// whole session, followed by 4 bytes per file ID.
+#[derive(Debug, PartialEq)]
+pub struct Manifest {
+    pub file_ids: Vec<FileId>,
+    // Some for a part of a wide manifest; a manifest with IDs that don't fit
+    // in a byte is sent as a wide one anyway, counting just its own IDs
+    pub total: Option<u32>,
+}
+
+// A parity packet (the parity capability) lets the client rebuild any one
+// lost chunk of a group of `count` consecutive data packets. The payload is
+// the XOR of the group's payloads, each padded with zeros to the longest, and
+// `length_xor` the XOR of their lengths, so a short last chunk comes back at
+// the right size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parity {
+    pub file_id: FileId,
+    // sent in 2 or 4 bytes, like a data packet's number
+    pub first_packet_number: u32,
+    pub count: u8,
+    // the group ends with the file's last packet
+    pub is_last_group: bool,
+    pub length_xor: u16,
+    pub payload: Vec<u8>,
+}
+
+// Borrowed view of a packet; parsing one of these never allocates.
+// Convert to `Packet` (or let `PacketGroup` copy the payload) only when the
+// bytes need to outlive the receive buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketRef<'a> {
+    Header(HeaderRef<'a>),
+    Data(DataRef<'a>),
+    Manifest(ManifestRef<'a>),
+    Parity(ParityRef<'a>),
+}
+
+// The file name is only copied when it has to be re-encoded (non-UTF-8 names
+// on platforms other than Unix)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderRef<'a> {
+    pub file_id: FileId,
+    pub file_name: Cow<'a, OsStr>,
+    pub metadata: Option<FileMetadata>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataRef<'a> {
+    pub file_id: FileId,
+    pub packet_number: u32,
+    pub is_last_packet: bool,
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ManifestRef<'a> {
+    // the file IDs as sent: 1 byte each, or 4 in a part of a wide manifest
+    pub ids: &'a [u8],
+    pub total: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParityRef<'a> {
+    pub file_id: FileId,
+    pub first_packet_number: u32,
+    pub count: u8,
+    pub is_last_group: bool,
+    pub length_xor: u16,
+    pub payload: &'a [u8],
+}
+
+impl PacketRef<'_> {
+    // None for a manifest, which is about the whole session
+    #[must_use]
+    pub fn file_id(&self) -> Option<FileId> {
+        match self {
+            PacketRef::Header(header) => Some(header.file_id),
+            PacketRef::Data(data) => Some(data.file_id),
+            PacketRef::Parity(parity) => Some(parity.file_id),
+            PacketRef::Manifest(_) => None,
+        }
+    }
+}
+
+impl Packet {
+    // None for a manifest, which is about the whole session
+    #[must_use]
+    pub fn file_id(&self) -> Option<FileId> {
+        match self {
+            Packet::Header(header) => Some(header.file_id),
+            Packet::Data(data) => Some(data.file_id),
+            Packet::Parity(parity) => Some(parity.file_id),
+            Packet::Manifest(_) => None,
+        }
+    }
+
+    // Encodes the packet the way the server sends it; the inverse of `try_from`
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Packet::Header(header) => header.to_bytes(),
+            Packet::Data(data) => data.to_bytes(),
+            Packet::Manifest(manifest) => manifest.to_bytes(),
+            Packet::Parity(parity) => parity.to_bytes(),
+        }
+    }
+}
+
+impl Header {
+    // `expected_packet_count` isn't sent, the server only marks the last packet
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let name = self.file_name.as_encoded_bytes();
+        let mut bytes = Vec::with_capacity(
+            PACKET_PREFIX_SIZE + WIDE_FILE_ID_EXTRA + METADATA_SIZE + name.len(),
+        );
+        bytes.push(if self.metadata.is_some() {
+            METADATA_BIT
+        } else {
+            0x00
+        });
+        push_file_id(&mut bytes, self.file_id);
+        if let Some(metadata) = self.metadata {
+            bytes.extend_from_slice(&metadata.to_bytes());
+        }
+        bytes.extend_from_slice(name);
+        bytes
+    }
+}
+
+impl FileMetadata {
+    // The metadata of a file on disk. Without Unix permissions a read-only
+    // file gets 0o444 and any other 0o644.
+    #[must_use]
+    pub fn from_fs(metadata: &fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+
+            metadata.permissions().mode() & 0o7777
+        };
+        #[cfg(not(unix))]
+        let mode = if metadata.permissions().readonly() {
+            0o444
+        } else {
+            0o644
+        };
+        let (mtime, mtime_nanos) = metadata.modified().map_or((0, 0), |modified| {
+            match modified.duration_since(UNIX_EPOCH) {
+                Ok(since) => (
+                    i64::try_from(since.as_secs()).unwrap_or(i64::MAX),
+                    since.subsec_nanos(),
+                ),
+                // before the epoch the seconds round down and the
+                // nanoseconds count back up from there
+                Err(before) => {
+                    let before = before.duration();
+                    let secs = i64::try_from(before.as_secs()).unwrap_or(i64::MAX);
+                    match before.subsec_nanos() {
+                        0 => (-secs, 0),
+                        nanos => (-secs - 1, 1_000_000_000 - nanos),
+                    }
+                }
+            }
+        });
+        FileMetadata {
+            size: metadata.len(),
+            mtime,
+            mtime_nanos,
+            mode,
+        }
+    }
+
+    // The modification time, or None if this platform can't represent it
+    #[must_use]
+    pub fn modified(&self) -> Option<SystemTime> {
+        let secs = Duration::from_secs(self.mtime.unsigned_abs());
+        let whole = if self.mtime < 0 {
+            UNIX_EPOCH.checked_sub(secs)
+        } else {
+            UNIX_EPOCH.checked_add(secs)
+        };
+        whole?.checked_add(Duration::from_nanos(u64::from(self.mtime_nanos)))
+    }
+
+    fn to_bytes(self) -> [u8; METADATA_SIZE] {
+        let mut bytes = [0; METADATA_SIZE];
+        bytes[..8].copy_from_slice(&self.size.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.mtime.to_be_bytes());
+        bytes[16..20].copy_from_slice(&self.mtime_nanos.to_be_bytes());
+        bytes[20..].copy_from_slice(&self.mode.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; METADATA_SIZE]) -> Self {
+        let mut size = [0; 8];
+        size.copy_from_slice(&bytes[..8]);
+        let mut mtime = [0; 8];
+        mtime.copy_from_slice(&bytes[8..16]);
+        FileMetadata {
+            size: u64::from_be_bytes(size),
+            mtime: i64::from_be_bytes(mtime),
+            mtime_nanos: read_number(&bytes[16..20]),
+            mode: read_number(&bytes[20..]),
+        }
+    }
+}
+
+impl Data {
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let status = if self.is_last_packet {
+            DATA_BIT | LAST_PACKET_BIT
+        } else {
+            DATA_BIT
+        };
+        let mut bytes =
+            Vec::with_capacity(WIDE_DATA_PACKET_SIZE + WIDE_FILE_ID_EXTRA + self.payload.len());
+        bytes.push(status);
+        push_file_id(&mut bytes, self.file_id);
+        push_packet_number(&mut bytes, self.packet_number);
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+impl Manifest {
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let narrow_ids: Option<Vec<u8>> = self
+            .file_ids
+            .iter()
+            .map(|&file_id| u8::try_from(file_id).ok())
+            .collect();
+        match (self.total, narrow_ids) {
+            (None, Some(file_ids)) => {
+                let mut bytes = Vec::with_capacity(1 + file_ids.len());
+                bytes.push(MANIFEST_BIT);
+                bytes.extend_from_slice(&file_ids);
+                bytes
+            }
+            (total, _) => {
+                let total =
+                    total.unwrap_or_else(|| u32::try_from(self.file_ids.len()).unwrap_or(u32::MAX));
+                let mut bytes =
+                    Vec::with_capacity(WIDE_MANIFEST_PREFIX_SIZE + 4 * self.file_ids.len());
+                bytes.push(MANIFEST_BIT | WIDE_FILE_ID_BIT);
+                bytes.extend_from_slice(&total.to_be_bytes());
+                for file_id in &self.file_ids {
+                    bytes.extend_from_slice(&file_id.to_be_bytes());
+                }
+                bytes
+            }
+        }
+    }
+}
+
+impl<'a> ManifestRef<'a> {
+    // the file IDs listed, decoded
+    pub fn file_ids(&self) -> impl Iterator<Item = FileId> + 'a {
+        let size = if self.total.is_some() { 4 } else { 1 };
+        self.ids.chunks_exact(size).map(read_number)
+    }
+}
+
+impl Parity {
+    // The parity packet for `chunks`, the payloads of packets
+    // `first_packet_number..` (at most 255 of them)
+    #[must_use]
+    pub fn new(
+        file_id: FileId,
+        first_packet_number: u32,
+        chunks: &[&[u8]],
+        is_last_group: bool,
+    ) -> Self {
+        let mut payload = vec![0; chunks.iter().map(|chunk| chunk.len()).max().unwrap_or(0)];
+        let mut length_xor = 0;
+        for chunk in chunks {
+            xor_into(&mut payload, chunk);
+            length_xor ^= u16::try_from(chunk.len()).unwrap_or(u16::MAX);
+        }
+        Parity {
+            file_id,
+            first_packet_number,
+            count: u8::try_from(chunks.len()).unwrap_or(u8::MAX),
+            is_last_group,
+            length_xor,
+            payload,
+        }
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let status = if self.is_last_group {
+            PARITY_BIT | LAST_PACKET_BIT
+        } else {
+            PARITY_BIT
+        };
+        let mut bytes =
+            Vec::with_capacity(WIDE_PARITY_PREFIX_SIZE + WIDE_FILE_ID_EXTRA + self.payload.len());
+        bytes.push(status);
+        push_file_id(&mut bytes, self.file_id);
+        push_packet_number(&mut bytes, self.first_packet_number);
+        bytes.push(self.count);
+        bytes.extend_from_slice(&self.length_xor.to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    // The packet numbers the group covers
+    #[must_use]
+    pub fn packet_numbers(&self) -> RangeInclusive<u32> {
+        group_packet_numbers(self.first_packet_number, self.count)
+    }
+}
+
+impl ParityRef<'_> {
+    // The packet numbers the group covers
+    #[must_use]
+    pub fn packet_numbers(&self) -> RangeInclusive<u32> {
+        group_packet_numbers(self.first_packet_number, self.count)
+    }
+}
+
+// cut short at the last possible packet number rather than wrapping; empty
+// for an empty group
+fn group_packet_numbers(first: u32, count: u8) -> RangeInclusive<u32> {
+    match (u64::from(first) + u64::from(count)).checked_sub(1) {
+        Some(last) if count > 0 => first..=u32::try_from(last).unwrap_or(u32::MAX),
+        // an inclusive range that yields nothing
+        _ => RangeInclusive::new(1, 0),
+    }
+}
+
+// Appends a file ID after the status byte: 1 byte if it fits, otherwise 4
+// with the wide file ID bit set in the status byte
+fn push_file_id(bytes: &mut Vec<u8>, file_id: FileId) {
+    if let Ok(file_id) = u8::try_from(file_id) {
+        bytes.push(file_id);
+    } else {
+        bytes[0] |= WIDE_FILE_ID_BIT;
+        bytes.extend_from_slice(&file_id.to_be_bytes());
+    }
+}
+
+// Appends a packet number after the status byte and file ID: 2 bytes if it
+// fits, otherwise 4 with the wide bit set in the status byte
+fn push_packet_number(bytes: &mut Vec<u8>, packet_number: u32) {
+    if let Ok(packet_number) = u16::try_from(packet_number) {
+        bytes.extend_from_slice(&packet_number.to_be_bytes());
+    } else {
+        bytes[0] |= WIDE_BIT;
+        bytes.extend_from_slice(&packet_number.to_be_bytes());
+    }
+}
+
+// XORs `chunk` into the start of `acc`, which must be at least as long
+pub fn xor_into(acc: &mut [u8], chunk: &[u8]) {
+    for (a, b) in acc.iter_mut().zip(chunk) {
+        *a ^= b;
+    }
+}
+
+// Ack is what the client sends back in reliable mode (the retransmit
+// capability): a cumulative acknowledgement plus a bitmap of the packets
+// received beyond it, for one file. The server retransmits whatever stays
+// unacknowledged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ack {
+    pub file_id: FileId,
+    pub has_header: bool,
+    // the manifest has arrived (it isn't tied to any one file)
+    pub has_manifest: bool,
+    // every packet numbered below this has arrived, and this one hasn't
+    pub cumulative: u32,
+    // bit i (least significant first in each byte) is set if packet
+    // `cumulative + 1 + i` has arrived
+    pub selective: Vec<u8>,
+}
+
+impl Ack {
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.has_header {
+            flags |= ACK_HAS_HEADER;
+        }
+        if self.has_manifest {
+            flags |= ACK_HAS_MANIFEST;
+        }
+        let mut bytes = Vec::with_capacity(WIDE_ACK_PREFIX_SIZE + self.selective.len());
+        bytes.push(ACK_STATUS);
+        push_file_id(&mut bytes, self.file_id);
+        bytes.push(flags);
+        bytes.extend_from_slice(&self.cumulative.to_be_bytes());
+        bytes.extend_from_slice(&self.selective);
+        bytes
+    }
+
+    /// Parses an ack from the client.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the datagram is shorter than an
+    /// ack's 7 byte prefix (10 with a 4 byte file ID), isn't an ack, or has a
+    /// longer bitmap than `MAX_ACK_BITMAP_SIZE`.
+    pub fn parse(datagram: &[u8]) -> Result<Self, PacketParseError> {
+        let status = datagram.first().copied().unwrap_or(0);
+        let prefix_size = if status == WIDE_ACK_STATUS {
+            WIDE_ACK_PREFIX_SIZE
+        } else {
+            ACK_PREFIX_SIZE
+        };
+        if datagram.len() < prefix_size {
+            return Err(PacketParseError::TooShort);
+        }
+        if datagram.len() > prefix_size + MAX_ACK_BITMAP_SIZE {
+            return Err(PacketParseError::Oversized {
+                len: datagram.len(),
+                max: prefix_size + MAX_ACK_BITMAP_SIZE,
+            });
+        }
+        // the file ID, then the flags and the cumulative packet number
+        let id_end = prefix_size - 5;
+        let flags = datagram[id_end];
+        if (status != ACK_STATUS && status != WIDE_ACK_STATUS)
+            || flags & !(ACK_HAS_HEADER | ACK_HAS_MANIFEST) != 0
+        {
+            return Err(PacketParseError::ReservedBitsSet(status));
+        }
+
+        Ok(Ack {
+            file_id: read_number(&datagram[1..id_end]),
+            has_header: flags & ACK_HAS_HEADER != 0,
+            has_manifest: flags & ACK_HAS_MANIFEST != 0,
+            cumulative: read_number(&datagram[id_end + 1..prefix_size]),
+            selective: datagram[prefix_size..].to_vec(),
+        })
+    }
+
+    // Whether this ack says `packet_number` has arrived
+    #[must_use]
+    pub fn has_packet(&self, packet_number: u32) -> bool {
+        if packet_number < self.cumulative {
+            return true;
+        }
+        let Some(bit) = (packet_number - self.cumulative)
+            .checked_sub(1)
+            .and_then(|bit| usize::try_from(bit).ok())
+        else {
+            return false;
+        };
+        self.selective
+            .get(bit / 8)
+            .is_some_and(|byte| byte & (1 << (bit % 8)) != 0)
+    }
+}
+
+// Feedback is what the client sends with the flow control capability: the
+// most packets per second it wants to receive, raised while nothing is lost
+// and cut when packets go missing. The server paces its sending to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Feedback {
+    pub rate: u32,
+}
+
+impl Feedback {
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FEEDBACK_SIZE);
+        bytes.push(FEEDBACK_STATUS);
+        bytes.extend_from_slice(&self.rate.to_be_bytes());
+        bytes
+    }
+
+    /// Parses flow control feedback from the client.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the datagram isn't exactly 5
+    /// bytes or isn't feedback.
+    pub fn parse(datagram: &[u8]) -> Result<Self, PacketParseError> {
+        let &[status, a, b, c, d] = datagram else {
+            return Err(if datagram.len() < FEEDBACK_SIZE {
+                PacketParseError::TooShort
+            } else {
+                PacketParseError::Oversized {
+                    len: datagram.len(),
+                    max: FEEDBACK_SIZE,
+                }
+            });
+        };
+        if status != FEEDBACK_STATUS {
+            return Err(PacketParseError::ReservedBitsSet(status));
+        }
+        Ok(Feedback {
+            rate: u32::from_be_bytes([a, b, c, d]),
+        })
+    }
+}
+
+// Adds the trailing checksum the checksums capability puts on every packet:
+// the internet checksum (as in IP and UDP) of the packet bytes, big-endian
+pub fn append_checksum(bytes: &mut Vec<u8>) {
+    let checksum = internet_checksum(&[bytes]);
+    bytes.extend_from_slice(&checksum.to_be_bytes());
+}
+
+// What to do with a file name that isn't valid UTF-8. On Unix an `OsString`
+// can hold any bytes so the name is always kept as-is; everywhere else it has
+// to be turned into Unicode somehow (or rejected).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileNameFallback {
+    Lossy,
+    #[default]
+    PercentEscape,
+    Reject,
+}
+
+impl FromStr for FileNameFallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lossy" => Ok(FileNameFallback::Lossy),
+            "escape" => Ok(FileNameFallback::PercentEscape),
+            "reject" => Ok(FileNameFallback::Reject),
+            _ => Err(format!("unknown file name fallback: {s}")),
+        }
+    }
+}
+
+// PacketParser holds the settings that affect how datagrams are decoded.
+// The `TryFrom` impls below all use `PacketParser::default()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketParser {
+    pub file_name_fallback: FileNameFallback,
+    // what the hello negotiated; none (the legacy format) until a server
+    // replies
+    pub capabilities: Capabilities,
+    // whether a server replied to the hello. Only a structured session has a
+    // manifest, whatever it negotiated, so until then the manifest bit is
+    // reserved like the others.
+    pub structured: bool,
+}
+
+impl PacketParser {
+    /// Parses a datagram into a borrowed packet.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the datagram is too long or too
+    /// short, the status byte has reserved bits set (or the manifest bit along
+    /// with any other, or at all outside a structured session), or the
+    /// header or data packet it holds is malformed.
+    /// With the checksums capability, also if the checksum doesn't match.
+    pub fn parse<'a>(&self, value: &'a [u8]) -> Result<PacketRef<'a>, PacketParseError> {
+        let parity = self.capabilities.contains(Capabilities::PARITY);
+        let large_files = self.capabilities.contains(Capabilities::LARGE_FILES);
+        let wide_file_ids = self.capabilities.contains(Capabilities::WIDE_FILE_IDS);
+        let extended_header = self.capabilities.contains(Capabilities::EXTENDED_HEADER);
+        let checksum_size = if self.capabilities.contains(Capabilities::CHECKSUMS) {
+            CHECKSUM_SIZE
+        } else {
+            0
+        };
+        let value = if checksum_size > 0 {
+            strip_checksum(
+                value,
+                max_packet_size(u8::MAX, self.capabilities) + CHECKSUM_SIZE,
+            )?
+        } else {
+            value
+        };
+        let status = value.first().copied().unwrap_or(0);
+        let is_parity = parity && status & PARITY_BIT != 0;
+
+        // reported with the checksum counted, as the datagram arrived
+        let max = max_packet_size(status, self.capabilities);
+        if value.len() > max {
+            return Err(PacketParseError::Oversized {
+                len: value.len() + checksum_size,
+                max: max + checksum_size,
+            });
+        }
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         // Check if the packet is too short
-        if value.len() < HEADER_PACKET_SIZE {
+        if value.len() < PACKET_PREFIX_SIZE {
             return Err(PacketParseError::TooShort);
         }
 
         let status_byte = value[0];
 
-        // Validate the status byte
-        if status_byte & 0xFC != 0 {
-            return Err(PacketParseError::InvalidPacketFormat);
+        // Validate the status byte; the parity, wide and metadata bits are
+        // reserved unless their capabilities were negotiated, and the
+        // manifest bit outside a structured session
+        if status_byte & RESERVED_BITS != 0
+            || (status_byte & MANIFEST_BIT != 0
+                && (!self.structured || status_byte & !WIDE_FILE_ID_BIT != MANIFEST_BIT))
+            || (status_byte & PARITY_BIT != 0
+                && (!parity
+                    || status_byte & !(LAST_PACKET_BIT | WIDE_BIT | WIDE_FILE_ID_BIT)
+                        != PARITY_BIT))
+            || (status_byte & WIDE_BIT != 0
+                && (!large_files || status_byte & (DATA_BIT | PARITY_BIT) == 0))
+            || (status_byte & WIDE_FILE_ID_BIT != 0 && !wide_file_ids)
+            || (status_byte & METADATA_BIT != 0
+                && (!extended_header
+                    || status_byte & (DATA_BIT | MANIFEST_BIT | PARITY_BIT | WIDE_BIT) != 0))
+        {
+            return Err(PacketParseError::ReservedBitsSet(status_byte));
         }
 
-        // Split to use the Header try_from or Data try_from
-        if (status_byte & 0x01) == 0 {
+        // Split to use the header, data, manifest or parity parser
+        if is_parity {
+            self.parse_parity(value).map(PacketRef::Parity)
+        } else if status_byte & MANIFEST_BIT != 0 {
+            self.parse_manifest(value).map(PacketRef::Manifest)
+        } else if (status_byte & DATA_BIT) == 0 {
             // Header packet
-            Header::try_from(value).map(Packet::Header)
+            self.parse_header(value).map(PacketRef::Header)
         } else {
             // Data packet
-            Data::try_from(value).map(Packet::Data)
+            self.parse_data(value).map(PacketRef::Data)
         }
     }
-}
-
-// TryFrom implementation for Header
-impl TryFrom<&[u8]> for Header {
-    type Error = PacketParseError;
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        if value.len() < HEADER_PACKET_SIZE {
-            // This is redundant? Is checked in try_from for Packet
+    /// Parses a header packet.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the packet is too short (to
+    /// hold its metadata too, if it has any), the file name is empty, or the
+    /// file name can't be decoded.
+    pub fn parse_header<'a>(&self, value: &'a [u8]) -> Result<HeaderRef<'a>, PacketParseError> {
+        let id_end = PACKET_PREFIX_SIZE + file_id_extra(value);
+        let has_metadata = value
+            .first()
+            .is_some_and(|status| status & METADATA_BIT != 0);
+        let prefix_size = if has_metadata {
+            id_end + METADATA_SIZE
+        } else {
+            id_end
+        };
+        if value.len() < prefix_size {
+            // This is redundant? Is checked in parse
             return Err(PacketParseError::TooShort);
         }
+        if value.len() == prefix_size {
+            return Err(PacketParseError::EmptyFileName);
+        }
 
-        let file_id = value[1];
-        let file_name = OsString::from(
-            String::from_utf8(value[2..].to_vec())
-                .map_err(|_| PacketParseError::InvalidPacketFormat)?,
-        );
-        
-        //let expected_packet_count = u16::from_be_bytes([value[0], value[1]]) as usize;
-        let expected_packet_count = 0;
+        let file_id = read_number(&value[1..id_end]);
+        let metadata = value[id_end..prefix_size]
+            .first_chunk()
+            .map(FileMetadata::from_bytes);
+        let file_name =
+            decode_file_name(&value[prefix_size..], prefix_size, self.file_name_fallback)?;
 
-        Ok(Header {
+        Ok(HeaderRef {
             file_id,
             file_name,
-            expected_packet_count,
+            metadata,
         })
     }
-}
-
-// TryFrom implementation for Data packet
-impl TryFrom<&[u8]> for Data {
-    type Error = PacketParseError;
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        if value.len() < DATA_PACKET_SIZE {
-            return Err(PacketParseError::TooShort);
+    /// Parses a data packet.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the packet is shorter than the
+    /// 4 bytes of bookkeeping every data packet carries (2 more with a 4 byte
+    /// packet number, and 3 more with a 4 byte file ID).
+    pub fn parse_data<'a>(&self, value: &'a [u8]) -> Result<DataRef<'a>, PacketParseError> {
+        let is_wide = value.first().is_some_and(|status| status & WIDE_BIT != 0);
+        let id_end = PACKET_PREFIX_SIZE + file_id_extra(value);
+        let prefix_size = id_end - PACKET_PREFIX_SIZE
+            + if is_wide {
+                WIDE_DATA_PACKET_SIZE
+            } else {
+                DATA_PACKET_SIZE
+            };
+        if value.len() < prefix_size {
+            return Err(PacketParseError::DataTooShort { len: value.len() });
         }
 
-        let file_id = value[1];
-        let packet_number = u16::from_be_bytes([value[2], value[3]]);
-        let is_last_packet = value[0] & 0x02 != 0;
-        let payload = value[4..].to_vec();
+        let file_id = read_number(&value[1..id_end]);
+        let packet_number = read_number(&value[id_end..prefix_size]);
+        let is_last_packet = value[0] & LAST_PACKET_BIT != 0;
+        let payload = &value[prefix_size..];
 
-        Ok(Data {
+        Ok(DataRef {
             file_id,
             packet_number,
             is_last_packet,
             payload,
         })
     }
+
+    /// Parses a parity packet.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the packet is shorter than the
+    /// 7 bytes every parity packet starts with (2 more with a 4 byte packet
+    /// number, and 3 more with a 4 byte file ID).
+    pub fn parse_parity<'a>(&self, value: &'a [u8]) -> Result<ParityRef<'a>, PacketParseError> {
+        let is_wide = value.first().is_some_and(|status| status & WIDE_BIT != 0);
+        let id_end = PACKET_PREFIX_SIZE + file_id_extra(value);
+        let prefix_size = id_end - PACKET_PREFIX_SIZE
+            + if is_wide {
+                WIDE_PARITY_PREFIX_SIZE
+            } else {
+                PARITY_PREFIX_SIZE
+            };
+        if value.len() < prefix_size {
+            return Err(PacketParseError::TooShort);
+        }
+        // the count and length XOR follow the packet number
+        let rest = &value[prefix_size - 3..prefix_size];
+
+        Ok(ParityRef {
+            file_id: read_number(&value[1..id_end]),
+            first_packet_number: read_number(&value[id_end..prefix_size - 3]),
+            count: rest[0],
+            is_last_group: value[0] & LAST_PACKET_BIT != 0,
+            length_xor: u16::from_be_bytes([rest[1], rest[2]]),
+            payload: &value[prefix_size..],
+        })
+    }
+
+    /// Parses a manifest.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a part of a wide manifest (the
+    /// wide file ID bit set) doesn't hold at least one file ID after the
+    /// total, or ends partway through one.
+    pub fn parse_manifest<'a>(&self, value: &'a [u8]) -> Result<ManifestRef<'a>, PacketParseError> {
+        if file_id_extra(value) == 0 {
+            return Ok(ManifestRef {
+                ids: value.get(1..).unwrap_or_default(),
+                total: None,
+            });
+        }
+        let Some(ids) = value
+            .get(WIDE_MANIFEST_PREFIX_SIZE..)
+            .filter(|ids| !ids.is_empty())
+        else {
+            return Err(PacketParseError::TooShort);
+        };
+        if ids.len() % 4 != 0 {
+            return Err(PacketParseError::PartialFileId { len: value.len() });
+        }
+        Ok(ManifestRef {
+            ids,
+            total: Some(read_number(&value[1..WIDE_MANIFEST_PREFIX_SIZE])),
+        })
+    }
+}
+
+// The longest packet allowed with this status byte, before any checksum.
+// Bits whose capabilities weren't negotiated don't count; with every bit set
+// this is the longest packet of any kind.
+fn max_packet_size(status: u8, capabilities: Capabilities) -> usize {
+    let has = |bit, capability| status & bit != 0 && capabilities.contains(capability);
+    let mut max = match (
+        has(PARITY_BIT, Capabilities::PARITY),
+        has(WIDE_BIT, Capabilities::LARGE_FILES),
+    ) {
+        (false, false) => MAX_PACKET_SIZE,
+        (false, true) => MAX_WIDE_PACKET_SIZE,
+        (true, false) => MAX_PARITY_PACKET_SIZE,
+        (true, true) => MAX_WIDE_PARITY_PACKET_SIZE,
+    };
+    if has(METADATA_BIT, Capabilities::EXTENDED_HEADER) {
+        max = max.max(MAX_METADATA_HEADER_SIZE);
+    }
+    if has(WIDE_FILE_ID_BIT, Capabilities::WIDE_FILE_IDS) {
+        max += WIDE_FILE_ID_EXTRA;
+    }
+    max
+}
+
+// how many more bytes than 1 the file ID after the status byte takes
+fn file_id_extra(value: &[u8]) -> usize {
+    if value
+        .first()
+        .is_some_and(|status| status & WIDE_FILE_ID_BIT != 0)
+    {
+        WIDE_FILE_ID_EXTRA
+    } else {
+        0
+    }
+}
+
+// a big-endian number of up to 4 bytes: a file ID or a packet number
+fn read_number(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |number, &byte| number << 8 | u32::from(byte))
+}
+
+// checks and removes the trailing checksum; see append_checksum
+fn strip_checksum(datagram: &[u8], max_len: usize) -> Result<&[u8], PacketParseError> {
+    if datagram.len() > max_len {
+        return Err(PacketParseError::Oversized {
+            len: datagram.len(),
+            max: max_len,
+        });
+    }
+    let Some((packet, trailer)) = datagram.split_last_chunk::<CHECKSUM_SIZE>() else {
+        return Err(PacketParseError::TooShort);
+    };
+    let expected = u16::from_be_bytes(*trailer);
+    let actual = internet_checksum(&[packet]);
+    if expected != actual {
+        return Err(PacketParseError::BadChecksum { expected, actual });
+    }
+    Ok(packet)
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)] // fallible on other platforms
+fn decode_file_name(
+    bytes: &[u8],
+    _offset: usize,
+    _fallback: FileNameFallback,
+) -> Result<Cow<'_, OsStr>, PacketParseError> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(Cow::Borrowed(OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+fn decode_file_name(
+    bytes: &[u8],
+    offset: usize,
+    fallback: FileNameFallback,
+) -> Result<Cow<'_, OsStr>, PacketParseError> {
+    let invalid_at = match std::str::from_utf8(bytes) {
+        Ok(name) => return Ok(Cow::Borrowed(OsStr::new(name))),
+        Err(e) => e.valid_up_to(),
+    };
+    match fallback {
+        FileNameFallback::Lossy => Ok(Cow::Owned(
+            String::from_utf8_lossy(bytes).into_owned().into(),
+        )),
+        FileNameFallback::PercentEscape => Ok(Cow::Owned(percent_escape(bytes).into())),
+        FileNameFallback::Reject => Err(PacketParseError::InvalidEncoding {
+            offset: offset + invalid_at,
+        }),
+    }
+}
+
+/// Turns arbitrary bytes into a UTF-8 string, writing every byte that isn't
+/// part of valid UTF-8 (and every `%`, so the result is unambiguous) as `%XX`.
+#[must_use]
+pub fn percent_escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c == '%' {
+                escaped.push_str("%25");
+            } else {
+                escaped.push(c);
+            }
+        }
+        for byte in chunk.invalid() {
+            let _ = write!(escaped, "%{byte:02X}");
+        }
+    }
+    escaped
+}
+
+// TryFrom implementation for PacketRef (Top level packet type)
+impl<'a> TryFrom<&'a [u8]> for PacketRef<'a> {
+    type Error = PacketParseError;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        PacketParser::default().parse(value)
+    }
+}
+
+// TryFrom implementation for HeaderRef
+impl<'a> TryFrom<&'a [u8]> for HeaderRef<'a> {
+    type Error = PacketParseError;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        PacketParser::default().parse_header(value)
+    }
+}
+
+// TryFrom implementation for DataRef
+impl<'a> TryFrom<&'a [u8]> for DataRef<'a> {
+    type Error = PacketParseError;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        PacketParser::default().parse_data(value)
+    }
+}
+
+// Owned conversions - these are the only places a parsed packet allocates
+impl From<PacketRef<'_>> for Packet {
+    fn from(packet: PacketRef<'_>) -> Self {
+        match packet {
+            PacketRef::Header(header) => Packet::Header(header.into()),
+            PacketRef::Data(data) => Packet::Data(data.into()),
+            PacketRef::Manifest(manifest) => Packet::Manifest(Manifest {
+                file_ids: manifest.file_ids().collect(),
+                total: manifest.total,
+            }),
+            PacketRef::Parity(parity) => Packet::Parity(parity.into()),
+        }
+    }
+}
+
+impl From<HeaderRef<'_>> for Header {
+    fn from(header: HeaderRef<'_>) -> Self {
+        //let expected_packet_count = u16::from_be_bytes([value[0], value[1]]) as usize;
+        let expected_packet_count = 0;
+
+        Header {
+            file_id: header.file_id,
+            file_name: header.file_name.into_owned(),
+            expected_packet_count,
+            metadata: header.metadata,
+        }
+    }
+}
+
+impl From<DataRef<'_>> for Data {
+    fn from(data: DataRef<'_>) -> Self {
+        Data {
+            file_id: data.file_id,
+            packet_number: data.packet_number,
+            is_last_packet: data.is_last_packet,
+            payload: data.payload.to_vec(),
+        }
+    }
+}
+
+impl From<ParityRef<'_>> for Parity {
+    fn from(parity: ParityRef<'_>) -> Self {
+        Parity {
+            file_id: parity.file_id,
+            first_packet_number: parity.first_packet_number,
+            count: parity.count,
+            is_last_group: parity.is_last_group,
+            length_xor: parity.length_xor,
+            payload: parity.payload.to_vec(),
+        }
+    }
+}
+
+// TryFrom implementation for Packet (Top level packet type)
+impl TryFrom<&[u8]> for Packet {
+    type Error = PacketParseError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        PacketRef::try_from(value).map(Packet::from)
+    }
+}
+
+// TryFrom implementation for Header
+impl TryFrom<&[u8]> for Header {
+    type Error = PacketParseError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        HeaderRef::try_from(value).map(Header::from)
+    }
+}
+
+// TryFrom implementation for Data packet
+impl TryFrom<&[u8]> for Data {
+    type Error = PacketParseError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        DataRef::try_from(value).map(Data::from)
+    }
 }
@@ -1,95 +1,771 @@
-use std::{collections::HashMap, convert::TryFrom, ffi::OsString, fs::File, io::Write};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    convert::TryFrom,
+    ffi::OsString,
+    fmt,
+    fs::File,
+    io::Write,
+    ops::RangeInclusive,
+    path::Path,
+};
 
 use crate::errors::PacketGroupError;
-use crate::packet::{Data, Header, Packet};
+use crate::output::{self, StreamingOutput, TempFile, OUTPUT_DIR};
+use crate::packet::{
+    xor_into, Ack, Data, FileId, FileMetadata, Header, Packet, PacketRef, Parity,
+    MAX_ACK_BITMAP_SIZE, MAX_PAYLOAD_SIZE,
+};
 
 // PacketGroup contains a file_name, expected packet count, and a map of packets
 #[derive(Default)]
 pub struct PacketGroup {
     pub file_name: Option<OsString>,
     pub expected_packet_count: Option<usize>,
-    pub packets: HashMap<u16, Vec<u8>>,
+    // from the header, with the extended header capability: the size the
+    // chunks must add up to, and the time and permissions the written file
+    // gets
+    pub metadata: Option<FileMetadata>,
+    pub packets: HashMap<u32, Vec<u8>>,
+    // how many of `packets` are numbered below the expected count (all of
+    // them until the last packet arrives), kept up to date as packets are
+    // processed so checking for completion doesn't scan the keys
+    pub received_in_range: usize,
+    // reject data packets that break the protocol's chunk rules instead of
+    // storing them: every chunk but the last is exactly 1024 bytes, and
+    // nothing comes after the last packet
+    pub strict_chunks: bool,
+    // write each chunk straight to its offset in a temporary file instead of
+    // keeping it in `packets` (which then only holds empty placeholders).
+    // Implies the strict chunk rules, since the offsets depend on them.
+    pub streaming: bool,
+    // the temporary file, created when the first data packet arrives
+    pub stream: Option<StreamingOutput>,
+    // parity packets whose group still misses more than one chunk, keyed by
+    // the group's first packet number. Once all but one chunk of a group
+    // are in, the missing one is rebuilt and the parity packet dropped.
+    pub parity: BTreeMap<u32, Parity>,
+    // how many chunks were rebuilt from parity packets
+    pub recovered: usize,
+    // the highest packet number that has arrived
+    pub highest_packet_number: Option<u32>,
+    // packet numbers jumped over when a packet arrived past the highest one
+    // so far, i.e. packets lost (or reordered) on the way; filling them in
+    // later doesn't take them back off
+    pub skipped: usize,
 }
 
 // Implementation for processing packets and writing files
 impl PacketGroup {
-    // process packet and update the state of the PacketGroup
-    pub fn process_packet(&mut self, packet: Packet) {
+    /// Processes a packet and updates the state of the `PacketGroup`.
+    ///
+    /// # Errors
+    ///
+    /// With `strict_chunks` set, this function will return an error (and
+    /// drop the packet) if a data packet breaks the chunk rules; see
+    /// `check_chunk`. Otherwise it never fails.
+    pub fn process_packet(&mut self, packet: Packet) -> Result<(), PacketGroupError> {
         match packet {
             Packet::Header(header) => {
                 self.process_header(header);
+                Ok(())
+            }
+            Packet::Data(data) => self.process_data(data),
+            Packet::Parity(parity) => self.process_parity(parity),
+            // manifests are about the whole session; see FileManager
+            Packet::Manifest(_) => Ok(()),
+        }
+    }
+
+    /// Same as `process_packet`, but for a borrowed packet - the payload is
+    /// only copied out of the receive buffer if this packet number is new.
+    ///
+    /// # Errors
+    ///
+    /// The same as `process_packet`.
+    pub fn process_packet_ref(&mut self, packet: PacketRef<'_>) -> Result<(), PacketGroupError> {
+        match packet {
+            PacketRef::Header(header) => {
+                self.file_name = Some(header.file_name.into_owned());
+                self.metadata = header.metadata;
             }
-            Packet::Data(data) => {
-                self.process_data(data);
+            PacketRef::Data(data) => {
+                self.check_chunk(data.packet_number, data.is_last_packet, data.payload.len())?;
+                if self.streaming {
+                    self.stream_chunk(
+                        data.file_id,
+                        data.packet_number,
+                        data.is_last_packet,
+                        data.payload,
+                    )?;
+                } else if let Entry::Vacant(entry) = self.packets.entry(data.packet_number) {
+                    entry.insert(data.payload.to_vec());
+                    self.count_new_packet(data.packet_number);
+                }
+                self.record_last_packet(data.packet_number, data.is_last_packet);
+                self.record_arrival(data.packet_number);
+                self.recover_around(data.file_id, data.packet_number)?;
             }
+            PacketRef::Parity(parity) => self.process_parity(parity.into())?,
+            PacketRef::Manifest(_) => {}
         }
+        Ok(())
     }
 
-    // sets the file name for the PacketGroup
+    // sets the file name (and metadata, if any) for the PacketGroup
     fn process_header(&mut self, header: Header) {
         self.file_name = Some(header.file_name);
+        self.metadata = header.metadata;
     }
 
     // inserts the data into the packets map and updates the expected packet count
-    fn process_data(&mut self, data: Data) {
-        self.packets.insert(data.packet_number, data.payload);
-        if data.is_last_packet {
-            self.expected_packet_count = Some((data.packet_number + 1) as usize);
+    fn process_data(&mut self, data: Data) -> Result<(), PacketGroupError> {
+        self.check_chunk(data.packet_number, data.is_last_packet, data.payload.len())?;
+        if self.streaming {
+            self.stream_chunk(
+                data.file_id,
+                data.packet_number,
+                data.is_last_packet,
+                &data.payload,
+            )?;
+        } else if let Entry::Vacant(entry) = self.packets.entry(data.packet_number) {
+            entry.insert(data.payload);
+            self.count_new_packet(data.packet_number);
+        }
+        self.record_last_packet(data.packet_number, data.is_last_packet);
+        self.record_arrival(data.packet_number);
+        self.recover_around(data.file_id, data.packet_number)
+    }
+
+    // keeps a parity packet until its group is down to one missing chunk
+    fn process_parity(&mut self, parity: Parity) -> Result<(), PacketGroupError> {
+        let (file_id, first) = (parity.file_id, parity.first_packet_number);
+        self.parity.entry(first).or_insert(parity);
+        self.recover(file_id, first)
+    }
+
+    // a new chunk may leave the group it belongs to one chunk short
+    fn recover_around(
+        &mut self,
+        file_id: FileId,
+        packet_number: u32,
+    ) -> Result<(), PacketGroupError> {
+        let group = self
+            .parity
+            .range(..=packet_number)
+            .next_back()
+            .filter(|(_, parity)| parity.packet_numbers().contains(&packet_number))
+            .map(|(&first, _)| first);
+        match group {
+            Some(first) => self.recover(file_id, first),
+            None => Ok(()),
+        }
+    }
+
+    // Rebuilds the one missing chunk of the group starting at `first`, if
+    // exactly one is missing. A parity packet that doesn't add up (a chunk
+    // longer than its payload, say) is dropped rather than trusted.
+    fn recover(&mut self, file_id: FileId, first: u32) -> Result<(), PacketGroupError> {
+        let Some(parity) = self.parity.get(&first) else {
+            return Ok(());
+        };
+        let members = parity.packet_numbers();
+        let missing: Vec<u32> = members
+            .clone()
+            .filter(|packet_number| !self.packets.contains_key(packet_number))
+            .take(2)
+            .collect();
+        let lost = match missing[..] {
+            // every chunk is in, so there's nothing left to recover
+            [] => {
+                self.parity.remove(&first);
+                return Ok(());
+            }
+            [lost] => lost,
+            _ => return Ok(()),
+        };
+
+        let Some(parity) = self.parity.remove(&first) else {
+            return Ok(());
+        };
+        let mut payload = parity.payload;
+        let mut len = usize::from(parity.length_xor);
+        for packet_number in members.clone().filter(|&n| n != lost) {
+            let chunk = self.chunk(packet_number)?;
+            if chunk.len() > payload.len() {
+                return Ok(());
+            }
+            xor_into(&mut payload, &chunk);
+            len ^= chunk.len();
+        }
+        if len > payload.len() {
+            return Ok(());
+        }
+        payload.truncate(len);
+
+        self.process_data(Data {
+            file_id,
+            packet_number: lost,
+            is_last_packet: parity.is_last_group && lost == *members.end(),
+            payload,
+        })?;
+        self.recovered += 1;
+        Ok(())
+    }
+
+    // a chunk that has arrived, read back from disk when streaming
+    fn chunk(&self, packet_number: u32) -> Result<Vec<u8>, PacketGroupError> {
+        match &self.stream {
+            Some(stream) => {
+                let len = if self.last_packet_number() == Some(packet_number) {
+                    stream.last_chunk_len().unwrap_or(0)
+                } else {
+                    MAX_PAYLOAD_SIZE
+                };
+                Ok(stream.read_chunk(packet_number, len)?)
+            }
+            None => Ok(self
+                .packets
+                .get(&packet_number)
+                .cloned()
+                .unwrap_or_default()),
+        }
+    }
+
+    // writes a new chunk to the temporary file and leaves an empty placeholder
+    // in `packets` so the completeness checks still work
+    fn stream_chunk(
+        &mut self,
+        file_id: FileId,
+        packet_number: u32,
+        is_last_packet: bool,
+        chunk: &[u8],
+    ) -> Result<(), PacketGroupError> {
+        if self.packets.contains_key(&packet_number) {
+            return Ok(());
+        }
+
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => self
+                .stream
+                .insert(
+                    StreamingOutput::create(OUTPUT_DIR, file_id).map_err(|source| {
+                        PacketGroupError::Write {
+                            path: OUTPUT_DIR.into(),
+                            offset: 0,
+                            source,
+                        }
+                    })?,
+                ),
+        };
+        stream
+            .write_chunk(packet_number, chunk)
+            .and_then(|()| {
+                if is_last_packet {
+                    stream.set_last_chunk(packet_number, chunk.len())
+                } else {
+                    Ok(())
+                }
+            })
+            .map_err(|source| PacketGroupError::Write {
+                path: stream.temp_path().to_path_buf(),
+                offset: u64::from(packet_number) * MAX_PAYLOAD_SIZE as u64,
+                source,
+            })?;
+
+        self.packets.insert(packet_number, Vec::new());
+        self.count_new_packet(packet_number);
+        Ok(())
+    }
+
+    fn count_new_packet(&mut self, packet_number: u32) {
+        if self
+            .expected_packet_count
+            .is_none_or(|expected_count| as_index(packet_number) < expected_count)
+        {
+            self.received_in_range += 1;
+        }
+    }
+
+    fn record_arrival(&mut self, packet_number: u32) {
+        match self.highest_packet_number {
+            Some(highest) if packet_number <= highest => {}
+            highest => {
+                let next = highest.map_or(0, |highest| highest + 1);
+                self.skipped += as_index(packet_number - next);
+                self.highest_packet_number = Some(packet_number);
+            }
+        }
+    }
+
+    fn record_last_packet(&mut self, packet_number: u32, is_last_packet: bool) {
+        let expected_count = as_index(packet_number) + 1;
+        if is_last_packet && self.expected_packet_count != Some(expected_count) {
+            self.expected_packet_count = Some(expected_count);
+            // only when the last packet changes, which it doesn't unless
+            // the server contradicts itself
+            self.received_in_range = self
+                .packets
+                .keys()
+                .filter(|&&n| as_index(n) < expected_count)
+                .count();
+        }
+    }
+
+    /// Checks a data packet against the chunk rules before it is stored. Does
+    /// nothing unless `strict_chunks` or `streaming` is set.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the payload is longer than 1024
+    /// bytes, a packet other than the last is shorter than that, the packet
+    /// comes after the last packet, or it is marked last when another packet
+    /// already is (or a higher packet number has already arrived).
+    pub fn check_chunk(
+        &self,
+        packet_number: u32,
+        is_last_packet: bool,
+        len: usize,
+    ) -> Result<(), PacketGroupError> {
+        if !self.strict_chunks && !self.streaming {
+            return Ok(());
         }
+        if len > MAX_PAYLOAD_SIZE {
+            return Err(PacketGroupError::OversizedChunk { packet_number, len });
+        }
+        if !is_last_packet && len < MAX_PAYLOAD_SIZE {
+            return Err(PacketGroupError::ShortChunk { packet_number, len });
+        }
+
+        match self.last_packet_number() {
+            Some(last) if is_last_packet && packet_number != last => {
+                Err(PacketGroupError::ConflictingLastPacket {
+                    packet_number,
+                    conflicts_with: last,
+                })
+            }
+            Some(last) if packet_number > last => Err(PacketGroupError::PastLastPacket {
+                packet_number,
+                last,
+            }),
+            // only looked for once, when the last packet first arrives
+            None if is_last_packet => match self.packets.keys().max() {
+                Some(&highest) if highest > packet_number => {
+                    Err(PacketGroupError::ConflictingLastPacket {
+                        packet_number,
+                        conflicts_with: highest,
+                    })
+                }
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn last_packet_number(&self) -> Option<u32> {
+        self.expected_packet_count
+            .and_then(|count| count.checked_sub(1))
+            .and_then(|last| u32::try_from(last).ok())
+    }
+
+    // The size the file will be, known as soon as the last packet arrives in
+    // strict mode since every chunk before it is exactly 1024 bytes. Always
+    // None without `strict_chunks` or `streaming`, where chunks can be any
+    // size.
+    #[must_use]
+    pub fn exact_file_size(&self) -> Option<u64> {
+        let last = self.last_packet_number()?;
+        let last_len = if self.streaming {
+            self.stream.as_ref()?.last_chunk_len()?
+        } else if self.strict_chunks {
+            self.packets.get(&last)?.len()
+        } else {
+            return None;
+        };
+        Some(u64::from(last) * MAX_PAYLOAD_SIZE as u64 + last_len as u64)
     }
 
-    // Checks if all packets are received for a SINGLE file
+    // The size the chunks 0..expected add up to, once they've all arrived
+    fn received_size(&self) -> Option<u64> {
+        let expected_count = self.expected_packet_count?;
+        if !self.all_packets_received() {
+            return None;
+        }
+        if self.stream.is_some() {
+            return self.exact_file_size();
+        }
+        Some(
+            self.packets
+                .iter()
+                .filter(|(&packet_number, _)| as_index(packet_number) < expected_count)
+                .map(|(_, data)| data.len() as u64)
+                .sum(),
+        )
+    }
+
+    // Checks if all packets are received for a SINGLE file: every packet
+    // number in 0..expected. Stray numbers past the last packet can't stand
+    // in for missing ones, and don't hold the file up either (validate
+    // reports them)
     #[must_use] // inserted to appease the all powerful clippy
     pub fn all_packets_received(&self) -> bool {
-        match self.expected_packet_count {
-            Some(expected_count) => self.packets.len() == expected_count,
-            None => false,
+        // the numbers are distinct, so that many in range means all of them
+        self.expected_packet_count == Some(self.received_in_range)
+    }
+
+    // What has arrived so far, as an ack for the server (see packet::Ack).
+    // `has_manifest` is about the whole session, so the FileManager fills it in.
+    #[must_use]
+    pub fn ack(&self, file_id: FileId) -> Ack {
+        let received = |n: u32| self.packets.contains_key(&n);
+
+        let mut cumulative = 0;
+        while received(cumulative) {
+            cumulative += 1;
         }
+
+        let highest = self.packets.keys().copied().max().unwrap_or(0);
+        let mut selective = Vec::new();
+        for (bit, packet_number) in (cumulative + 1..=highest)
+            .take(MAX_ACK_BITMAP_SIZE * 8)
+            .enumerate()
+        {
+            if bit % 8 == 0 {
+                selective.push(0);
+            }
+            if received(packet_number) {
+                if let Some(byte) = selective.last_mut() {
+                    *byte |= 1 << (bit % 8);
+                }
+            }
+        }
+
+        Ack {
+            file_id,
+            has_header: self.file_name.is_some(),
+            has_manifest: false,
+            cumulative,
+            selective,
+        }
+    }
+
+    // Checks everything a complete file should satisfy and lists every way
+    // this one doesn't; see ReassemblyReport
+    #[must_use]
+    pub fn validate(&self) -> ReassemblyReport {
+        let mut report = ReassemblyReport {
+            missing_file_name: self.file_name.is_none(),
+            missing_last_packet: self.expected_packet_count.is_none(),
+            ..ReassemblyReport::default()
+        };
+
+        let mut packet_numbers: Vec<u32> = self.packets.keys().copied().collect();
+        packet_numbers.sort_unstable();
+
+        if let Some(expected_count) = self.expected_packet_count {
+            // the gaps between the numbers that arrived, so a stray last
+            // packet numbered in the billions costs nothing extra
+            let in_range = packet_numbers.partition_point(|&n| as_index(n) < expected_count);
+            // the lowest number not yet accounted for; only reaches
+            // u32::MAX + 1 once every number up to u32::MAX has arrived
+            let mut next = 0;
+            for &packet_number in &packet_numbers[..in_range] {
+                if u64::from(packet_number) > next {
+                    report
+                        .missing_packets
+                        .push(as_packet_number(next)..=packet_number - 1);
+                }
+                next = u64::from(packet_number) + 1;
+            }
+            if next < expected_count as u64 {
+                report
+                    .missing_packets
+                    .push(as_packet_number(next)..=as_packet_number(expected_count as u64 - 1));
+            }
+            report.unexpected_packets = packet_numbers[in_range..].to_vec();
+        }
+
+        if let (Some(metadata), Some(received)) = (self.metadata, self.received_size()) {
+            if metadata.size != received {
+                report.wrong_size = Some((metadata.size, received));
+            }
+        }
+
+        // streamed chunks were size checked on arrival and aren't kept
+        if self.streaming {
+            return report;
+        }
+
+        let last_packet = self
+            .expected_packet_count
+            .and_then(|count| count.checked_sub(1));
+        for &packet_number in &packet_numbers {
+            let len = self.packets[&packet_number].len();
+            let is_last = last_packet == Some(as_index(packet_number));
+            if len > MAX_PAYLOAD_SIZE || (!is_last && len < MAX_PAYLOAD_SIZE) {
+                report.bad_chunk_sizes.push((packet_number, len));
+            }
+        }
+
+        report
     }
 
     /// Writes the file represented by this `PacketGroup` to the `src` directory.
+    /// A file name with directories in it goes in those directories, which
+    /// are created as needed (see `output::relative_path`). The file is
+    /// written to a temporary file and renamed into place (see
+    /// `output::TempFile`); when streaming, the chunks are already in one and
+    /// it is just trimmed.
     ///
     /// # Errors
     ///
     /// This function will return an error if:
     /// - The file name is missing (`PacketGroupError::MissingFileName`).
+    /// - The file name would put the file outside `src`
+    ///   (`PacketGroupError::UnsafePath`).
     /// - The expected packet count is not set (`PacketGroupError::MissingPacketCount`).
     /// - A packet is missing (`PacketGroupError::MissingPacket`).
-    /// - There is an I/O error while creating or writing to the file (`PacketGroupError::IoError`).
+    /// - The last packet number doesn't fit in 32 bits (`PacketGroupError::TooManyPackets`).
+    /// - There is an I/O error while creating or writing to the file (`PacketGroupError::Write`).
+    /// - The chunks don't add up to the size the header's metadata gave
+    ///   (`PacketGroupError::WrongSize`); nothing is written then.
+    /// - In strict mode, the bytes written don't add up to `exact_file_size`
+    ///   (`PacketGroupError::SizeMismatch`); the file isn't put in place then.
+    /// - The modification time or permissions from the header's metadata
+    ///   can't be set (`PacketGroupError::Metadata`); the file is written
+    ///   anyway.
     pub fn write_file(&self) -> Result<(), PacketGroupError> {
         let file_name = self
             .file_name
             .as_ref()
             .ok_or(PacketGroupError::MissingFileName)?;
 
-        // Write to src directory - appeasing the bats test gods
-        let file_path = format!("src/{}", file_name.to_string_lossy());
+        let relative_path = output::relative_path(file_name)
+            .ok_or_else(|| PacketGroupError::UnsafePath(file_name.clone()))?;
+        let file_path = Path::new(OUTPUT_DIR).join(&relative_path);
+
+        // If expected packet count is not set, we cannot check for missing packets
+        let expected_count = self
+            .expected_packet_count
+            .ok_or(PacketGroupError::MissingPacketCount)?;
+        let last = expected_count
+            .checked_sub(1)
+            .map(|last| {
+                u32::try_from(last).map_err(|_| PacketGroupError::TooManyPackets(expected_count))
+            })
+            .transpose()?;
+        let packet_numbers = || last.into_iter().flat_map(|last| 0..=last);
 
         // Check if all expected packets are present
-        if let Some(expected_count) = self.expected_packet_count {
-            let expected_count_u16 =
-                u16::try_from(expected_count).map_err(|_| PacketGroupError::MissingPacketCount)?;
+        for packet_number in packet_numbers() {
+            if !self.packets.contains_key(&packet_number) {
+                return Err(PacketGroupError::MissingPacket(packet_number));
+            }
+        }
 
-            for packet_number in 0..expected_count_u16 {
-                if !self.packets.contains_key(&packet_number) {
-                    return Err(PacketGroupError::MissingPacket(packet_number));
-                }
+        if let (Some(metadata), Some(received)) = (self.metadata, self.received_size()) {
+            if metadata.size != received {
+                return Err(PacketGroupError::WrongSize {
+                    expected: metadata.size,
+                    received,
+                });
             }
-        } else {
-            // If expected packet count is not set, we cannot check for missing packets
-            return Err(PacketGroupError::MissingPacketCount);
         }
 
-        let mut file = File::create(file_path)?;
-        let mut packet_count: Vec<u16> = self.packets.keys().copied().collect(); // clippy wanted copied instead of cloned
-        packet_count.sort_unstable(); // clippy wanted unstable sort
+        let write_error = |offset, source| PacketGroupError::Write {
+            path: file_path.clone(),
+            offset,
+            source,
+        };
+        output::create_parent_dirs(Path::new(OUTPUT_DIR), &relative_path)
+            .map_err(|e| write_error(0, e))?;
+
+        // either way the file is finished in a TempFile and renamed into
+        // place, so nothing already at the name is written through
+        let written;
+        let temp = if let Some(stream) = &self.stream {
+            // the last chunk has arrived, so the size is known
+            let len = self
+                .exact_file_size()
+                .ok_or(PacketGroupError::MissingPacketCount)?;
+            stream.trim(len).map_err(|e| write_error(0, e))?;
+            stream.temp()
+        } else {
+            written = TempFile::create(OUTPUT_DIR, "write").map_err(|e| write_error(0, e))?;
+            self.write_packets(written.file(), &file_path, last)?;
+            &written
+        };
+
+        // a file whose time or permissions couldn't be set is still written
+        let restored = match &self.metadata {
+            Some(metadata) => output::restore_metadata(temp.file(), metadata).map_err(|source| {
+                PacketGroupError::Metadata {
+                    path: file_path.clone(),
+                    source,
+                }
+            }),
+            None => Ok(()),
+        };
+        temp.persist(&file_path).map_err(|e| write_error(0, e))?;
+        restored
+    }
 
-        // For each packet number, write the data to the file
-        for packet_number in packet_count {
+    // Writes packets 0..=last to `file` in order; anything numbered past the
+    // last packet isn't part of the file. Errors name `file_path`, where the
+    // file is going.
+    fn write_packets(
+        &self,
+        mut file: &File,
+        file_path: &Path,
+        last: Option<u32>,
+    ) -> Result<(), PacketGroupError> {
+        let write_error = |offset, source| PacketGroupError::Write {
+            path: file_path.to_path_buf(),
+            offset,
+            source,
+        };
+        let packet_numbers = || last.into_iter().flat_map(|last| 0..=last);
+        let total: u64 = packet_numbers()
+            .filter_map(|packet_number| self.packets.get(&packet_number))
+            .map(|data| data.len() as u64)
+            .sum();
+        output::preallocate(file, total).map_err(|e| write_error(0, e))?;
+
+        let mut offset = 0;
+        for packet_number in packet_numbers() {
             if let Some(data) = self.packets.get(&packet_number) {
-                file.write_all(data)?;
+                file.write_all(data).map_err(|e| write_error(offset, e))?;
+                offset += data.len() as u64;
+            }
+        }
+
+        // in strict mode the size is known up front, so a short file means
+        // something was lost between receiving and writing
+        if let Some(expected) = self.exact_file_size() {
+            if offset != expected {
+                return Err(PacketGroupError::SizeMismatch {
+                    path: file_path.to_path_buf(),
+                    expected,
+                    written: offset,
+                });
             }
         }
         Ok(())
     }
 }
+
+// ReassemblyReport lists every way a PacketGroup falls short of a complete,
+// well-formed file. Packet numbers are sorted.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReassemblyReport {
+    pub missing_file_name: bool,
+    // no packet marked last has arrived, so the packet count is unknown
+    pub missing_last_packet: bool,
+    // runs of numbers in 0..expected that haven't arrived
+    pub missing_packets: Vec<RangeInclusive<u32>>,
+    // numbers at or past the expected count, which can't belong to the file
+    pub unexpected_packets: Vec<u32>,
+    // (packet number, length) of chunks that aren't exactly 1024 bytes
+    // (or, for the last chunk, are longer than that)
+    pub bad_chunk_sizes: Vec<(u32, usize)>,
+    // (size from the header, size the chunks add up to) when they differ
+    pub wrong_size: Option<(u64, u64)>,
+}
+
+impl ReassemblyReport {
+    // true if the file has its name and every packet in 0..expected; packets
+    // past the last one are left out of the file, so they don't count
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        !self.missing_file_name && !self.missing_last_packet && self.missing_packets.is_empty()
+    }
+
+    // true if the file is complete, nothing arrived past its last packet,
+    // and every chunk (and the file, if the header said how big it is) has
+    // the right size
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.is_complete()
+            && self.unexpected_packets.is_empty()
+            && self.bad_chunk_sizes.is_empty()
+            && self.wrong_size.is_none()
+    }
+}
+
+impl fmt::Display for ReassemblyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut problems = Vec::new();
+        if self.missing_file_name {
+            problems.push("no header packet".to_string());
+        }
+        if self.missing_last_packet {
+            problems.push("no last packet".to_string());
+        }
+        if !self.missing_packets.is_empty() {
+            problems.push(format!(
+                "missing packets {}",
+                format_ranges(&self.missing_packets)
+            ));
+        }
+        if !self.unexpected_packets.is_empty() {
+            problems.push(format!(
+                "packets past the last one {}",
+                packet_ranges(&self.unexpected_packets)
+            ));
+        }
+        for (packet_number, len) in &self.bad_chunk_sizes {
+            problems.push(format!("packet {packet_number} has {len} bytes"));
+        }
+        if let Some((expected, received)) = self.wrong_size {
+            problems.push(format!(
+                "the header says {expected} bytes, the chunks add up to {received}"
+            ));
+        }
+
+        if problems.is_empty() {
+            write!(f, "complete")
+        } else {
+            write!(f, "{}", problems.join("; "))
+        }
+    }
+}
+
+// a packet number as a count or index; a u32 fits in a usize on every
+// platform this runs on
+fn as_index(packet_number: u32) -> usize {
+    usize::try_from(packet_number).unwrap_or(usize::MAX)
+}
+
+// the inverse of as_index, for counts that are known to be at most
+// u32::MAX + 1 and so leave every index below them in range
+fn as_packet_number(index: u64) -> u32 {
+    u32::try_from(index).unwrap_or(u32::MAX)
+}
+
+// Formats sorted packet numbers compactly, e.g. "0-3, 7, 9-10"
+fn packet_ranges(packet_numbers: &[u32]) -> String {
+    let mut ranges: Vec<RangeInclusive<u32>> = Vec::new();
+    for &n in packet_numbers {
+        match ranges.last_mut() {
+            Some(range) if u64::from(*range.end()) + 1 == u64::from(n) => {
+                *range = *range.start()..=n;
+            }
+            _ => ranges.push(n..=n),
+        }
+    }
+    format_ranges(&ranges)
+}
+
+fn format_ranges(ranges: &[RangeInclusive<u32>]) -> String {
+    ranges
+        .iter()
+        .map(|range| {
+            if range.start() == range.end() {
+                range.start().to_string()
+            } else {
+                format!("{}-{}", range.start(), range.end())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
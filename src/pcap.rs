@@ -0,0 +1,475 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{capture::CapturedDatagram, config::LOCAL_PORT};
+
+// The port the server sends from unless told otherwise
+pub const SERVER_PORT: u16 = 6014;
+
+// Link types we can read (see https://www.tcpdump.org/linktypes.html).
+// We only ever write Ethernet.
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const SNAPLEN: u32 = 65535;
+// the largest snapshot length tcpdump will use, anything bigger is corrupt
+const MAX_RECORD_LEN: u32 = 262_144;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+const IPPROTO_UDP: u8 = 17;
+
+// locally administered MAC addresses for the synthetic Ethernet header
+const SERVER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+const CLIENT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+
+// PcapWriter records datagrams from the server as a classic (microsecond,
+// little-endian) pcap file. Every datagram is wrapped in made-up Ethernet,
+// IPv4 and UDP headers, server to client, so Wireshark shows the 6014 -> 7077
+// conversation as if it had been sniffed off the wire.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    server: SocketAddrV4,
+    client: SocketAddrV4,
+    // wall clock time of the first record, pcap timestamps are absolute
+    start_time: Duration,
+    started: Instant,
+    // IPv4 identification field, bumped per datagram like a real stack would
+    next_id: u16,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    /// Creates (or truncates) a pcap file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be created.
+    pub fn create(
+        path: impl AsRef<Path>,
+        server: SocketAddr,
+        client: SocketAddr,
+    ) -> io::Result<Self> {
+        PcapWriter::new(BufWriter::new(File::create(path)?), server, client)
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Starts a pcap on `writer`, writing the global header straight away.
+    /// IPv6 addresses are shown as IPv4 (mapped, or loopback if they can't
+    /// be), keeping their ports.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the header can't be written.
+    pub fn new(mut writer: W, server: SocketAddr, client: SocketAddr) -> io::Result<Self> {
+        writer.write_all(&MAGIC_MICROS.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?; // version 2.4
+        writer.write_all(&4u16.to_le_bytes())?;
+        writer.write_all(&0i32.to_le_bytes())?; // timezone offset
+        writer.write_all(&0u32.to_le_bytes())?; // timestamp accuracy
+        writer.write_all(&SNAPLEN.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+
+        Ok(PcapWriter {
+            writer,
+            server: as_v4(server),
+            client: as_v4(client),
+            start_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            started: Instant::now(),
+            next_id: 0,
+        })
+    }
+
+    /// Records a datagram received just now.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the record can't be written.
+    pub fn record(&mut self, datagram: &[u8]) -> io::Result<()> {
+        self.record_at(self.started.elapsed(), datagram)
+    }
+
+    /// Records a datagram at an explicit offset from the start of the capture.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the datagram doesn't fit in an
+    /// IPv4 packet or the record can't be written.
+    pub fn record_at(&mut self, offset: Duration, datagram: &[u8]) -> io::Result<()> {
+        let frame = udp_frame(self.server, self.client, self.next_id, datagram)?;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let timestamp = self.start_time + offset;
+        // classic pcap timestamps run out in 2106
+        let seconds = u32::try_from(timestamp.as_secs()).unwrap_or(u32::MAX);
+        // the frame is at most 65535 + 14 bytes, see udp_frame
+        let len = u32::try_from(frame.len()).unwrap_or(u32::MAX);
+
+        self.writer.write_all(&seconds.to_le_bytes())?;
+        self.writer
+            .write_all(&timestamp.subsec_micros().to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&frame)
+    }
+
+    /// Flushes buffered records to the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the flush fails.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    // gives back the underlying writer, without flushing it
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// The address a writer uses when there is no real conversation to copy,
+// for example when converting an existing capture
+#[must_use]
+pub fn default_server_addr() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, SERVER_PORT))
+}
+
+#[must_use]
+pub fn default_client_addr() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, LOCAL_PORT))
+}
+
+fn as_v4(addr: SocketAddr) -> SocketAddrV4 {
+    match addr {
+        SocketAddr::V4(v4) => v4,
+        SocketAddr::V6(v6) => SocketAddrV4::new(
+            v6.ip().to_ipv4_mapped().unwrap_or(Ipv4Addr::LOCALHOST),
+            v6.port(),
+        ),
+    }
+}
+
+// Builds an Ethernet frame holding one UDP datagram from `src` to `dst`
+fn udp_frame(src: SocketAddrV4, dst: SocketAddrV4, id: u16, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "datagram too large for IPv4");
+    let udp_len = u16::try_from(UDP_HEADER_LEN + payload.len()).map_err(|_| too_large())?;
+    let ip_len = u16::try_from(IPV4_HEADER_LEN + usize::from(udp_len)).map_err(|_| too_large())?;
+
+    let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + usize::from(ip_len));
+    frame.extend_from_slice(&CLIENT_MAC);
+    frame.extend_from_slice(&SERVER_MAC);
+    frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+
+    let ip_start = frame.len();
+    frame.extend_from_slice(&[0x45, 0]); // version 4, 20 byte header, no DSCP
+    frame.extend_from_slice(&ip_len.to_be_bytes());
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.extend_from_slice(&[0x40, 0]); // don't fragment
+    frame.extend_from_slice(&[64, IPPROTO_UDP]);
+    frame.extend_from_slice(&[0, 0]); // checksum, filled in below
+    frame.extend_from_slice(&src.ip().octets());
+    frame.extend_from_slice(&dst.ip().octets());
+    let checksum = internet_checksum(&[&frame[ip_start..]]);
+    frame[ip_start + 10..ip_start + 12].copy_from_slice(&checksum.to_be_bytes());
+
+    let udp_start = frame.len();
+    frame.extend_from_slice(&src.port().to_be_bytes());
+    frame.extend_from_slice(&dst.port().to_be_bytes());
+    frame.extend_from_slice(&udp_len.to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(payload);
+    let mut pseudo_header = [0; 12];
+    pseudo_header[..4].copy_from_slice(&src.ip().octets());
+    pseudo_header[4..8].copy_from_slice(&dst.ip().octets());
+    pseudo_header[9] = IPPROTO_UDP;
+    pseudo_header[10..].copy_from_slice(&udp_len.to_be_bytes());
+    let checksum = match internet_checksum(&[&pseudo_header, &frame[udp_start..]]) {
+        // zero means "no checksum" for UDP, so it is sent as all ones instead
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    frame[udp_start + 6..udp_start + 8].copy_from_slice(&checksum.to_be_bytes());
+
+    Ok(frame)
+}
+
+/// The RFC 1071 ones' complement checksum over `parts`, as if they were one
+/// buffer. Every part but the last must have an even length.
+#[must_use]
+pub fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for pair in part.chunks(2) {
+            let word = u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]);
+            sum += u32::from(word);
+        }
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    // the loop above leaves at most 16 bits
+    !u16::try_from(sum).unwrap_or(u16::MAX)
+}
+
+// One UDP datagram pulled out of a pcap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcapDatagram {
+    // capture time since the Unix epoch
+    pub timestamp: Duration,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+// PcapReader iterates over the UDP datagrams in a classic pcap file, such as
+// one written by `tcpdump -w`. Frames that aren't UDP over IPv4 or IPv6, IPv4
+// fragments, and datagrams cut short by the snapshot length are skipped.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    nanos: bool,
+    link_type: u32,
+}
+
+impl PcapReader<BufReader<File>> {
+    /// Opens the pcap file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be opened or
+    /// isn't a pcap file we can read.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        PcapReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Starts reading a pcap from `reader`, checking its global header.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an `InvalidData` error if the magic number
+    /// is wrong (pcapng isn't supported) or the link type is one we can't
+    /// decode.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 24];
+        reader.read_exact(&mut header)?;
+
+        let magic = [header[0], header[1], header[2], header[3]];
+        let (big_endian, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (MAGIC_MICROS, _) => (false, false),
+            (MAGIC_NANOS, _) => (false, true),
+            (_, MAGIC_MICROS) => (true, false),
+            (_, MAGIC_NANOS) => (true, true),
+            _ => return Err(invalid_data("not a pcap file")),
+        };
+
+        let mut pcap = PcapReader {
+            reader,
+            big_endian,
+            nanos,
+            link_type: 0,
+        };
+        // the top bits of the link type field hold FCS information
+        pcap.link_type = pcap.u32_at(&header, 20) & 0x0FFF_FFFF;
+        match pcap.link_type {
+            LINKTYPE_NULL | LINKTYPE_ETHERNET | LINKTYPE_RAW | LINKTYPE_LINUX_SLL
+            | LINKTYPE_LINUX_SLL2 => Ok(pcap),
+            other => Err(invalid_data(&format!("unsupported pcap link type {other}"))),
+        }
+    }
+
+    #[must_use]
+    pub fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    fn u32_at(&self, bytes: &[u8], at: usize) -> u32 {
+        let field = [bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]];
+        if self.big_endian {
+            u32::from_be_bytes(field)
+        } else {
+            u32::from_le_bytes(field)
+        }
+    }
+
+    // reads the next record, returning its timestamp and frame
+    fn read_record(&mut self) -> io::Result<Option<(Duration, Vec<u8>)>> {
+        let mut header = [0; 16];
+        // a clean end of file can only happen between records
+        if self.reader.read(&mut header[..1])? == 0 {
+            return Ok(None);
+        }
+        self.reader.read_exact(&mut header[1..])?;
+
+        let seconds = u64::from(self.u32_at(&header, 0));
+        let fraction = self.u32_at(&header, 4);
+        let subsec_nanos = if self.nanos {
+            fraction
+        } else {
+            fraction.saturating_mul(1000)
+        };
+        let captured_len = self.u32_at(&header, 8);
+        if captured_len > MAX_RECORD_LEN {
+            return Err(invalid_data("pcap record larger than any snapshot length"));
+        }
+
+        let mut frame = vec![0; captured_len as usize];
+        self.reader.read_exact(&mut frame)?;
+        Ok(Some((
+            Duration::from_secs(seconds) + Duration::from_nanos(u64::from(subsec_nanos)),
+            frame,
+        )))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<PcapDatagram>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (timestamp, frame) = match self.read_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some((source, destination, data)) = decode_frame(self.link_type, &frame) {
+                return Some(Ok(PcapDatagram {
+                    timestamp,
+                    source,
+                    destination,
+                    data: data.to_vec(),
+                }));
+            }
+        }
+    }
+}
+
+/// Picks out the datagrams sent from `server_port` (the server's side of the
+/// conversation, so not the client's hello), as capture datagrams timed from
+/// the first record that was kept.
+pub fn server_datagrams<R: Read>(
+    reader: PcapReader<R>,
+    server_port: u16,
+) -> impl Iterator<Item = io::Result<CapturedDatagram>> {
+    let mut first_timestamp = None;
+    reader.filter_map(move |datagram| match datagram {
+        Ok(datagram) if datagram.source.port() == server_port => {
+            let first = *first_timestamp.get_or_insert(datagram.timestamp);
+            Some(Ok(CapturedDatagram {
+                offset: datagram.timestamp.saturating_sub(first),
+                data: datagram.data,
+            }))
+        }
+        Ok(_) => None,
+        Err(e) => Some(Err(e)),
+    })
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn be16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*bytes.get(at)?, *bytes.get(at + 1)?]))
+}
+
+// Unwraps the link layer, then the IP layer, down to the UDP payload
+fn decode_frame(link_type: u32, frame: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (ethertype, packet) = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut at = 12;
+            let mut ethertype = be16(frame, at)?;
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                at += 4;
+                ethertype = be16(frame, at)?;
+            }
+            (Some(ethertype), frame.get(at + 2..)?)
+        }
+        LINKTYPE_LINUX_SLL => (Some(be16(frame, 14)?), frame.get(16..)?),
+        LINKTYPE_LINUX_SLL2 => (Some(be16(frame, 0)?), frame.get(20..)?),
+        // the null header's address family is in the capturing host's byte
+        // order and differs between BSDs, so go by the IP version instead
+        LINKTYPE_NULL => (None, frame.get(4..)?),
+        _ => (None, frame),
+    };
+
+    let version = packet.first()? >> 4;
+    match (ethertype, version) {
+        (Some(ETHERTYPE_IPV4) | None, 4) => decode_ipv4(packet),
+        (Some(ETHERTYPE_IPV6) | None, 6) => decode_ipv6(packet),
+        _ => None,
+    }
+}
+
+fn decode_ipv4(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let header_len = usize::from(packet.first()? & 0x0F) * 4;
+    let total_len = usize::from(be16(packet, 2)?);
+    // fragments are skipped, the server never sends datagrams big enough to
+    // be fragmented so reassembling them isn't worth it
+    let fragment = be16(packet, 6)?;
+    if *packet.get(9)? != IPPROTO_UDP || fragment & 0x3FFF != 0 || header_len < IPV4_HEADER_LEN {
+        return None;
+    }
+    // a record cut short can end inside the header
+    let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+    let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+    let udp = packet.get(header_len..total_len.min(packet.len()))?;
+    decode_udp(
+        Ipv4Addr::from(source).into(),
+        Ipv4Addr::from(destination).into(),
+        udp,
+    )
+}
+
+fn decode_ipv6(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    // only UDP directly after the fixed header, no extension headers
+    if *packet.get(6)? != IPPROTO_UDP {
+        return None;
+    }
+    let payload_len = usize::from(be16(packet, 4)?);
+    let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+    let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+    let udp = packet.get(40..(40 + payload_len).min(packet.len()))?;
+    decode_udp(
+        Ipv6Addr::from(source).into(),
+        Ipv6Addr::from(destination).into(),
+        udp,
+    )
+}
+
+fn decode_udp(
+    source: IpAddr,
+    destination: IpAddr,
+    udp: &[u8],
+) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let source_port = be16(udp, 0)?;
+    let destination_port = be16(udp, 2)?;
+    let len = usize::from(be16(udp, 4)?);
+    // a shorter slice means the snapshot length cut the datagram short
+    let data = udp.get(UDP_HEADER_LEN..len)?;
+    Some((
+        SocketAddr::new(source, source_port),
+        SocketAddr::new(destination, destination_port),
+        data,
+    ))
+}
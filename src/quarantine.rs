@@ -0,0 +1,81 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::errors::PacketParseError;
+
+pub const INDEX_FILE_NAME: &str = "index.tsv";
+
+// Quarantine keeps every datagram that failed to parse so server bugs can be
+// looked at after the fact. Each datagram is saved byte-for-byte in its own
+// `.bin` file, and `index.tsv` gets one line per datagram:
+//
+//   <unix time in ms> <tab> <length> <tab> <file name> <tab> <parse error>
+pub struct Quarantine {
+    dir: PathBuf,
+    index: File,
+    saved: usize,
+}
+
+impl Quarantine {
+    /// Opens (creating if needed) a quarantine directory. Entries from earlier
+    /// runs are kept; new ones are appended to the index.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory or its index file
+    /// can't be created.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(INDEX_FILE_NAME))?;
+        Ok(Quarantine {
+            dir,
+            index,
+            saved: 0,
+        })
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // number of datagrams saved since this quarantine was opened
+    #[must_use]
+    pub fn saved(&self) -> usize {
+        self.saved
+    }
+
+    /// Saves one datagram along with the error it failed to parse with, and
+    /// returns the path of the saved copy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the datagram or its index entry
+    /// can't be written.
+    pub fn save(&mut self, datagram: &[u8], error: &PacketParseError) -> io::Result<PathBuf> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis());
+        // the counter keeps names unique within a millisecond, the timestamp across runs
+        let file_name = format!("{timestamp_ms}-{:06}.bin", self.saved);
+        let path = self.dir.join(&file_name);
+
+        fs::write(&path, datagram)?;
+        writeln!(
+            self.index,
+            "{timestamp_ms}\t{}\t{file_name}\t{error}",
+            datagram.len()
+        )?;
+
+        self.saved += 1;
+        Ok(path)
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    net::UdpSocket,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    capture::{CaptureReader, CapturedDatagram, CAPTURE_MAGIC},
+    file_manager::FileManager,
+    hello::{Arrival, Handshake},
+    packet::PacketParser,
+    pcap::{self, PcapReader},
+};
+
+// The datagrams in a capture file, whichever format it is in
+pub type Datagrams = Box<dyn Iterator<Item = io::Result<CapturedDatagram>>>;
+
+/// Opens a capture file, telling our own format from pcap by its magic. For
+/// pcap only the datagrams sent from `server_port` are kept.
+///
+/// # Errors
+///
+/// This function will return an error if the file can't be opened or is in
+/// neither format.
+pub fn open_capture(path: impl AsRef<Path>, server_port: u16) -> io::Result<Datagrams> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; CAPTURE_MAGIC.len()];
+    let sniffed = file.by_ref().take(magic.len() as u64).read(&mut magic)?;
+    file.rewind()?;
+
+    if &magic[..sniffed] == CAPTURE_MAGIC {
+        Ok(Box::new(CaptureReader::new(file)?))
+    } else {
+        Ok(Box::new(pcap::server_datagrams(
+            PcapReader::new(file)?,
+            server_port,
+        )))
+    }
+}
+
+// What happened when a capture was fed through a FileManager
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    // datagrams fed in, including hello replies and ones that failed to
+    // parse; ones held for a reply count once they're released
+    pub datagrams: usize,
+    pub parse_errors: usize,
+    // parsed, but rejected by strict chunk checks
+    pub rejected_packets: usize,
+    // true if the FileManager considered every file complete
+    pub complete: bool,
+}
+
+/// Feeds captured datagrams into `file_manager` exactly as the client loop
+/// would, stopping as soon as every file is complete (so a capture with extra
+/// trailing datagrams reproduces the same state the client ended up in).
+///
+/// # Errors
+///
+/// This function will return an error if reading the capture fails.
+pub fn replay_offline<I>(
+    datagrams: I,
+    parser: &PacketParser,
+    file_manager: &mut FileManager,
+) -> io::Result<ReplayReport>
+where
+    I: IntoIterator<Item = io::Result<CapturedDatagram>>,
+{
+    let mut report = ReplayReport::default();
+    // a recorded hello reply switches formats, and datagrams recorded before
+    // it wait for it, just like they did for the client
+    let mut handshake = Handshake::new(*parser);
+
+    for datagram in datagrams {
+        if file_manager.received_all_packets() {
+            break;
+        }
+        let datagram = datagram?;
+        let ready = match handshake.arrive(&datagram.data) {
+            Arrival::Reply(reply) => {
+                report.datagrams += 1;
+                handshake.negotiate(reply.capabilities)
+            }
+            Arrival::LateReply => {
+                report.datagrams += 1;
+                Vec::new()
+            }
+            Arrival::Full => handshake.settle_legacy(),
+            Arrival::Ready => vec![datagram.data],
+            Arrival::Held => Vec::new(),
+        };
+        feed(&handshake, ready, file_manager, &mut report);
+    }
+    // the capture ends without a reply, which the client takes to mean a
+    // legacy server
+    let held = handshake.settle_legacy();
+    feed(&handshake, held, file_manager, &mut report);
+
+    report.complete = file_manager.received_all_packets();
+    Ok(report)
+}
+
+// Parses datagrams whose format is settled and hands them to the
+// FileManager, counting what goes wrong. Like the client, stops once every
+// file is complete, even partway through released datagrams.
+fn feed(
+    handshake: &Handshake,
+    datagrams: Vec<Vec<u8>>,
+    file_manager: &mut FileManager,
+    report: &mut ReplayReport,
+) {
+    for datagram in datagrams {
+        if file_manager.received_all_packets() {
+            return;
+        }
+        report.datagrams += 1;
+        match handshake.parser().parse(&datagram) {
+            Ok(packet) => {
+                if file_manager.process_packet_ref(packet).is_err() {
+                    report.rejected_packets += 1;
+                }
+            }
+            Err(_) => report.parse_errors += 1,
+        }
+    }
+}
+
+/// Plays the part of the server: waits for a hello on `sock`, then sends
+/// every captured datagram back to whoever said hello. With `realtime` the
+/// recorded gaps between datagrams are kept; otherwise they are sent as fast
+/// as possible. Returns the number of datagrams sent.
+///
+/// # Errors
+///
+/// This function will return an error if reading the capture, receiving the
+/// hello, or sending fails.
+pub fn serve<I>(sock: &UdpSocket, datagrams: I, realtime: bool) -> io::Result<usize>
+where
+    I: IntoIterator<Item = io::Result<CapturedDatagram>>,
+{
+    let mut hello = [0; 2048];
+    let (_, client) = sock.recv_from(&mut hello)?;
+
+    let started = Instant::now();
+    let mut first_offset: Option<Duration> = None;
+    let mut sent = 0;
+
+    for datagram in datagrams {
+        let datagram = datagram?;
+        if realtime {
+            let first = *first_offset.get_or_insert(datagram.offset);
+            let due = datagram.offset.saturating_sub(first);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        sock.send_to(&datagram.data, client)?;
+        sent += 1;
+    }
+
+    Ok(sent)
+}
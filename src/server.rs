@@ -0,0 +1,649 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs, io,
+    net::{SocketAddr, UdpSocket},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    hello::{Capabilities, Hello, HelloReply},
+    packet::{
+        append_checksum, Ack, Data, Feedback, FileId, FileMetadata, Header, Manifest, Parity,
+        MAX_DATAGRAM_SIZE, MAX_MANIFEST_PART_IDS, MAX_PACKET_SIZE, MAX_PAYLOAD_SIZE,
+    },
+};
+
+pub const WINDOW: usize = 64;
+pub const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+// without retransmission or flow control nothing paces the sending, so a
+// window's worth of packets goes out at a time with this gap in between, or a
+// client's receive buffer overflows
+pub const BURST_GAP: Duration = Duration::from_millis(5);
+// with flow control, sends may run this far ahead of the rate before the
+// server sleeps, since sleeping for less isn't precise anyway
+const PACING_SLACK: Duration = Duration::from_millis(1);
+
+// ServerOptions is how a session is served
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerOptions {
+    // what the server will agree to; the session uses whatever of this the
+    // client's hello offers
+    pub capabilities: Capabilities,
+    // in reliable mode, how many packets past the oldest unacknowledged one
+    // may be in flight; otherwise, how many go out in one burst
+    pub window: usize,
+    // resend a packet that hasn't been acknowledged this long after sending it
+    pub retransmit_timeout: Duration,
+    // give up on a client that hasn't acknowledged anything for this long
+    pub idle_timeout: Duration,
+    // with the parity capability, send a parity packet after every this
+    // many data packets of a file
+    pub parity_group: Option<u8>,
+    // the fraction of packets to drop on purpose, for testing
+    pub loss: f64,
+    pub seed: u64,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            capabilities: Capabilities::SUPPORTED,
+            window: WINDOW,
+            retransmit_timeout: RETRANSMIT_TIMEOUT,
+            idle_timeout: IDLE_TIMEOUT,
+            parity_group: None,
+            loss: 0.0,
+            seed: 1,
+        }
+    }
+}
+
+// ServedFile is a file the server hands out, under the name the client
+// will save it as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServedFile {
+    pub name: OsString,
+    pub contents: Vec<u8>,
+    // sent in the header when the client negotiated the extended header
+    pub metadata: Option<FileMetadata>,
+}
+
+impl ServedFile {
+    /// Reads a file to serve under its own file name.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be read or the
+    /// path doesn't end in a file name.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} isn't a file", path.display()),
+            )
+        })?;
+        Self::read(path, name.to_os_string())
+    }
+
+    /// Reads every file under `dir`, in name order, to serve as a directory
+    /// tree. Each is named by its path from `dir`, with `/` between the
+    /// parts, behind the name of `dir` itself (if it has one; `.` doesn't).
+    /// Symlinks are skipped.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a directory or file can't be
+    /// read.
+    pub fn open_tree(dir: &Path) -> io::Result<Vec<Self>> {
+        let mut files = Vec::new();
+        Self::read_tree(dir, dir.file_name(), &mut files)?;
+        Ok(files)
+    }
+
+    fn read_tree(dir: &Path, prefix: Option<&OsStr>, files: &mut Vec<Self>) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(fs::DirEntry::file_name);
+        for entry in entries {
+            let name = match prefix {
+                Some(prefix) => {
+                    let mut name = prefix.to_os_string();
+                    name.push("/");
+                    name.push(entry.file_name());
+                    name
+                }
+                None => entry.file_name(),
+            };
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                Self::read_tree(&entry.path(), Some(&name), files)?;
+            } else if file_type.is_file() {
+                files.push(Self::read(&entry.path(), name)?);
+            }
+        }
+        Ok(())
+    }
+
+    // The file's payloads, once it's checked that its name fits in a header
+    // and its chunks can all be numbered. An empty file is still one (empty)
+    // last packet.
+    fn chunks(&self, large_files: bool) -> io::Result<Vec<&[u8]>> {
+        let too_big = |what: String| io::Error::new(io::ErrorKind::InvalidInput, what);
+        // the prefix and any metadata make room for themselves, but a header
+        // still only holds this much name
+        let max_name = MAX_PACKET_SIZE - 2;
+        if self.name.len() > max_name {
+            return Err(too_big(format!(
+                "{} is too long a name: {} bytes is more than {max_name}",
+                self.name.to_string_lossy(),
+                self.name.len()
+            )));
+        }
+
+        let chunks: Vec<&[u8]> = if self.contents.is_empty() {
+            vec![&[]]
+        } else {
+            self.contents.chunks(MAX_PAYLOAD_SIZE).collect()
+        };
+        let max_packets = if large_files {
+            u64::from(u32::MAX) + 1
+        } else {
+            u64::from(u16::MAX) + 1
+        };
+        if chunks.len() as u64 > max_packets {
+            return Err(too_big(format!(
+                "{} is too big: {} packets is more than {max_packets}",
+                self.name.to_string_lossy(),
+                chunks.len()
+            )));
+        }
+        Ok(chunks)
+    }
+
+    fn read(path: &Path, name: OsString) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        let metadata = FileMetadata {
+            // what was read, in case the file changed in between
+            size: contents.len() as u64,
+            ..FileMetadata::from_fs(&fs::metadata(path)?)
+        };
+        Ok(ServedFile {
+            name,
+            contents,
+            metadata: Some(metadata),
+        })
+    }
+}
+
+// What happened in one session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionReport {
+    pub client: Option<SocketAddr>,
+    pub capabilities: Capabilities,
+    // distinct packets in the session, including the manifest and parity
+    pub packets: usize,
+    pub parity: usize,
+    // every send, including retransmissions and deliberately dropped ones
+    pub sent: usize,
+    pub retransmitted: usize,
+    pub dropped: usize,
+    // with flow control, how much feedback arrived and the last rate asked for
+    pub feedback: usize,
+    pub rate: Option<u32>,
+}
+
+// Where each file's packets sit in the session's packet list
+struct FileSlots {
+    header: usize,
+    // indexed by packet number
+    data: Vec<usize>,
+}
+
+// Every packet of a session, in sending order, with where to find them
+struct Packets {
+    datagrams: Vec<Vec<u8>>,
+    // one slot, or several for a wide manifest in parts
+    manifest: Vec<usize>,
+    files: Vec<FileSlots>,
+    // parity packets aren't acknowledged, so they're sent once
+    parity: Vec<usize>,
+}
+
+impl Packets {
+    // The manifest (structured sessions only), then each file's header and
+    // data packets in order, with a parity packet after every `parity_group`
+    // data packets if given. Without the large files capability a file can't
+    // have more packets than 2 byte packet numbers can count, and without
+    // wide file IDs a session can't have more files than 1 byte file IDs.
+    // A file's name (with any directories) has to fit in one header.
+    // Headers carry the files' metadata with the extended header capability.
+    fn build(
+        files: &[ServedFile],
+        with_manifest: bool,
+        parity_group: Option<u8>,
+        capabilities: Capabilities,
+    ) -> io::Result<Self> {
+        let too_many = |what: String| io::Error::new(io::ErrorKind::InvalidInput, what);
+        let large_files = capabilities.contains(Capabilities::LARGE_FILES);
+        let extended_header = capabilities.contains(Capabilities::EXTENDED_HEADER);
+        let max_files = if capabilities.contains(Capabilities::WIDE_FILE_IDS) {
+            u64::from(FileId::MAX) + 1
+        } else {
+            u64::from(u8::MAX) + 1
+        };
+        if files.len() as u64 > max_files {
+            return Err(too_many(format!(
+                "{} files is more than {max_files}",
+                files.len()
+            )));
+        }
+        let file_ids: Vec<FileId> = (0..=FileId::MAX).take(files.len()).collect();
+
+        let mut packets = Packets {
+            datagrams: Vec::new(),
+            manifest: Vec::new(),
+            files: Vec::with_capacity(files.len()),
+            parity: Vec::new(),
+        };
+        if with_manifest && !files.is_empty() {
+            // more files than 1 byte IDs can name need a wide manifest, split
+            // into parts that each say how many files there are in all
+            let total = (file_ids.len() > usize::from(u8::MAX) + 1)
+                .then(|| u32::try_from(file_ids.len()).unwrap_or(u32::MAX));
+            let part_size = if total.is_some() {
+                MAX_MANIFEST_PART_IDS
+            } else {
+                file_ids.len()
+            };
+            for part in file_ids.chunks(part_size) {
+                packets.manifest.push(packets.datagrams.len());
+                packets.datagrams.push(
+                    Manifest {
+                        file_ids: part.to_vec(),
+                        total,
+                    }
+                    .to_bytes(),
+                );
+            }
+        }
+
+        for (file, file_id) in files.iter().zip(file_ids) {
+            let chunks = file.chunks(large_files)?;
+
+            let header = packets.datagrams.len();
+            packets.datagrams.push(
+                Header {
+                    file_id,
+                    file_name: file.name.clone(),
+                    expected_packet_count: chunks.len(),
+                    metadata: file.metadata.filter(|_| extended_header),
+                }
+                .to_bytes(),
+            );
+            let last = chunks.len() - 1;
+            let mut data = Vec::with_capacity(chunks.len());
+            for ((index, chunk), packet_number) in chunks.iter().enumerate().zip(0..=u32::MAX) {
+                data.push(packets.datagrams.len());
+                packets.datagrams.push(
+                    Data {
+                        file_id,
+                        packet_number,
+                        is_last_packet: index == last,
+                        payload: chunk.to_vec(),
+                    }
+                    .to_bytes(),
+                );
+
+                let group = parity_group.map_or(0, usize::from);
+                if group > 0 && (index % group == group - 1 || index == last) {
+                    let first = index - index % group;
+                    packets.parity.push(packets.datagrams.len());
+                    packets.datagrams.push(
+                        Parity::new(
+                            file_id,
+                            u32::try_from(first).unwrap_or(u32::MAX),
+                            &chunks[first..=index],
+                            index == last,
+                        )
+                        .to_bytes(),
+                    );
+                }
+            }
+            packets.files.push(FileSlots { header, data });
+        }
+        Ok(packets)
+    }
+
+    // Marks everything `ack` covers in `acked`
+    fn apply(&self, ack: &Ack, acked: &mut [bool]) {
+        if ack.has_manifest {
+            for &manifest in &self.manifest {
+                acked[manifest] = true;
+            }
+        }
+        let Some(slots) = usize::try_from(ack.file_id)
+            .ok()
+            .and_then(|index| self.files.get(index))
+        else {
+            return;
+        };
+        if ack.has_header {
+            acked[slots.header] = true;
+        }
+        for (packet_number, &slot) in (0..).zip(&slots.data) {
+            if ack.has_packet(packet_number) {
+                acked[slot] = true;
+            }
+        }
+    }
+}
+
+// Drops packets on purpose, reproducibly: xorshift64 seeded from the options
+struct Loss {
+    rate: f64,
+    state: u64,
+}
+
+impl Loss {
+    fn new(rate: f64, seed: u64) -> Self {
+        Loss {
+            rate,
+            // xorshift gets stuck on zero
+            state: seed.max(1),
+        }
+    }
+
+    fn drop_next(&mut self) -> bool {
+        if self.rate <= 0.0 {
+            return false;
+        }
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        // the top 53 bits make an evenly spread f64 in [0, 1)
+        #[allow(clippy::cast_precision_loss)]
+        let sample = (self.state >> 11) as f64 / (1u64 << 53) as f64;
+        sample < self.rate
+    }
+}
+
+// Spaces sends out evenly at the rate the client asked for
+struct Pacer {
+    // between two sends
+    gap: Duration,
+    next: Instant,
+}
+
+impl Pacer {
+    fn new(rate: u32) -> Self {
+        Pacer {
+            gap: Pacer::gap(rate),
+            next: Instant::now(),
+        }
+    }
+
+    fn gap(rate: u32) -> Duration {
+        Duration::from_secs(1) / rate.max(1)
+    }
+
+    // sleeps until the next send is due
+    fn wait_turn(&mut self) {
+        let now = Instant::now();
+        if self.next > now + PACING_SLACK {
+            thread::sleep(self.next - now);
+        }
+        self.next = self.next.max(now) + self.gap;
+    }
+}
+
+// One client's session: where to send and what to count
+struct Sender<'a> {
+    sock: &'a UdpSocket,
+    client: SocketAddr,
+    loss: Loss,
+    // set with flow control
+    pacer: Option<Pacer>,
+    report: SessionReport,
+}
+
+impl Sender<'_> {
+    fn send(&mut self, datagram: &[u8]) -> io::Result<()> {
+        if let Some(pacer) = &mut self.pacer {
+            pacer.wait_turn();
+        }
+        self.report.sent += 1;
+        if self.loss.drop_next() {
+            self.report.dropped += 1;
+            return Ok(());
+        }
+        self.sock.send_to(datagram, self.client).map(|_| ())
+    }
+
+    // paces the rest of the session at the rate the client asked for
+    fn set_rate(&mut self, rate: u32) {
+        match &mut self.pacer {
+            Some(pacer) => pacer.gap = Pacer::gap(rate),
+            None => self.pacer = Some(Pacer::new(rate)),
+        }
+        self.report.rate = Some(rate);
+    }
+
+    // Without retransmission nothing else reads from the socket, so this
+    // picks up whatever feedback has arrived in between bursts
+    fn poll_feedback(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.sock.set_nonblocking(true)?;
+        let result = loop {
+            match self.sock.recv_from(buf) {
+                Ok((len, from)) if from == self.client => {
+                    if let Ok(feedback) = Feedback::parse(&buf[..len]) {
+                        self.report.feedback += 1;
+                        self.set_rate(feedback.rate);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.sock.set_nonblocking(false)?;
+        result
+    }
+}
+
+/// Waits for a client's hello and sends it every file. A structured hello
+/// gets a reply selecting the capabilities both sides support and a
+/// manifest; a legacy one gets the packets straight away, like the original
+/// server. The reply is repeated, after every burst or in answer to a
+/// repeated hello, in case it was lost. With the retransmit capability the
+/// packets go out through a sliding window and are resent until the client
+/// acknowledges them, and the reply only until the first ack. With
+/// flow control they go out no faster than the client's latest feedback (or
+/// its hello) asks for.
+///
+/// # Errors
+///
+/// This function will return an error if a file is too big for the
+/// protocol, a socket operation fails, or (in reliable mode) the client
+/// stops acknowledging for longer than `options.idle_timeout`.
+pub fn serve_session(
+    sock: &UdpSocket,
+    files: &[ServedFile],
+    options: &ServerOptions,
+) -> io::Result<SessionReport> {
+    sock.set_read_timeout(None)?;
+    let mut buf = [0; 2048];
+    let (len, client) = sock.recv_from(&mut buf)?;
+
+    let hello = Hello::parse(&buf[..len]);
+    let reply = hello.map(|hello| hello.reply(options.capabilities));
+    let capabilities = reply.map_or(Capabilities::NONE, |reply| reply.capabilities);
+    if let Some(reply) = reply {
+        sock.send_to(&reply.to_bytes(), client)?;
+    }
+
+    let parity_group = options
+        .parity_group
+        .filter(|_| capabilities.contains(Capabilities::PARITY));
+    let mut packets = Packets::build(files, reply.is_some(), parity_group, capabilities)?;
+    if capabilities.contains(Capabilities::CHECKSUMS) {
+        packets.datagrams.iter_mut().for_each(append_checksum);
+    }
+
+    let mut sender = Sender {
+        sock,
+        client,
+        loss: Loss::new(options.loss, options.seed),
+        pacer: None,
+        report: SessionReport {
+            client: Some(client),
+            capabilities,
+            packets: packets.datagrams.len(),
+            parity: packets.parity.len(),
+            ..Default::default()
+        },
+    };
+
+    let flow_control = capabilities.contains(Capabilities::FLOW_CONTROL);
+    if let (true, Some(rate)) = (flow_control, hello.and_then(|hello| hello.rate)) {
+        sender.set_rate(rate);
+    }
+
+    if capabilities.contains(Capabilities::RETRANSMIT) {
+        send_reliably(&mut sender, &packets, reply, options)?;
+    } else {
+        for burst in packets.datagrams.chunks(options.window.max(1)) {
+            if flow_control {
+                sender.poll_feedback(&mut buf)?;
+            }
+            for datagram in burst {
+                sender.send(datagram)?;
+            }
+            // nothing tells us whether the reply arrived, and without it the
+            // client can't read the packets, so it follows every burst too
+            if let Some(reply) = reply {
+                sock.send_to(&reply.to_bytes(), client)?;
+            }
+            if sender.pacer.is_none() {
+                thread::sleep(BURST_GAP);
+            }
+        }
+    }
+    Ok(sender.report)
+}
+
+// Sliding window: keeps up to `options.window` packets past the oldest
+// unacknowledged one in flight, resends any that time out, and finishes once
+// the client has acknowledged every packet
+fn send_reliably(
+    sender: &mut Sender,
+    packets: &Packets,
+    reply: Option<HelloReply>,
+    options: &ServerOptions,
+) -> io::Result<()> {
+    let total = packets.datagrams.len();
+    let mut acked = vec![false; total];
+    for &parity in &packets.parity {
+        acked[parity] = true;
+    }
+    let mut sent_at: Vec<Option<Instant>> = vec![None; total];
+    // everything before `base` is acknowledged, nothing from `next` on is sent
+    let mut base = 0;
+    let mut next = 0;
+    let mut last_heard = Instant::now();
+    // until then, the reply may have been lost
+    let mut heard_ack = false;
+    let mut buf = [0; MAX_DATAGRAM_SIZE];
+
+    while base < total {
+        let first_new = next;
+        while next < total && next < base + options.window.max(1) {
+            sender.send(&packets.datagrams[next])?;
+            sent_at[next] = Some(Instant::now());
+            next += 1;
+        }
+        if next > first_new {
+            resend_reply(sender, reply, heard_ack)?;
+        }
+
+        let now = Instant::now();
+        let oldest = (base..next)
+            .filter(|&i| !acked[i])
+            .filter_map(|i| sent_at[i])
+            .min()
+            .unwrap_or(now);
+        let wait = (oldest + options.retransmit_timeout).saturating_duration_since(now);
+        sender
+            .sock
+            .set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+
+        match sender.sock.recv_from(&mut buf) {
+            Ok((len, from)) if from == sender.client => {
+                if let Ok(ack) = Ack::parse(&buf[..len]) {
+                    packets.apply(&ack, &mut acked);
+                    last_heard = Instant::now();
+                    heard_ack = true;
+                } else if let Ok(feedback) = Feedback::parse(&buf[..len]) {
+                    sender.report.feedback += 1;
+                    sender.set_rate(feedback.rate);
+                } else if let (Some(reply), Some(_)) = (reply, Hello::parse(&buf[..len])) {
+                    // the client is still saying hello, so the reply was lost
+                    sender.sock.send_to(&reply.to_bytes(), sender.client)?;
+                }
+            }
+            // another client, or an ICMP error from one that has gone away;
+            // either way only the idle timeout ends the session
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::ConnectionRefused
+                ) => {}
+            Err(e) => return Err(e),
+        }
+
+        let now = Instant::now();
+        if now.duration_since(last_heard) > options.idle_timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "{} stopped acknowledging with {} of {total} packets unacknowledged",
+                    sender.client,
+                    acked.iter().filter(|&&acked| !acked).count()
+                ),
+            ));
+        }
+        let mut resent = false;
+        for i in base..next {
+            if !acked[i]
+                && sent_at[i].is_some_and(|at| now.duration_since(at) >= options.retransmit_timeout)
+            {
+                sender.send(&packets.datagrams[i])?;
+                sender.report.retransmitted += 1;
+                sent_at[i] = Some(now);
+                resent = true;
+            }
+        }
+        if resent {
+            resend_reply(sender, reply, heard_ack)?;
+        }
+        while base < total && acked[base] {
+            base += 1;
+        }
+    }
+    Ok(())
+}
+
+// Until the client acks something it may not have the reply, and it stops
+// saying hello once packets arrive, so the reply follows whatever was just
+// sent, as it follows every burst in best-effort mode
+fn resend_reply(sender: &Sender, reply: Option<HelloReply>, heard_ack: bool) -> io::Result<()> {
+    if let (Some(reply), false) = (reply, heard_ack) {
+        sender.sock.send_to(&reply.to_bytes(), sender.client)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+// Options applied to the client socket before it is bound. Anything left as
+// `None`/`false` keeps the operating system's default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    // requested SO_RCVBUF size in bytes; the kernel may grant more or less
+    pub recv_buffer_size: Option<usize>,
+    pub reuse_address: bool,
+    pub reuse_port: bool,
+    // only used for IPv6 binds: Some(true) also accepts IPv4-mapped traffic
+    pub dual_stack: Option<bool>,
+}
+
+/// Creates a UDP socket with `options` applied and binds it to `addr`.
+///
+/// # Errors
+///
+/// This function will return an error if the socket can't be created, an
+/// option is rejected by the operating system (or isn't supported on this
+/// platform), or the bind fails.
+pub fn bind_socket(addr: SocketAddr, options: &SocketOptions) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if options.reuse_address {
+        socket.set_reuse_address(true)?;
+    }
+    if options.reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    if let (Some(dual_stack), SocketAddr::V6(_)) = (options.dual_stack, addr) {
+        socket.set_only_v6(!dual_stack)?;
+    }
+
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// Resolves `remote` and pairs each of its addresses with the local address
+/// to bind for it, as `(local, remote)`, in resolution order.
+///
+/// If `local` is `None` the local address is the unspecified address of the
+/// same family as the remote address (`0.0.0.0` or `::`) on `local_port`.
+/// Otherwise `local` is resolved too and the first address of the matching
+/// family is used; remote addresses without one are left out.
+///
+/// # Errors
+///
+/// This function will return an error if name resolution fails or leaves no
+/// usable pair.
+pub fn server_addresses(
+    remote: &str,
+    local: Option<&str>,
+    local_port: u16,
+) -> io::Result<Vec<(SocketAddr, SocketAddr)>> {
+    let local_addrs: Option<Vec<SocketAddr>> = match local {
+        Some(local) => Some(local.to_socket_addrs()?.collect()),
+        None => None,
+    };
+
+    let pairs: Vec<_> = remote
+        .to_socket_addrs()?
+        .filter_map(|remote_addr| {
+            let local_addr = match &local_addrs {
+                Some(addrs) => *addrs
+                    .iter()
+                    .find(|a| a.is_ipv4() == remote_addr.is_ipv4())?,
+                None => SocketAddr::new(unspecified_for(remote_addr), local_port),
+            };
+            Some((local_addr, remote_addr))
+        })
+        .collect();
+
+    if pairs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("no usable address for {remote}"),
+        ));
+    }
+    Ok(pairs)
+}
+
+/// Binds a socket to `local` with `options` applied and connects it to
+/// `remote`.
+///
+/// Connecting a UDP socket only sets where its datagrams go, so this says
+/// nothing about whether anything is listening at `remote`.
+///
+/// # Errors
+///
+/// This function will return an error if the bind or connect fails.
+pub fn connect_pair(
+    local: SocketAddr,
+    remote: SocketAddr,
+    options: &SocketOptions,
+) -> io::Result<UdpSocket> {
+    let sock = bind_socket(local, options)?;
+    sock.connect(remote)?;
+    Ok(sock)
+}
+
+/// Resolves `remote` and connects a socket to the first of its addresses
+/// that can be bound and connected (see [`server_addresses`] for how the
+/// local address is chosen).
+///
+/// Like [`connect_pair`], this can't tell whether a server is listening;
+/// callers that need one to answer try the other addresses from
+/// [`server_addresses`] themselves.
+///
+/// # Errors
+///
+/// This function will return an error if name resolution fails, or the error
+/// from the last attempt if no address could be bound and connected.
+pub fn connect_to_server(
+    remote: &str,
+    local: Option<&str>,
+    local_port: u16,
+    options: &SocketOptions,
+) -> io::Result<UdpSocket> {
+    let mut last_error = None;
+    for (local_addr, remote_addr) in server_addresses(remote, local, local_port)? {
+        match connect_pair(local_addr, remote_addr, options) {
+            Ok(sock) => return Ok(sock),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    // server_addresses never returns an empty list, so this is a real error
+    Err(last_error.unwrap_or_else(|| io::ErrorKind::AddrNotAvailable.into()))
+}
+
+fn unspecified_for(addr: SocketAddr) -> IpAddr {
+    match addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+/// Returns the receive buffer size the kernel actually granted, which can
+/// differ from what was requested (Linux doubles it and caps it at
+/// `net.core.rmem_max`).
+///
+/// # Errors
+///
+/// This function will return an error if the option can't be read.
+pub fn effective_recv_buffer_size(sock: &UdpSocket) -> io::Result<usize> {
+    socket2::SockRef::from(sock).recv_buffer_size()
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
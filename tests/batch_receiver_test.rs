@@ -0,0 +1,106 @@
+use segmented_file_system_client::batch_receiver::BatchReceiver;
+use segmented_file_system_client::errors::PacketParseError;
+use segmented_file_system_client::file_manager::FileManager;
+use segmented_file_system_client::packet::PacketRef;
+
+use std::net::UdpSocket;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn socket_pair() -> (UdpSocket, UdpSocket) {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+        receiver.connect(sender.local_addr().unwrap()).unwrap();
+        (sender, receiver)
+    }
+
+    // Keeps receiving until `count` datagrams have arrived, since a batch may
+    // come back with fewer datagrams than were sent
+    fn receive(receiver: &mut BatchReceiver, sock: &UdpSocket, count: usize) -> Vec<Vec<u8>> {
+        let mut datagrams = Vec::new();
+        while datagrams.len() < count {
+            receiver.recv(sock).unwrap();
+            datagrams.extend(receiver.datagrams().map(<[u8]>::to_vec));
+        }
+        datagrams
+    }
+
+    #[test]
+    fn test_batch_receives_datagrams_in_order() {
+        let (sender, sock) = socket_pair();
+        for i in 0..5u8 {
+            sender.send(&[1, 7, 0, i, b'x']).unwrap();
+        }
+
+        let mut receiver = BatchReceiver::new(8);
+        let datagrams = receive(&mut receiver, &sock, 5);
+
+        assert_eq!(datagrams.len(), 5);
+        for (i, datagram) in (0..5u8).zip(&datagrams) {
+            assert_eq!(datagram, &vec![1, 7, 0, i, b'x']);
+        }
+    }
+
+    #[test]
+    fn test_single_buffer_receives_one_at_a_time() {
+        let (sender, sock) = socket_pair();
+        sender.send(b"one").unwrap();
+        sender.send(b"two").unwrap();
+
+        let mut receiver = BatchReceiver::new(1);
+        assert_eq!(receiver.recv(&sock).unwrap(), 1);
+        assert_eq!(receiver.datagrams().collect::<Vec<_>>(), vec![b"one"]);
+        assert_eq!(receiver.recv(&sock).unwrap(), 1);
+        assert_eq!(receiver.datagrams().collect::<Vec<_>>(), vec![b"two"]);
+    }
+
+    #[test]
+    fn test_buffers_are_reused_between_batches() {
+        let (sender, sock) = socket_pair();
+        sender.send(&[0xAA; 100]).unwrap();
+        let mut receiver = BatchReceiver::new(4);
+        receive(&mut receiver, &sock, 1);
+
+        // A shorter datagram in the same slot must not expose the old bytes
+        sender.send(&[0xBB; 10]).unwrap();
+        let datagrams = receive(&mut receiver, &sock, 1);
+        assert_eq!(datagrams, vec![vec![0xBB; 10]]);
+    }
+
+    #[test]
+    fn test_batch_feeds_file_manager() {
+        let (sender, sock) = socket_pair();
+        sender.send(b"\x00\x05file.txt").unwrap();
+        sender.send(&[1, 5, 0, 0, b'a', b'b']).unwrap();
+        sender.send(&[3, 5, 0, 1, b'c']).unwrap();
+
+        let mut receiver = BatchReceiver::new(16);
+        let mut file_manager = FileManager::default();
+        for datagram in receive(&mut receiver, &sock, 3) {
+            file_manager
+                .process_packet_ref(PacketRef::try_from(datagram.as_slice()).unwrap())
+                .unwrap();
+        }
+
+        assert!(file_manager.received_all_packets());
+        let file_group = file_manager.get_packet_group(5).unwrap();
+        assert_eq!(file_group.packets.get(&1), Some(&vec![b'c']));
+    }
+
+    #[test]
+    fn test_oversized_datagram_is_not_silently_truncated() {
+        let (sender, sock) = socket_pair();
+        sender.send(&[1; 1100]).unwrap();
+
+        let mut receiver = BatchReceiver::new(2);
+        let datagrams = receive(&mut receiver, &sock, 1);
+        assert!(matches!(
+            PacketRef::try_from(datagrams[0].as_slice()),
+            Err(PacketParseError::Oversized { .. })
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+use segmented_file_system_client::capture::{
+    CaptureReader, CaptureWriter, CapturedDatagram, CAPTURE_MAGIC,
+};
+
+use std::io::{self, Cursor};
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_capture_round_trip() {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer
+            .record_at(Duration::from_micros(5), &[0, 1, b'a'])
+            .unwrap();
+        writer.record_at(Duration::from_millis(2), &[]).unwrap();
+        writer.record(&[3, 1, 0, 0, 9]).unwrap();
+        writer.flush().unwrap();
+
+        let bytes = writer.into_inner();
+        assert_eq!(&bytes[..8], CAPTURE_MAGIC);
+
+        let datagrams: Vec<CapturedDatagram> = CaptureReader::new(Cursor::new(bytes))
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(datagrams.len(), 3);
+        assert_eq!(
+            datagrams[0],
+            CapturedDatagram {
+                offset: Duration::from_micros(5),
+                data: vec![0, 1, b'a'],
+            }
+        );
+        assert_eq!(datagrams[1].offset, Duration::from_millis(2));
+        assert!(datagrams[1].data.is_empty());
+        assert_eq!(datagrams[2].data, vec![3, 1, 0, 0, 9]);
+    }
+
+    #[test]
+    fn test_capture_rejects_bad_magic() {
+        let result = CaptureReader::new(Cursor::new(b"NOTACAPTURE".to_vec()));
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_capture_truncated_record_is_an_error() {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer.record_at(Duration::ZERO, &[0, 1, b'a']).unwrap();
+        let mut bytes = writer.into_inner();
+        bytes.pop();
+
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_capture_rejects_oversized_record() {
+        let mut bytes = CAPTURE_MAGIC.to_vec();
+        bytes.extend_from_slice(&0u64.to_be_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_capture_file_on_disk() {
+        let path = std::env::temp_dir().join(format!("sfs-capture-{}.sfscap", std::process::id()));
+        let mut writer = CaptureWriter::create(&path).unwrap();
+        writer.record(&[0, 1, b'x']).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let datagrams: Vec<CapturedDatagram> = CaptureReader::open(&path)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].data, vec![0, 1, b'x']);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,378 @@
+use segmented_file_system_client::client::{run_client, run_client_with_addrs};
+use segmented_file_system_client::config::ClientConfig;
+use segmented_file_system_client::errors::ClientError;
+use segmented_file_system_client::hello::{Capabilities, Hello};
+use segmented_file_system_client::packet::{append_checksum, Ack};
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // A tiny stand-in for the real server: waits for the hello, then sends one
+    // single-packet file back to whoever said hello
+    fn serve_one_file(server: UdpSocket, file_name: &'static str, contents: &'static [u8]) {
+        thread::spawn(move || {
+            let mut buf = [0; 1028];
+            let (_, client) = server.recv_from(&mut buf).unwrap();
+
+            let mut data = vec![3, 9, 0, 0];
+            data.extend_from_slice(contents);
+            server.send_to(&data, client).unwrap();
+
+            let mut header = vec![0, 9];
+            header.extend_from_slice(file_name.as_bytes());
+            server.send_to(&header, client).unwrap();
+        });
+    }
+
+    // Receives acks until one says the single-packet file 4 is complete
+    fn wait_for_final_ack(server: &UdpSocket) {
+        let mut buf = [0; 1028];
+        loop {
+            let (len, _) = server.recv_from(&mut buf).unwrap();
+            if Ack::parse(&buf[..len]).is_ok_and(|ack| ack.has_header && ack.cumulative == 1) {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_client_over_ipv6_loopback() {
+        // IPv6 may be disabled in some CI containers
+        let Ok(server) = UdpSocket::bind("[::1]:0") else {
+            return;
+        };
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            ..Default::default()
+        };
+        serve_one_file(server, "ipv6_client_test.txt", b"over ipv6");
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client(&config).unwrap();
+
+        let file_contents = std::fs::read("src/ipv6_client_test.txt").unwrap();
+        assert_eq!(file_contents, b"over ipv6");
+        std::fs::remove_file("src/ipv6_client_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_run_client_over_ipv4_loopback() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            ..Default::default()
+        };
+        serve_one_file(server, "ipv4_client_test.txt", b"over ipv4");
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client(&config).unwrap();
+
+        let file_contents = std::fs::read("src/ipv4_client_test.txt").unwrap();
+        assert_eq!(file_contents, b"over ipv4");
+        std::fs::remove_file("src/ipv4_client_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_run_client_quarantines_bad_datagrams() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let quarantine_dir =
+            std::env::temp_dir().join(format!("sfs-client-quarantine-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&quarantine_dir);
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            quarantine_dir: Some(quarantine_dir.clone()),
+            ..Default::default()
+        };
+
+        thread::spawn(move || {
+            let mut buf = [0; 1028];
+            let (_, client) = server.recv_from(&mut buf).unwrap();
+            // reserved bits set - can't be parsed
+            server.send_to(&[0xF0, 9, 0, 0], client).unwrap();
+            server.send_to(&[3, 9, 0, 0, b'o', b'k'], client).unwrap();
+            server
+                .send_to(b"\x00\x09quarantine_client_test.txt", client)
+                .unwrap();
+        });
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client(&config).unwrap();
+
+        let index = std::fs::read_to_string(quarantine_dir.join("index.tsv")).unwrap();
+        assert_eq!(index.lines().count(), 1);
+        assert!(index.contains("reserved bits set"));
+
+        std::fs::remove_file("src/quarantine_client_test.txt").unwrap();
+        std::fs::remove_dir_all(quarantine_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_client_negotiates_checksums() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            ..Default::default()
+        };
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 1028];
+            let (len, client) = server.recv_from(&mut buf).unwrap();
+            let hello = Hello::parse(&buf[..len]).unwrap();
+            let reply = hello.reply(Capabilities::CHECKSUMS);
+            server.send_to(&reply.to_bytes(), client).unwrap();
+
+            for mut packet in [
+                b"\x03\x04\x00\x00checked".to_vec(),
+                b"\x00\x04checksum_client_test.txt".to_vec(),
+            ] {
+                append_checksum(&mut packet);
+                server.send_to(&packet, client).unwrap();
+            }
+            hello
+        });
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client(&config).unwrap();
+
+        assert_eq!(handle.join().unwrap().capabilities, Capabilities::SUPPORTED);
+        let file_contents = std::fs::read("src/checksum_client_test.txt").unwrap();
+        assert_eq!(file_contents, b"checked");
+        std::fs::remove_file("src/checksum_client_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_run_client_holds_packets_until_a_lost_reply_is_repeated() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            hello_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 1028];
+            let (len, client) = server.recv_from(&mut buf).unwrap();
+            let hello = Hello::parse(&buf[..len]).unwrap();
+            // the reply is "lost", but the checksummed packets arrive; read
+            // as the legacy format they would keep their checksums
+            for mut packet in [
+                b"\x03\x04\x00\x00checked".to_vec(),
+                b"\x00\x04lost_reply_client_test.txt".to_vec(),
+            ] {
+                append_checksum(&mut packet);
+                server.send_to(&packet, client).unwrap();
+            }
+
+            // the reply that follows the burst gets there
+            let reply = hello.reply(Capabilities::CHECKSUMS);
+            server.send_to(&reply.to_bytes(), client).unwrap();
+        });
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client(&config).unwrap();
+        handle.join().unwrap();
+
+        let file_contents = std::fs::read("src/lost_reply_client_test.txt").unwrap();
+        assert_eq!(file_contents, b"checked");
+        std::fs::remove_file("src/lost_reply_client_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_run_client_says_hello_once_to_a_sending_legacy_server() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            hello_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 1028];
+            let (_, client) = server.recv_from(&mut buf).unwrap();
+            server.send_to(b"\x03\x05\x00\x00legacy", client).unwrap();
+            server
+                .send_to(b"\x00\x05legacy_once_client_test.txt", client)
+                .unwrap();
+
+            // a legacy server takes another hello as a new request
+            server
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            server.recv_from(&mut buf).map(|(len, _)| len)
+        });
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client(&config).unwrap();
+        let second = handle.join().unwrap();
+        assert!(second.is_err(), "got a second hello: {second:?}");
+
+        let file_contents = std::fs::read("src/legacy_once_client_test.txt").unwrap();
+        assert_eq!(file_contents, b"legacy");
+        std::fs::remove_file("src/legacy_once_client_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_run_client_resends_lost_hello() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            hello_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        // the first hello is "lost"
+        let mut buf = [0; 1028];
+        let handle = thread::spawn(move || {
+            server.recv_from(&mut buf).unwrap();
+            serve_one_file(server, "retry_client_test.txt", b"second time");
+        });
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client(&config).unwrap();
+        handle.join().unwrap();
+
+        let file_contents = std::fs::read("src/retry_client_test.txt").unwrap();
+        assert_eq!(file_contents, b"second time");
+        std::fs::remove_file("src/retry_client_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_run_client_reports_server_not_responding() {
+        // a port nobody is listening on any more
+        let remote_addr = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let config = ClientConfig {
+            remote_addr,
+            local_port: 0,
+            hello_interval: Duration::from_millis(10),
+            connect_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+
+        let err = run_client(&config).unwrap_err();
+        let ClientError::ServerNotResponding { hellos, source, .. } = &err else {
+            panic!("expected the server not to respond, got {err:?}");
+        };
+        assert!(*hellos > 1);
+        // loopback reports the closed port back (ICMP port unreachable) on Linux
+        if cfg!(target_os = "linux") {
+            assert_eq!(
+                source.as_ref().map(io::Error::kind),
+                Some(io::ErrorKind::ConnectionRefused)
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_client_falls_back_to_next_address() {
+        // nobody is listening at the first address any more
+        let dead = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let local: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let addrs = [(local, dead), (local, server.local_addr().unwrap())];
+        let config = ClientConfig {
+            remote_addr: "fallback.test".to_string(),
+            local_port: 0,
+            hello_interval: Duration::from_millis(20),
+            connect_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        serve_one_file(server, "fallback_client_test.txt", b"second address");
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client_with_addrs(&config, &addrs).unwrap();
+
+        let file_contents = std::fs::read("src/fallback_client_test.txt").unwrap();
+        assert_eq!(file_contents, b"second address");
+        std::fs::remove_file("src/fallback_client_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_run_client_answers_retransmissions_after_the_final_acks() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            ack_interval: Duration::from_millis(5),
+            ..Default::default()
+        };
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 1028];
+            let (len, client) = server.recv_from(&mut buf).unwrap();
+            let hello = Hello::parse(&buf[..len]).unwrap();
+            let reply = hello.reply(Capabilities::RETRANSMIT);
+            server.send_to(&reply.to_bytes(), client).unwrap();
+
+            let data = b"\x03\x04\x00\x00again".to_vec();
+            for packet in [
+                b"\x04\x04".to_vec(),
+                data.clone(),
+                b"\x00\x04linger_client_test.txt".to_vec(),
+            ] {
+                server.send_to(&packet, client).unwrap();
+            }
+
+            // every ack up to the final one is "lost", so the data goes out
+            // again and the client has to say it's done once more
+            wait_for_final_ack(&server);
+            thread::sleep(Duration::from_millis(50));
+            server.send_to(&data, client).unwrap();
+
+            server
+                .set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            wait_for_final_ack(&server);
+        });
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client(&config).unwrap();
+        handle.join().unwrap();
+
+        let file_contents = std::fs::read("src/linger_client_test.txt").unwrap();
+        assert_eq!(file_contents, b"again");
+        std::fs::remove_file("src/linger_client_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_run_client_gives_up_on_a_silent_reliable_server() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            idle_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        thread::spawn(move || {
+            let mut buf = [0; 1028];
+            let (len, client) = server.recv_from(&mut buf).unwrap();
+            let hello = Hello::parse(&buf[..len]).unwrap();
+            let reply = hello.reply(Capabilities::RETRANSMIT);
+            server.send_to(&reply.to_bytes(), client).unwrap();
+            // then nothing, as if the server had crashed
+        });
+
+        let started = Instant::now();
+        let err = run_client(&config).unwrap_err();
+        let ClientError::Receive { source, .. } = &err else {
+            panic!("expected the receive to time out, got {err:?}");
+        };
+        assert_eq!(source.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,218 @@
+use segmented_file_system_client::capture::CaptureFormat;
+use segmented_file_system_client::config::{ClientConfig, LOCAL_PORT, REMOTE_ADDR};
+use segmented_file_system_client::errors::ClientError;
+use segmented_file_system_client::flow_control::{FEEDBACK_INTERVAL, INITIAL_RATE};
+use segmented_file_system_client::hello::Capabilities;
+use segmented_file_system_client::packet::FileNameFallback;
+
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_no_args_uses_defaults() {
+        let config = ClientConfig::from_args(args(&[])).unwrap();
+        assert_eq!(config, ClientConfig::default());
+        assert_eq!(config.local_addr, None);
+        assert_eq!(config.local_port, LOCAL_PORT);
+        assert_eq!(config.remote_addr, REMOTE_ADDR);
+        assert_eq!(config.batch_size, 1);
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let config = ClientConfig::from_args(args(&[
+            "--local",
+            "127.0.0.1:9000",
+            "--remote",
+            "10.0.0.1:6014",
+            "--batch-size",
+            "64",
+        ]))
+        .unwrap();
+        assert_eq!(config.local_addr.as_deref(), Some("127.0.0.1:9000"));
+        assert_eq!(config.remote_addr, "10.0.0.1:6014");
+        assert_eq!(config.batch_size, 64);
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        let result = ClientConfig::from_args(args(&["--bogus"]));
+        assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_missing_value() {
+        let result = ClientConfig::from_args(args(&["--batch-size"]));
+        assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_invalid_batch_size() {
+        for value in ["0", "lots"] {
+            let result = ClientConfig::from_args(args(&["--batch-size", value]));
+            assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+        }
+    }
+
+    #[test]
+    fn test_parse_socket_options() {
+        let config = ClientConfig::from_args(args(&[
+            "--recv-buffer",
+            "8388608",
+            "--reuse-address",
+            "--reuse-port",
+            "--dual-stack",
+        ]))
+        .unwrap();
+        assert_eq!(config.socket.recv_buffer_size, Some(8_388_608));
+        assert!(config.socket.reuse_address);
+        assert!(config.socket.reuse_port);
+        assert_eq!(config.socket.dual_stack, Some(true));
+
+        let config = ClientConfig::from_args(args(&["--ipv6-only"])).unwrap();
+        assert_eq!(config.socket.dual_stack, Some(false));
+        assert_eq!(config.socket.recv_buffer_size, None);
+    }
+
+    #[test]
+    fn test_parse_local_port_and_ipv6_remote() {
+        let config =
+            ClientConfig::from_args(args(&["--local-port", "0", "--remote", "[::1]:6014"]))
+                .unwrap();
+        assert_eq!(config.local_addr, None);
+        assert_eq!(config.local_port, 0);
+        assert_eq!(config.remote_addr, "[::1]:6014");
+    }
+
+    #[test]
+    fn test_parse_non_utf8_names() {
+        let config = ClientConfig::from_args(args(&["--non-utf8-names", "lossy"])).unwrap();
+        assert_eq!(config.parser.file_name_fallback, FileNameFallback::Lossy);
+
+        let result = ClientConfig::from_args(args(&["--non-utf8-names", "bogus"]));
+        assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_parse_quarantine_dir() {
+        assert_eq!(ClientConfig::default().quarantine_dir, None);
+        let config = ClientConfig::from_args(args(&["--quarantine", "bad-packets"])).unwrap();
+        assert_eq!(
+            config.quarantine_dir,
+            Some(std::path::PathBuf::from("bad-packets"))
+        );
+    }
+
+    #[test]
+    fn test_parse_capture_file() {
+        assert_eq!(ClientConfig::default().capture_file, None);
+        let config = ClientConfig::from_args(args(&["--capture", "run.sfscap"])).unwrap();
+        assert_eq!(
+            config.capture_file,
+            Some(std::path::PathBuf::from("run.sfscap"))
+        );
+
+        let result = ClientConfig::from_args(args(&["--capture"]));
+        assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_parse_capture_format() {
+        assert_eq!(
+            ClientConfig::default().capture_format,
+            CaptureFormat::Native
+        );
+        let config = ClientConfig::from_args(args(&["--capture-format", "pcap"])).unwrap();
+        assert_eq!(config.capture_format, CaptureFormat::Pcap);
+
+        let result = ClientConfig::from_args(args(&["--capture-format", "pcapng"]));
+        assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_parse_strict_chunks() {
+        assert!(!ClientConfig::default().strict_chunks);
+        let config = ClientConfig::from_args(args(&["--strict-chunks"])).unwrap();
+        assert!(config.strict_chunks);
+    }
+
+    #[test]
+    fn test_parse_stream_implies_strict_chunks() {
+        assert!(!ClientConfig::default().streaming);
+        let config = ClientConfig::from_args(args(&["--stream"])).unwrap();
+        assert!(config.streaming);
+        assert!(config.strict_chunks);
+    }
+
+    #[test]
+    fn test_parse_expect_files() {
+        assert_eq!(ClientConfig::default().expected_files, None);
+        let config = ClientConfig::from_args(args(&["--expect-files", "3"])).unwrap();
+        assert_eq!(config.expected_files, Some(3));
+
+        let result = ClientConfig::from_args(args(&["--expect-files", "0"]));
+        assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_parse_capabilities() {
+        assert_eq!(
+            ClientConfig::default().capabilities,
+            Capabilities::SUPPORTED
+        );
+        let config = ClientConfig::from_args(args(&["--capabilities", "none"])).unwrap();
+        assert_eq!(config.capabilities, Capabilities::NONE);
+
+        // known, but not something this client can handle
+        let result = ClientConfig::from_args(args(&["--capabilities", "compression"]));
+        assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_parse_hello_timing() {
+        let config = ClientConfig::from_args(args(&[
+            "--hello-interval",
+            "0.5",
+            "--connect-timeout",
+            "30",
+            "--ack-interval",
+            "0.01",
+            "--idle-timeout",
+            "2",
+        ]))
+        .unwrap();
+        assert_eq!(config.hello_interval, Duration::from_millis(500));
+        assert_eq!(config.connect_timeout, Duration::from_secs(30));
+        assert_eq!(config.ack_interval, Duration::from_millis(10));
+        assert_eq!(config.idle_timeout, Duration::from_secs(2));
+
+        for bad in ["0", "-1", "soon"] {
+            let result = ClientConfig::from_args(args(&["--connect-timeout", bad]));
+            assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+        }
+    }
+
+    #[test]
+    fn test_parse_flow_control() {
+        let config = ClientConfig::default();
+        assert_eq!(config.rate, INITIAL_RATE);
+        assert_eq!(config.feedback_interval, FEEDBACK_INTERVAL);
+
+        let config =
+            ClientConfig::from_args(args(&["--rate", "10000", "--feedback-interval", "0.05"]))
+                .unwrap();
+        assert_eq!(config.rate, 10_000);
+        assert_eq!(config.feedback_interval, Duration::from_millis(50));
+
+        let result = ClientConfig::from_args(args(&["--rate", "10"]));
+        assert!(matches!(result, Err(ClientError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,166 @@
+use segmented_file_system_client::errors::{
+    report, ClientError, PacketGroupError, PacketParseError,
+};
+use segmented_file_system_client::file_manager::FileManager;
+use segmented_file_system_client::packet_group::PacketGroup;
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::io;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_error_display() {
+        assert_eq!(
+            PacketParseError::TooShort.to_string(),
+            "packet is too short"
+        );
+        assert_eq!(
+            PacketParseError::ReservedBitsSet(0xFF).to_string(),
+            "reserved bits set in status byte 0xff"
+        );
+        assert_eq!(
+            PacketParseError::EmptyFileName.to_string(),
+            "header packet has an empty file name"
+        );
+        assert_eq!(
+            PacketParseError::InvalidEncoding { offset: 5 }.to_string(),
+            "file name has an invalid encoding at byte offset 5"
+        );
+        assert_eq!(
+            PacketParseError::DataTooShort { len: 3 }.to_string(),
+            "data packet is 3 bytes, shorter than the 4 byte minimum"
+        );
+        assert_eq!(
+            PacketParseError::Oversized {
+                len: 1029,
+                max: 1028
+            }
+            .to_string(),
+            "datagram is 1029 bytes, longer than the 1028 byte maximum"
+        );
+        assert_eq!(
+            PacketParseError::Oversized {
+                len: 1058,
+                max: 1055
+            }
+            .to_string(),
+            "datagram is 1058 bytes, longer than the 1055 byte maximum"
+        );
+        assert_eq!(
+            PacketParseError::BadChecksum {
+                expected: 0x1234,
+                actual: 0xabcd
+            }
+            .to_string(),
+            "checksum mismatch: datagram says 0x1234, contents give 0xabcd"
+        );
+    }
+
+    #[test]
+    fn test_parse_context_chain() {
+        let err = ClientError::Parse {
+            peer: Some("127.0.0.1:6014".parse().unwrap()),
+            len: 2,
+            source: PacketParseError::TooShort,
+        };
+        assert_eq!(
+            report(&err),
+            "Could not parse 2 byte datagram from 127.0.0.1:6014: packet is too short"
+        );
+        let source = err.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<PacketParseError>(),
+            Some(&PacketParseError::TooShort)
+        );
+    }
+
+    #[test]
+    fn test_file_context_chain() {
+        let err = ClientError::File {
+            file_id: 3,
+            file_name: Some(OsString::from("a.txt")),
+            source: PacketGroupError::Write {
+                path: "src/a.txt".into(),
+                offset: 2048,
+                source: io::Error::new(io::ErrorKind::PermissionDenied, "denied"),
+            },
+        };
+        assert_eq!(
+            report(&err),
+            "File 3 (a.txt): Could not write src/a.txt at byte 2048: denied"
+        );
+
+        // Walk all the way down to the io::Error
+        let group_err = err.source().unwrap();
+        let io_err = group_err.source().unwrap();
+        assert_eq!(
+            io_err.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn test_io_error_is_not_repeated() {
+        let err = ClientError::from(io::Error::other("boom"));
+        assert_eq!(report(&err), "IO error: boom");
+    }
+
+    #[test]
+    fn test_invalid_argument_has_no_source() {
+        let err = ClientError::InvalidArgument("--bogus".to_string());
+        assert!(err.source().is_none());
+        assert_eq!(report(&err), "Invalid argument: --bogus");
+    }
+
+    #[test]
+    fn test_write_all_files_names_the_failing_file() {
+        // a file where the directory should go
+        std::fs::create_dir_all("src").unwrap();
+        std::fs::write("src/errors_test_blocker", b"").unwrap();
+        let mut file_manager = FileManager::default();
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("errors_test_blocker/errors_test.txt")),
+            expected_packet_count: Some(1),
+            ..Default::default()
+        };
+        packet_group.packets.insert(0, vec![1, 2, 3]);
+        file_manager.insert_packet_group(42, packet_group);
+
+        let result = file_manager.write_all_files();
+        std::fs::remove_file("src/errors_test_blocker").unwrap();
+        match result {
+            Err(ClientError::File {
+                file_id: 42,
+                file_name: Some(name),
+                source:
+                    PacketGroupError::Write {
+                        path, offset: 0, ..
+                    },
+            }) => {
+                assert_eq!(name, OsString::from("errors_test_blocker/errors_test.txt"));
+                assert!(path.ends_with("errors_test_blocker/errors_test.txt"));
+            }
+            other => panic!("Expected a write error for file 42, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_missing_packet_names_packet_number() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("errors_test_missing.txt")),
+            expected_packet_count: Some(3),
+            ..Default::default()
+        };
+        packet_group.packets.insert(0, vec![1]);
+        packet_group.packets.insert(2, vec![3]);
+
+        let err = packet_group.write_file().unwrap_err();
+        assert!(matches!(err, PacketGroupError::MissingPacket(1)));
+        assert_eq!(err.to_string(), "Missing packet: 1");
+    }
+}
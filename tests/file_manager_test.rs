@@ -1,5 +1,8 @@
+use segmented_file_system_client::errors::ClientError;
 use segmented_file_system_client::file_manager::FileManager;
-use segmented_file_system_client::packet::{Data, Header, Packet};
+use segmented_file_system_client::packet::{
+    Data, FileId, Header, Manifest, ManifestRef, Packet, PacketRef,
+};
 use segmented_file_system_client::packet_group::PacketGroup;
 
 use std::ffi::OsString;
@@ -19,6 +22,7 @@ mod tests {
             file_id: 1,
             file_name: OsString::from("test_file"),
             expected_packet_count: 1,
+            metadata: None,
         });
         let data_packet = Packet::Data(Data {
             file_id: 1,
@@ -27,8 +31,8 @@ mod tests {
             payload: vec![1, 2, 3],
         });
 
-        file_manager.process_packet(header_packet);
-        file_manager.process_packet(data_packet);
+        file_manager.process_packet(header_packet).unwrap();
+        file_manager.process_packet(data_packet).unwrap();
 
         // Now assert that all packets have been received
         assert!(file_manager.received_all_packets());
@@ -43,6 +47,7 @@ mod tests {
             file_id: 1,
             file_name: OsString::from("test_file"),
             expected_packet_count: 2,
+            metadata: None,
         });
         let data_packet1 = Packet::Data(Data {
             file_id: 1,
@@ -57,9 +62,9 @@ mod tests {
             payload: vec![4, 5, 6],
         });
 
-        file_manager.process_packet(header_packet);
-        file_manager.process_packet(data_packet1);
-        file_manager.process_packet(data_packet2);
+        file_manager.process_packet(header_packet).unwrap();
+        file_manager.process_packet(data_packet1).unwrap();
+        file_manager.process_packet(data_packet2).unwrap();
 
         // Now assert that all packets have been received
         assert!(file_manager.received_all_packets());
@@ -73,8 +78,9 @@ mod tests {
             file_id: 1,
             file_name: OsString::from("test_file"),
             expected_packet_count: 2,
+            metadata: None,
         });
-        file_manager.process_packet(header_packet);
+        file_manager.process_packet(header_packet).unwrap();
 
         // Simulate receiving only one data packet
         let data_packet = Packet::Data(Data {
@@ -84,7 +90,7 @@ mod tests {
             payload: vec![1, 2, 3],
         });
 
-        file_manager.process_packet(data_packet);
+        file_manager.process_packet(data_packet).unwrap();
 
         // Verify that not all packets have been received
         assert!(!file_manager.received_all_packets());
@@ -103,6 +109,7 @@ mod tests {
             file_id: 1,
             file_name: OsString::from("test_file"),
             expected_packet_count: 2,
+            metadata: None,
         });
         let data_packet = Packet::Data(Data {
             file_id: 1,
@@ -111,8 +118,8 @@ mod tests {
             payload: vec![1, 2, 3],
         });
 
-        file_manager.process_packet(header_packet);
-        file_manager.process_packet(data_packet);
+        file_manager.process_packet(header_packet).unwrap();
+        file_manager.process_packet(data_packet).unwrap();
 
         let file_group = file_manager.get_packet_group(1).unwrap();
         assert_eq!(file_group.file_name, Some(OsString::from("test_file")));
@@ -127,23 +134,24 @@ mod tests {
             file_id: 1,
             file_name: OsString::from("test_file"),
             expected_packet_count: 2,
+            metadata: None,
         });
         let data_packet = Packet::Data(Data {
             file_id: 1,
             packet_number: 0,
-            is_last_packet: true,
+            is_last_packet: false,
             payload: vec![1, 2, 3],
         });
         let data_packet2 = Packet::Data(Data {
             file_id: 1,
             packet_number: 1,
-            is_last_packet: false,
+            is_last_packet: true,
             payload: vec![4, 5, 6],
         });
 
-        file_manager.process_packet(header_packet);
-        file_manager.process_packet(data_packet);
-        file_manager.process_packet(data_packet2);
+        file_manager.process_packet(header_packet).unwrap();
+        file_manager.process_packet(data_packet).unwrap();
+        file_manager.process_packet(data_packet2).unwrap();
 
         // Ensure the directory exists
         std::fs::create_dir_all("src").unwrap();
@@ -164,8 +172,10 @@ mod tests {
         let mut file_manager = FileManager::default();
 
         // Simulate adding a PacketGroup
-        let mut packet_group = PacketGroup::default();
-        packet_group.expected_packet_count = Some(2);
+        let mut packet_group = PacketGroup {
+            expected_packet_count: Some(2),
+            ..Default::default()
+        };
         packet_group.packets.insert(0, vec![1, 2, 3]);
         file_manager.insert_packet_group(1, packet_group);
 
@@ -183,6 +193,7 @@ mod tests {
             file_id: 1,
             file_name: OsString::from("test_file"),
             expected_packet_count: 1,
+            metadata: None,
         });
         let data_packet = Packet::Data(Data {
             file_id: 1,
@@ -191,12 +202,188 @@ mod tests {
             payload: vec![1, 2, 3],
         });
 
-        file_manager.process_packet(header_packet);
-        file_manager.process_packet(data_packet);
+        file_manager.process_packet(header_packet).unwrap();
+        file_manager.process_packet(data_packet).unwrap();
 
         let file_group = file_manager.get_packet_group(1).unwrap();
         assert_eq!(file_group.file_name, Some(OsString::from("test_file")));
         assert_eq!(file_group.packets.len(), 1);
         assert_eq!(file_group.packets.get(&0), Some(&vec![1, 2, 3]));
     }
+
+    #[test]
+    fn test_reassembly_reports_in_file_id_order() {
+        let mut file_manager = FileManager::default();
+        file_manager.insert_packet_group(
+            9,
+            PacketGroup {
+                file_name: Some(OsString::from("nine.txt")),
+                expected_packet_count: Some(1),
+                ..Default::default()
+            },
+        );
+        file_manager.insert_packet_group(2, PacketGroup::default());
+
+        let reports = file_manager.reassembly_reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].0, 2);
+        assert!(reports[0].1.missing_file_name);
+        assert_eq!(reports[1].0, 9);
+        assert_eq!(reports[1].1.missing_packets, vec![0..=0]);
+    }
+
+    #[test]
+    fn test_strict_chunks_reach_every_file() {
+        let mut file_manager = FileManager {
+            strict_chunks: true,
+            ..Default::default()
+        };
+        file_manager
+            .process_packet(Packet::Header(Header {
+                file_id: 4,
+                file_name: OsString::from("strict.txt"),
+                expected_packet_count: 0,
+                metadata: None,
+            }))
+            .unwrap();
+        let result = file_manager.process_packet(Packet::Data(Data {
+            file_id: 4,
+            packet_number: 0,
+            is_last_packet: false,
+            payload: vec![1, 2, 3],
+        }));
+
+        let Err(ClientError::File {
+            file_id, file_name, ..
+        }) = result
+        else {
+            panic!("expected a file error, got {result:?}");
+        };
+        assert_eq!(file_id, 4);
+        assert_eq!(file_name, Some(OsString::from("strict.txt")));
+        assert!(file_manager.get_packet_group(4).unwrap().strict_chunks);
+    }
+
+    // a file with a name and its only packet
+    fn finish_file(file_manager: &mut FileManager, file_id: FileId) {
+        file_manager
+            .process_packet(Packet::Header(Header {
+                file_id,
+                file_name: OsString::from(format!("file_{file_id}")),
+                expected_packet_count: 0,
+                metadata: None,
+            }))
+            .unwrap();
+        file_manager
+            .process_packet(Packet::Data(Data {
+                file_id,
+                packet_number: 0,
+                is_last_packet: true,
+                payload: file_id.to_be_bytes().to_vec(),
+            }))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_expected_files_waits_for_the_rest() {
+        let mut file_manager = FileManager {
+            expected_files: Some(2),
+            ..Default::default()
+        };
+        finish_file(&mut file_manager, 1);
+        assert!(!file_manager.received_all_packets());
+        finish_file(&mut file_manager, 2);
+        assert!(file_manager.received_all_packets());
+    }
+
+    #[test]
+    fn test_manifest_defines_completion() {
+        let mut file_manager = FileManager {
+            // the manifest takes precedence
+            expected_files: Some(1),
+            ..Default::default()
+        };
+        file_manager
+            .process_packet(Packet::Manifest(Manifest {
+                file_ids: vec![3, 5],
+                total: None,
+            }))
+            .unwrap();
+        finish_file(&mut file_manager, 3);
+        assert!(!file_manager.received_all_packets());
+
+        // a file outside the manifest doesn't count, even unfinished
+        file_manager
+            .process_packet(Packet::Header(Header {
+                file_id: 8,
+                file_name: OsString::from("stray"),
+                expected_packet_count: 0,
+                metadata: None,
+            }))
+            .unwrap();
+        assert!(!file_manager.is_expected(8));
+        finish_file(&mut file_manager, 5);
+        assert!(file_manager.received_all_packets());
+
+        // copies of the manifest add to it
+        file_manager
+            .process_packet_ref(PacketRef::Manifest(ManifestRef {
+                ids: &[5, 6],
+                total: None,
+            }))
+            .unwrap();
+        assert!(file_manager.is_expected(6));
+        assert!(!file_manager.received_all_packets());
+    }
+
+    #[test]
+    fn test_reliable_mode_waits_for_the_manifest() {
+        let mut file_manager = FileManager {
+            wait_for_manifest: true,
+            ..Default::default()
+        };
+        finish_file(&mut file_manager, 0);
+        assert!(!file_manager.received_all_packets());
+        assert!(!file_manager.ack(0).unwrap().has_manifest);
+        assert!(file_manager.ack(1).is_none());
+
+        file_manager
+            .process_packet(Packet::Manifest(Manifest {
+                file_ids: vec![0],
+                total: None,
+            }))
+            .unwrap();
+        assert!(file_manager.received_all_packets());
+        let ack = file_manager.ack(0).unwrap();
+        assert!(ack.has_manifest && ack.has_header);
+        assert_eq!(ack.cumulative, 1);
+    }
+
+    #[test]
+    fn test_wide_manifest_waits_for_every_part() {
+        let mut file_manager = FileManager::default();
+        finish_file(&mut file_manager, 300);
+        finish_file(&mut file_manager, 70_000);
+        file_manager
+            .process_packet_ref(PacketRef::Manifest(ManifestRef {
+                ids: &300_u32.to_be_bytes(),
+                total: Some(2),
+            }))
+            .unwrap();
+
+        // the other part is still to come, so nothing is ruled out yet
+        assert!(!file_manager.received_all_packets());
+        assert!(file_manager.is_expected(70_000));
+        assert!(!file_manager.ack(300).unwrap().has_manifest);
+
+        file_manager
+            .process_packet(Packet::Manifest(Manifest {
+                file_ids: vec![70_000],
+                total: Some(2),
+            }))
+            .unwrap();
+        assert!(file_manager.received_all_packets());
+        assert!(file_manager.ack(70_000).unwrap().has_manifest);
+        assert!(!file_manager.is_expected(5));
+    }
 }
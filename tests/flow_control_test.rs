@@ -0,0 +1,37 @@
+use segmented_file_system_client::flow_control::{RateController, MIN_RATE, RATE_STEP};
+
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_rate_rises_without_loss_and_halves_with_it() {
+        let started = Instant::now();
+        let at = |millis| started + Duration::from_millis(millis);
+        let mut controller = RateController::new(1000, Duration::from_millis(100), started);
+
+        // nothing changes until an interval has passed
+        assert_eq!(controller.update(0, at(50)), None);
+        assert_eq!(controller.due(), at(100));
+        assert_eq!(controller.update(0, at(100)), Some(1000 + RATE_STEP));
+        assert_eq!(controller.update(0, at(200)), Some(1000 + 2 * RATE_STEP));
+        // two packets went missing
+        assert_eq!(controller.update(2, at(300)), Some(1000));
+        // the same two don't count again
+        assert_eq!(controller.update(2, at(400)), Some(1000 + RATE_STEP));
+        assert_eq!(controller.rate(), 1000 + RATE_STEP);
+    }
+
+    #[test]
+    fn test_rate_never_drops_below_the_minimum() {
+        let started = Instant::now();
+        let mut controller = RateController::new(MIN_RATE * 3, Duration::from_millis(10), started);
+        for (skipped, millis) in (1..5).zip((10..).step_by(10)) {
+            controller.update(skipped, started + Duration::from_millis(millis));
+        }
+        assert_eq!(controller.rate(), MIN_RATE);
+    }
+}
@@ -0,0 +1,176 @@
+use segmented_file_system_client::errors::ClientError;
+use segmented_file_system_client::hello::{
+    Capabilities, Hello, HelloReply, HelloRetry, HELLO_MAGIC, MAX_HELLO_INTERVAL, PROTOCOL_VERSION,
+};
+use segmented_file_system_client::packet::PacketParser;
+
+use std::io;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_hello_round_trip() {
+        let hello = Hello::new(Capabilities::CHECKSUMS);
+        let bytes = hello.to_bytes();
+        assert_eq!(&bytes[..4], HELLO_MAGIC);
+        assert_eq!(
+            bytes,
+            [b'S', b'F', b'S', b'H', PROTOCOL_VERSION, 0x00, 0x02]
+        );
+        assert_eq!(Hello::parse(&bytes), Some(hello));
+
+        // the legacy hello and replies aren't hellos
+        assert_eq!(Hello::parse(&[0; 1028]), None);
+        assert_eq!(
+            Hello::parse(&hello.reply(Capabilities::NONE).to_bytes()),
+            None
+        );
+        assert_eq!(Hello::parse(&bytes[..6]), None);
+    }
+
+    #[test]
+    fn test_hello_carries_the_flow_control_rate() {
+        let hello = Hello {
+            rate: Some(4000),
+            ..Hello::new(Capabilities::FLOW_CONTROL)
+        };
+        let bytes = hello.to_bytes();
+        assert_eq!(bytes.len(), 11);
+        assert_eq!(&bytes[7..], 4000u32.to_be_bytes());
+        assert_eq!(Hello::parse(&bytes), Some(hello));
+        // neither a rate cut short nor a reply with one is valid
+        assert_eq!(Hello::parse(&bytes[..9]), None);
+        let mut reply = hello.reply(Capabilities::SUPPORTED).to_bytes();
+        reply.extend_from_slice(&bytes[7..]);
+        assert_eq!(HelloReply::parse(&reply), None);
+    }
+
+    #[test]
+    fn test_reply_selects_common_capabilities() {
+        let hello = Hello {
+            version: 7,
+            capabilities: Capabilities::CHECKSUMS.union(Capabilities::RETRANSMIT),
+            rate: None,
+        };
+        let reply = hello.reply(Capabilities::CHECKSUMS.union(Capabilities::COMPRESSION));
+        assert_eq!(reply.version, PROTOCOL_VERSION);
+        assert_eq!(reply.capabilities, Capabilities::CHECKSUMS);
+        assert_eq!(HelloReply::parse(&reply.to_bytes()), Some(reply));
+        assert_eq!(
+            reply.accept(hello.capabilities).unwrap(),
+            Capabilities::CHECKSUMS
+        );
+    }
+
+    #[test]
+    fn test_no_capabilities_make_a_reply_parse_as_a_packet() {
+        let supported = Capabilities::SUPPORTED.bits();
+        for bits in (0..=supported).filter(|bits| bits & !supported == 0) {
+            let capabilities = Capabilities::from_bits(bits);
+            let parser = PacketParser {
+                capabilities,
+                ..Default::default()
+            };
+            let reply = Hello::new(capabilities).reply(capabilities).to_bytes();
+            assert!(
+                parser.parse(&reply).is_err(),
+                "a reply parsed as a packet with {capabilities}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_reply_beyond_offer_is_rejected() {
+        let reply = HelloReply {
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::COMPRESSION,
+        };
+        let result = reply.accept(Capabilities::CHECKSUMS);
+        assert!(matches!(
+            result,
+            Err(ClientError::Negotiation {
+                offered: Capabilities::CHECKSUMS,
+                selected: Capabilities::COMPRESSION,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_capabilities_display_and_parse() {
+        assert_eq!(Capabilities::NONE.to_string(), "none");
+        let both = Capabilities::RETRANSMIT.union(Capabilities::CHECKSUMS);
+        assert_eq!(both.to_string(), "retransmit, checksums");
+        assert_eq!(
+            Capabilities::from_bits(0x8002).to_string(),
+            "checksums, unknown 0x8000"
+        );
+
+        assert_eq!("none".parse(), Ok(Capabilities::NONE));
+        assert_eq!("checksums,retransmit".parse(), Ok(both));
+        assert_eq!(
+            "extended-header, compression".parse(),
+            Ok(Capabilities::from_bits(0x0C))
+        );
+        assert!("checksums,bogus".parse::<Capabilities>().is_err());
+    }
+
+    #[test]
+    fn test_hello_retry_backs_off_until_the_deadline() {
+        let started = Instant::now();
+        let mut retry = HelloRetry::new(Duration::from_secs(1), Duration::from_secs(10), started);
+        assert_eq!(retry.interval(), Duration::from_secs(1));
+
+        let at = |secs| started + Duration::from_secs(secs);
+        assert_eq!(retry.due(), at(1));
+        assert_eq!(retry.next_interval(at(1)), Some(Duration::from_secs(2)));
+        assert_eq!(retry.due(), at(3));
+        assert_eq!(retry.next_interval(at(3)), Some(Duration::from_secs(4)));
+        // capped, then cut short by the deadline
+        assert_eq!(retry.next_interval(at(4)), Some(MAX_HELLO_INTERVAL));
+        assert_eq!(retry.next_interval(at(8)), Some(Duration::from_secs(2)));
+        assert_eq!(retry.next_interval(at(10)), None);
+        assert_eq!(retry.sent(), 5);
+    }
+
+    #[test]
+    fn test_hello_retry_reports_refused_connections() {
+        let started = Instant::now();
+        let mut retry = HelloRetry::new(Duration::from_secs(1), Duration::from_secs(1), started);
+        assert!(HelloRetry::is_retryable(&io::ErrorKind::WouldBlock.into()));
+        assert!(HelloRetry::is_retryable(
+            &io::ErrorKind::ConnectionRefused.into()
+        ));
+        assert!(!HelloRetry::is_retryable(
+            &io::ErrorKind::PermissionDenied.into()
+        ));
+
+        retry.record_error(io::ErrorKind::ConnectionRefused.into());
+        // a later timeout doesn't hide why the server never answered
+        retry.record_error(io::ErrorKind::WouldBlock.into());
+        let err = retry.give_up(
+            "127.0.0.1:6014".to_string(),
+            started + Duration::from_secs(1),
+        );
+        let ClientError::ServerNotResponding {
+            hellos,
+            waited,
+            source: Some(source),
+            ..
+        } = &err
+        else {
+            panic!("expected a refused connection, got {err:?}");
+        };
+        assert_eq!(*hellos, 1);
+        assert_eq!(*waited, Duration::from_secs(1));
+        assert_eq!(source.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(
+            err.to_string(),
+            "Server 127.0.0.1:6014 not responding: sent 1 hello over 1.0s \
+             (nothing is listening there)"
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use segmented_file_system_client::hex::hex_dump;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_hex_dump_short() {
+        assert_eq!(
+            hex_dump(&[0, 1, b't', b'e', b's', b't']),
+            "0000  00 01 74 65 73 74                                 |..test|\n"
+        );
+    }
+
+    #[test]
+    fn test_hex_dump_multiple_lines() {
+        let bytes: Vec<u8> = (0..20).collect();
+        let dump = hex_dump(&bytes);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "0000  00 01 02 03 04 05 06 07  08 09 0a 0b 0c 0d 0e 0f  |................|"
+        );
+        assert_eq!(
+            lines[1],
+            "0010  10 11 12 13                                       |....|"
+        );
+    }
+
+    #[test]
+    fn test_hex_dump_empty() {
+        assert_eq!(hex_dump(&[]), "");
+    }
+}
@@ -0,0 +1,257 @@
+use segmented_file_system_client::capture::{CaptureReader, CaptureWriter};
+use segmented_file_system_client::errors::PacketParseError;
+use segmented_file_system_client::hello::{Capabilities, HelloReply};
+use segmented_file_system_client::inspect::{dissect, dissect_capture, explain, run_inspect};
+use segmented_file_system_client::packet::{append_checksum, PacketParser};
+
+use std::io::Cursor;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn dissect_default(datagram: &[u8]) -> String {
+        dissect(datagram, &PacketParser::default())
+    }
+
+    #[test]
+    fn test_dissect_header() {
+        assert_eq!(
+            dissect_default(b"\x00\x09test"),
+            "Segmented File System, 6 bytes\n\
+             \x20   Status: 0x00 (header)\n\
+             \x20       .... ...0 = Data packet: no\n\
+             \x20       .... ..0. = Last packet: no\n\
+             \x20       .... .0.. = Manifest: no\n\
+             \x20       .... 0... = Parity: no\n\
+             \x20       ...0 .... = Wide packet number: no\n\
+             \x20       ..0. .... = Wide file ID: no\n\
+             \x20       .0.. .... = Metadata: no\n\
+             \x20       0... .... = Reserved: 0x00\n\
+             \x20   File ID: 9\n\
+             \x20   File name: \"test\" (4 bytes)\n"
+        );
+    }
+
+    #[test]
+    fn test_dissect_last_data_packet() {
+        let mut datagram = vec![3, 7, 0x01, 0x02];
+        datagram.extend(0..20);
+        let decoded = dissect_default(&datagram);
+        assert!(decoded.starts_with("Segmented File System, 24 bytes\n"));
+        assert!(decoded.contains("Status: 0x03 (data, last packet)"));
+        assert!(decoded.contains(".... ...1 = Data packet: yes"));
+        assert!(decoded.contains(".... ..1. = Last packet: yes"));
+        assert!(decoded.contains("File ID: 7"));
+        assert!(decoded.contains("Packet number: 258"));
+        assert!(decoded
+            .contains("Payload: 20 bytes [00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f ...]"));
+    }
+
+    #[test]
+    fn test_dissect_long_file_name_is_cut_short() {
+        let mut datagram = vec![0, 1];
+        datagram.extend(std::iter::repeat_n(b'a', 100));
+        let decoded = dissect_default(&datagram);
+        let preview = format!("File name: \"{}\"... (100 bytes)", "a".repeat(64));
+        assert!(decoded.contains(&preview), "{decoded}");
+    }
+
+    #[test]
+    fn test_dissect_malformed_datagrams() {
+        let decoded = dissect_default(&[0xF1, 2]);
+        assert!(decoded.starts_with("Segmented File System, 2 bytes [malformed]\n"));
+        assert!(decoded.contains("...1 .... = Wide packet number: yes"));
+        assert!(decoded.contains("..1. .... = Wide file ID: yes"));
+        assert!(decoded.contains(".1.. .... = Metadata: yes"));
+        assert!(decoded.contains("1... .... = Reserved: 0x80"));
+        assert!(decoded.contains("File ID: 2"));
+        assert!(decoded.contains("Error: reserved bits set in status byte 0xf1"));
+        assert!(decoded.contains("Explanation: only bit 0"));
+        assert!(decoded.contains("0000  f1 02"));
+
+        let decoded = dissect_default(&[]);
+        assert!(decoded.starts_with("Segmented File System, 0 bytes [malformed]\n"));
+        assert!(!decoded.contains("Status"));
+        assert!(decoded.contains("Error: packet is too short"));
+
+        let decoded = dissect_default(&[1, 4, 0]);
+        assert!(decoded.contains("Status: 0x01 (data)"));
+        assert!(decoded.contains("but only 3 bytes arrived"));
+    }
+
+    #[test]
+    fn test_every_parse_error_is_explained() {
+        let errors = [
+            PacketParseError::TooShort,
+            PacketParseError::ReservedBitsSet(0x80),
+            PacketParseError::EmptyFileName,
+            PacketParseError::InvalidEncoding { offset: 3 },
+            PacketParseError::DataTooShort { len: 2 },
+            PacketParseError::Oversized {
+                len: 1029,
+                max: 1028,
+            },
+            PacketParseError::BadChecksum {
+                expected: 1,
+                actual: 2,
+            },
+        ];
+        for error in errors {
+            assert!(!explain(&error).is_empty());
+        }
+        assert!(explain(&PacketParseError::ReservedBitsSet(0x81)).contains("sets 0x80"));
+        assert!(explain(&PacketParseError::InvalidEncoding { offset: 3 }).contains("offset 3"));
+        assert!(explain(&PacketParseError::Oversized {
+            len: 1033,
+            max: 1030
+        })
+        .contains("at most 1030 bytes"));
+    }
+
+    #[test]
+    fn test_run_inspect_needs_a_source() {
+        assert!(run_inspect(Vec::new()).is_err());
+        let both = vec!["capture.sfscap".to_string(), "--live".to_string()];
+        assert!(run_inspect(both).is_err());
+    }
+
+    #[test]
+    fn test_dissect_capture_follows_the_recorded_reply() {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        let reply = HelloReply {
+            version: 1,
+            capabilities: Capabilities::CHECKSUMS,
+        };
+        writer.record_at(Duration::ZERO, &reply.to_bytes()).unwrap();
+        for (i, mut packet) in [b"\x04\x07".to_vec(), b"\x03\x07\x00\x00ok".to_vec()]
+            .into_iter()
+            .enumerate()
+        {
+            append_checksum(&mut packet);
+            writer
+                .record_at(Duration::from_millis(i as u64 + 1), &packet)
+                .unwrap();
+        }
+        let capture = writer.into_inner();
+
+        let mut out = Vec::new();
+        let datagrams = CaptureReader::new(Cursor::new(capture)).unwrap();
+        dissect_capture(datagrams, &PacketParser::default(), &mut out).unwrap();
+        let decoded = String::from_utf8(out).unwrap();
+
+        assert!(decoded.contains("#1 +0.000000s"), "{decoded}");
+        assert!(decoded.contains("Capabilities: checksums"), "{decoded}");
+        // a manifest, which only a replying server sends
+        assert!(decoded.contains("File IDs: 1 (7)"), "{decoded}");
+        // the checksum isn't taken for payload
+        assert!(decoded.contains("#3 +0.002000s"), "{decoded}");
+        assert!(decoded.contains("Payload: 2 bytes"), "{decoded}");
+        assert!(!decoded.contains("[malformed]"), "{decoded}");
+    }
+
+    #[test]
+    fn test_dissect_manifest() {
+        let parser = PacketParser {
+            structured: true,
+            ..Default::default()
+        };
+        let decoded = dissect(&[0x04, 9, 3, 12], &parser);
+        assert!(decoded.contains("Status: 0x04 (manifest)"));
+        assert!(decoded.contains(".... .1.. = Manifest: yes"));
+        assert!(decoded.contains("File IDs: 3 (9, 3, 12)"));
+        assert!(!decoded.contains("File ID: "));
+
+        assert!(explain(&PacketParseError::ReservedBitsSet(0x05)).contains("can't be combined"));
+        // in the legacy format there are no manifests
+        let decoded = dissect_default(&[0x04, 9, 3, 12]);
+        assert!(decoded.contains("[malformed]"), "{decoded}");
+        assert!(
+            decoded.contains("Explanation: bit 2 (manifest)"),
+            "{decoded}"
+        );
+        assert!(decoded.contains("replied to the hello"), "{decoded}");
+        assert_eq!(
+            dissect_default(b"SFSR\x01\x00\x02"),
+            "Segmented File System hello reply, 7 bytes\n    Version: 1\n    Capabilities: checksums\n"
+        );
+    }
+
+    #[test]
+    fn test_dissect_parity() {
+        let parser = PacketParser {
+            capabilities: Capabilities::PARITY,
+            ..Default::default()
+        };
+        let decoded = dissect(&[0x0A, 4, 0, 8, 3, 0x04, 0x01, 0xAA, 0xBB], &parser);
+        assert!(decoded.contains("Status: 0x0a (parity)"), "{decoded}");
+        assert!(decoded.contains(".... 1... = Parity: yes"));
+        assert!(decoded.contains("File ID: 4"));
+        assert!(decoded.contains("Packets: 8 to 10 (3 packets, ending the file)"));
+        assert!(decoded.contains("Length XOR: 0x0401"));
+        assert!(decoded.contains("Parity: 2 bytes"));
+
+        // without the capability the parity bit is reserved
+        let decoded = dissect_default(&[0x08, 4, 0, 0, 1, 0, 0]);
+        assert!(decoded.contains("[malformed]"));
+        assert!(decoded.contains("Explanation: bit 3 (parity)"));
+    }
+
+    #[test]
+    fn test_dissect_wide_file_ids() {
+        let parser = PacketParser {
+            capabilities: Capabilities::WIDE_FILE_IDS,
+            structured: true,
+            ..Default::default()
+        };
+        let decoded = dissect(&[0x20, 0, 0, 1, 0x2C, b'w', b'i', b'd', b'e'], &parser);
+        assert!(
+            decoded.contains("..1. .... = Wide file ID: yes"),
+            "{decoded}"
+        );
+        assert!(decoded.contains("File ID: 300"));
+        assert!(decoded.contains("File name: \"wide\" (4 bytes)"));
+
+        let decoded = dissect(&[0x24, 0, 0, 1, 0xF4, 0, 0, 0, 1, 0, 0, 1, 0x2C], &parser);
+        assert!(decoded.contains("Status: 0x24 (manifest)"), "{decoded}");
+        assert!(decoded.contains("Files in the session: 500"));
+        assert!(decoded.contains("File IDs: 2 (1, 300)"));
+
+        // without the capability the wide file ID bit is reserved
+        let decoded = dissect_default(&[0x21, 0, 0, 1, 0x2C, 0, 0]);
+        assert!(
+            decoded.contains("Explanation: bit 5 (wide file ID)"),
+            "{decoded}"
+        );
+        assert!(explain(&PacketParseError::ReservedBitsSet(0x25)).contains("can't be combined"));
+    }
+
+    #[test]
+    fn test_dissect_metadata() {
+        let parser = PacketParser {
+            capabilities: Capabilities::EXTENDED_HEADER,
+            ..Default::default()
+        };
+        let mut datagram = vec![0x40, 3];
+        datagram.extend_from_slice(&1500u64.to_be_bytes());
+        datagram.extend_from_slice(&1_700_000_000i64.to_be_bytes());
+        datagram.extend_from_slice(&5u32.to_be_bytes());
+        datagram.extend_from_slice(&0o755u32.to_be_bytes());
+        datagram.extend_from_slice(b"run.sh");
+        let decoded = dissect(&datagram, &parser);
+        assert!(decoded.contains(".1.. .... = Metadata: yes"), "{decoded}");
+        assert!(decoded.contains("Size: 1500 bytes"));
+        assert!(decoded.contains("Modified: 1700000000.000000005 seconds since the epoch"));
+        assert!(decoded.contains("Mode: 0o755"));
+        assert!(decoded.contains("File name: \"run.sh\" (6 bytes)"));
+
+        // without the capability the metadata bit is reserved
+        let decoded = dissect_default(&datagram);
+        assert!(
+            decoded.contains("Explanation: bit 6 (metadata)"),
+            "{decoded}"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use segmented_file_system_client::output::{
+    create_parent_dirs, preallocate, relative_path, StreamingOutput,
+};
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sfs-output-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_chunks_written_out_of_order_land_at_their_offsets() {
+        let dir = temp_dir("order");
+        let mut output = StreamingOutput::create(&dir, 3).unwrap();
+        output.write_chunk(2, b"end").unwrap();
+        output.set_last_chunk(2, 3).unwrap();
+        output.write_chunk(0, &[0; 1024]).unwrap();
+        output.write_chunk(1, &[1; 1024]).unwrap();
+        assert_eq!(output.last_chunk_len(), Some(3));
+
+        let path = dir.join("done.bin");
+        output.trim(2 * 1024 + 3).unwrap();
+        output.temp().persist(&path).unwrap();
+        let contents = fs::read(&path).unwrap();
+        assert_eq!(contents.len(), 2 * 1024 + 3);
+        assert!(contents[..1024].iter().all(|&b| b == 0));
+        assert!(contents[1024..2048].iter().all(|&b| b == 1));
+        assert_eq!(&contents[2048..], b"end");
+        assert!(!output.temp_path().exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unfinished_output_is_removed_on_drop() {
+        let dir = temp_dir("drop");
+        let output = StreamingOutput::create(&dir, 7).unwrap();
+        let temp_path = output.temp_path().to_path_buf();
+        assert!(temp_path.exists());
+        drop(output);
+        assert!(!temp_path.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_preallocate_grows_but_never_shrinks() {
+        let dir = temp_dir("prealloc");
+        let path = dir.join("file");
+        let file = fs::File::create(&path).unwrap();
+        preallocate(&file, 0).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        preallocate(&file, 5000).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 5000);
+        preallocate(&file, 100).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 5000);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_relative_paths_are_normalized() {
+        let relative = |name: &str| relative_path(OsStr::new(name));
+        assert_eq!(relative("a.txt"), Some(PathBuf::from("a.txt")));
+        assert_eq!(
+            relative("docs/a/b.txt"),
+            Some(Path::new("docs").join("a").join("b.txt"))
+        );
+        assert_eq!(
+            relative("docs\\a\\b.txt"),
+            Some(Path::new("docs").join("a").join("b.txt"))
+        );
+        assert_eq!(
+            relative("./docs//b.txt/"),
+            Some(Path::new("docs").join("b.txt"))
+        );
+        assert_eq!(relative("..a"), Some(PathBuf::from("..a")));
+    }
+
+    #[test]
+    fn test_relative_paths_stay_inside_the_output_directory() {
+        for name in [
+            "../escape.txt",
+            "docs/../../escape.txt",
+            "docs\\..\\escape.txt",
+            "/etc/passwd",
+            "\\server\\share",
+            ".",
+            "./",
+            "//",
+        ] {
+            assert_eq!(relative_path(OsStr::new(name)), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_create_parent_dirs() {
+        let dir = temp_dir("parents");
+        create_parent_dirs(&dir, Path::new("flat.txt")).unwrap();
+        create_parent_dirs(&dir, &Path::new("a").join("b").join("c.txt")).unwrap();
+        assert!(dir.join("a").join("b").is_dir());
+        assert!(!dir.join("a").join("b").join("c.txt").exists());
+
+        // a file in the way
+        fs::write(dir.join("file"), b"").unwrap();
+        assert!(create_parent_dirs(&dir, &Path::new("file").join("x.txt")).is_err());
+
+        // a symlink to somewhere else isn't followed
+        #[cfg(unix)]
+        {
+            let outside = temp_dir("parents-outside");
+            std::os::unix::fs::symlink(&outside, dir.join("link")).unwrap();
+            let through_link = Path::new("link").join("sub").join("x.txt");
+            assert!(create_parent_dirs(&dir, &through_link).is_err());
+            assert!(!outside.join("sub").exists());
+            fs::remove_dir_all(outside).unwrap();
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
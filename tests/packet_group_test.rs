@@ -1,11 +1,14 @@
-use segmented_file_system_client::packet::{Data, Header, Packet};
-use segmented_file_system_client::packet_group::PacketGroup;
+use segmented_file_system_client::errors::PacketGroupError;
+use segmented_file_system_client::packet::{
+    Data, DataRef, FileMetadata, Header, HeaderRef, Packet, PacketRef, Parity,
+};
+use segmented_file_system_client::packet_group::{PacketGroup, ReassemblyReport};
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use std::ffi::OsString;
+    use std::ffi::{OsStr, OsString};
 
     #[test]
     fn test_process_header_sets_file_name() {
@@ -14,8 +17,9 @@ mod tests {
             file_id: 1,
             file_name: OsString::from("test_file"),
             expected_packet_count: 1,
+            metadata: None,
         });
-        packet_group.process_packet(header_packet);
+        packet_group.process_packet(header_packet).unwrap();
         assert_eq!(packet_group.file_name, Some(OsString::from("test_file")));
     }
 
@@ -28,24 +32,35 @@ mod tests {
             is_last_packet: false,
             payload: vec![1, 2, 3],
         });
-        packet_group.process_packet(data_packet);
+        packet_group.process_packet(data_packet).unwrap();
         assert_eq!(packet_group.packets.len(), 1);
     }
 
     #[test]
     fn test_received_all_packets() {
         let mut packet_group = PacketGroup::default();
-        packet_group.expected_packet_count = Some(2);
-        packet_group.packets.insert(0, vec![1, 2, 3]);
-        packet_group.packets.insert(1, vec![4, 5, 6]);
+        packet_group
+            .process_packet_ref(data_ref(1, true, &[4, 5, 6]))
+            .unwrap();
+        assert!(!packet_group.all_packets_received());
+        // a repeat doesn't count twice
+        packet_group
+            .process_packet_ref(data_ref(1, true, &[4, 5, 6]))
+            .unwrap();
+        assert!(!packet_group.all_packets_received());
+        packet_group
+            .process_packet_ref(data_ref(0, false, &[1, 2, 3]))
+            .unwrap();
         assert!(packet_group.all_packets_received());
     }
 
     #[test]
     fn test_write_file() {
-        let mut packet_group = PacketGroup::default();
-        packet_group.file_name = Some(OsString::from("test_file.txt"));
-        packet_group.expected_packet_count = Some(2);
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("test_file.txt")),
+            expected_packet_count: Some(2),
+            ..Default::default()
+        };
         packet_group.packets.insert(0, vec![1, 2, 3]);
         packet_group.packets.insert(1, vec![4, 5, 6]);
 
@@ -71,8 +86,10 @@ mod tests {
 
     #[test]
     fn test_write_file_missing_packets() {
-        let mut packet_group = PacketGroup::default();
-        packet_group.file_name = Some(OsString::from("test_file.txt"));
+        let packet_group = PacketGroup {
+            file_name: Some(OsString::from("test_file.txt")),
+            ..Default::default()
+        };
         assert!(packet_group.write_file().is_err());
     }
 
@@ -84,6 +101,7 @@ mod tests {
             file_id: 1,
             file_name: OsString::from("test_file"),
             expected_packet_count: 1,
+            metadata: None,
         });
         let data_packet = Packet::Data(Data {
             file_id: 1,
@@ -92,12 +110,779 @@ mod tests {
             payload: vec![1, 2, 3],
         });
 
-        packet_group.process_packet(header_packet);
-        packet_group.process_packet(data_packet);
+        packet_group.process_packet(header_packet).unwrap();
+        packet_group.process_packet(data_packet).unwrap();
 
         assert_eq!(packet_group.file_name, Some(OsString::from("test_file")));
         assert_eq!(packet_group.packets.len(), 1);
         assert_eq!(packet_group.packets.get(&0), Some(&vec![1, 2, 3]));
         assert_eq!(packet_group.expected_packet_count, Some(1));
     }
+
+    #[test]
+    fn test_packet_group_process_packet_ref() {
+        let mut packet_group = PacketGroup::default();
+
+        packet_group
+            .process_packet_ref(PacketRef::Data(DataRef {
+                file_id: 1,
+                packet_number: 0,
+                is_last_packet: true,
+                payload: &[1, 2, 3],
+            }))
+            .unwrap();
+        packet_group
+            .process_packet_ref(PacketRef::Header(HeaderRef {
+                file_id: 1,
+                file_name: OsStr::new("test_file").into(),
+                metadata: None,
+            }))
+            .unwrap();
+
+        assert_eq!(packet_group.file_name, Some(OsString::from("test_file")));
+        assert_eq!(packet_group.packets.get(&0), Some(&vec![1, 2, 3]));
+        assert_eq!(packet_group.expected_packet_count, Some(1));
+        assert!(packet_group.all_packets_received());
+    }
+
+    #[test]
+    fn test_duplicate_packet_ref_keeps_first_payload() {
+        let mut packet_group = PacketGroup::default();
+        for payload in [&[1, 2, 3][..], &[9, 9, 9][..]] {
+            packet_group
+                .process_packet_ref(PacketRef::Data(DataRef {
+                    file_id: 1,
+                    packet_number: 0,
+                    is_last_packet: false,
+                    payload,
+                }))
+                .unwrap();
+        }
+        assert_eq!(packet_group.packets.len(), 1);
+        assert_eq!(packet_group.packets.get(&0), Some(&vec![1, 2, 3]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_with_non_utf8_name() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let file_name = OsString::from_vec(b"caf\xE9_test.txt".to_vec());
+        let mut packet_group = PacketGroup {
+            file_name: Some(file_name.clone()),
+            expected_packet_count: Some(1),
+            ..Default::default()
+        };
+        packet_group.packets.insert(0, vec![1, 2, 3]);
+
+        std::fs::create_dir_all("src").unwrap();
+        packet_group.write_file().unwrap();
+
+        // The name on disk is the raw bytes, not a lossy replacement
+        let path = std::path::Path::new("src").join(&file_name);
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn full_chunk() -> Vec<u8> {
+        vec![7; 1024]
+    }
+
+    #[test]
+    fn test_packets_past_the_last_do_not_complete_the_file() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("gaps.txt")),
+            ..Default::default()
+        };
+        // arrives before the last packet, so it's counted until then
+        packet_group
+            .process_packet_ref(data_ref(40, false, &full_chunk()))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(0, false, &full_chunk()))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(2, true, &[1]))
+            .unwrap();
+        // stands in for the missing packet 1 if only the count is compared
+        assert!(!packet_group.all_packets_received());
+
+        let report = packet_group.validate();
+        assert!(!report.is_complete());
+        assert_eq!(report.missing_packets, vec![1..=1]);
+        assert_eq!(report.unexpected_packets, vec![40]);
+    }
+
+    #[test]
+    fn test_stray_packet_past_the_last_still_completes_the_file() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("stray.txt")),
+            ..Default::default()
+        };
+        packet_group
+            .process_packet_ref(data_ref(0, false, &full_chunk()))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(7, false, &full_chunk()))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(1, true, &[1, 2]))
+            .unwrap();
+        assert!(packet_group.all_packets_received());
+
+        let report = packet_group.validate();
+        assert!(report.is_complete());
+        assert!(!report.is_valid());
+        assert_eq!(report.unexpected_packets, vec![7]);
+        assert_eq!(report.to_string(), "packets past the last one 7");
+    }
+
+    #[test]
+    fn test_stray_last_packet_reports_missing_ranges() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("far.txt")),
+            ..Default::default()
+        };
+        for packet_number in [0, 3, 4] {
+            packet_group
+                .process_packet_ref(data_ref(packet_number, false, &full_chunk()))
+                .unwrap();
+        }
+        // valid with the large files capability, and leaves billions missing
+        packet_group
+            .process_packet_ref(data_ref(u32::MAX, true, &[1]))
+            .unwrap();
+        assert!(!packet_group.all_packets_received());
+
+        let report = packet_group.validate();
+        assert_eq!(report.missing_packets, vec![1..=2, 5..=u32::MAX - 1]);
+        assert!(report.unexpected_packets.is_empty());
+        assert_eq!(report.to_string(), "missing packets 1-2, 5-4294967294");
+        assert!(matches!(
+            packet_group.write_file(),
+            Err(PacketGroupError::MissingPacket(1))
+        ));
+    }
+
+    #[test]
+    fn test_validate_complete_file() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("valid.txt")),
+            expected_packet_count: Some(3),
+            ..Default::default()
+        };
+        packet_group.packets.insert(0, full_chunk());
+        packet_group.packets.insert(1, full_chunk());
+        packet_group.packets.insert(2, vec![1, 2]);
+
+        let report = packet_group.validate();
+        assert_eq!(report, ReassemblyReport::default());
+        assert!(report.is_valid());
+        assert_eq!(report.to_string(), "complete");
+    }
+
+    #[test]
+    fn test_validate_chunk_sizes() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("sizes.txt")),
+            expected_packet_count: Some(3),
+            ..Default::default()
+        };
+        packet_group.packets.insert(0, vec![1; 1000]);
+        packet_group.packets.insert(1, full_chunk());
+        packet_group.packets.insert(2, vec![1; 1025]);
+
+        let report = packet_group.validate();
+        assert!(report.is_complete());
+        assert!(!report.is_valid());
+        assert_eq!(report.bad_chunk_sizes, vec![(0, 1000), (2, 1025)]);
+    }
+
+    #[test]
+    fn test_validate_empty_group() {
+        let report = PacketGroup::default().validate();
+        assert!(report.missing_file_name);
+        assert!(report.missing_last_packet);
+        assert!(report.missing_packets.is_empty());
+        assert_eq!(report.to_string(), "no header packet; no last packet");
+    }
+
+    #[test]
+    fn test_validate_report_lists_ranges() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("ranges.txt")),
+            expected_packet_count: Some(10),
+            ..Default::default()
+        };
+        for packet_number in [4, 5, 8, 9] {
+            packet_group.packets.insert(packet_number, full_chunk());
+        }
+        packet_group.packets.insert(12, vec![3; 10]);
+
+        assert_eq!(
+            packet_group.validate().to_string(),
+            "missing packets 0-3, 6-7; packets past the last one 12; packet 12 has 10 bytes"
+        );
+    }
+
+    #[test]
+    fn test_write_file_skips_packets_past_the_last() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("past_last_test.txt")),
+            expected_packet_count: Some(1),
+            ..Default::default()
+        };
+        packet_group.packets.insert(0, vec![1, 2]);
+        packet_group.packets.insert(5, vec![9, 9]);
+
+        std::fs::create_dir_all("src").unwrap();
+        packet_group.write_file().unwrap();
+        assert_eq!(std::fs::read("src/past_last_test.txt").unwrap(), vec![1, 2]);
+        std::fs::remove_file("src/past_last_test.txt").unwrap();
+    }
+
+    fn strict_group() -> PacketGroup {
+        PacketGroup {
+            strict_chunks: true,
+            ..Default::default()
+        }
+    }
+
+    fn data_ref(packet_number: u32, is_last_packet: bool, payload: &[u8]) -> PacketRef<'_> {
+        PacketRef::Data(DataRef {
+            file_id: 1,
+            packet_number,
+            is_last_packet,
+            payload,
+        })
+    }
+
+    #[test]
+    fn test_strict_chunks_reject_short_and_oversized_chunks() {
+        let mut packet_group = strict_group();
+        let result = packet_group.process_packet_ref(data_ref(0, false, &[1; 1000]));
+        assert!(matches!(
+            result,
+            Err(PacketGroupError::ShortChunk {
+                packet_number: 0,
+                len: 1000
+            })
+        ));
+
+        let result = packet_group.process_packet(Packet::Data(Data {
+            file_id: 1,
+            packet_number: 1,
+            is_last_packet: true,
+            payload: vec![1; 1025],
+        }));
+        assert!(matches!(
+            result,
+            Err(PacketGroupError::OversizedChunk {
+                packet_number: 1,
+                len: 1025
+            })
+        ));
+
+        // rejected packets aren't stored
+        assert!(packet_group.packets.is_empty());
+        assert_eq!(packet_group.expected_packet_count, None);
+    }
+
+    #[test]
+    fn test_strict_chunks_reject_packets_past_the_last() {
+        let mut packet_group = strict_group();
+        packet_group
+            .process_packet_ref(data_ref(1, true, &[1, 2]))
+            .unwrap();
+
+        let result = packet_group.process_packet_ref(data_ref(2, false, &full_chunk()));
+        assert!(matches!(
+            result,
+            Err(PacketGroupError::PastLastPacket {
+                packet_number: 2,
+                last: 1
+            })
+        ));
+        let result = packet_group.process_packet_ref(data_ref(3, true, &[1]));
+        assert!(matches!(
+            result,
+            Err(PacketGroupError::ConflictingLastPacket {
+                packet_number: 3,
+                conflicts_with: 1
+            })
+        ));
+        // the same last packet again is just a duplicate
+        packet_group
+            .process_packet_ref(data_ref(1, true, &[1, 2]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_strict_chunks_reject_last_packet_below_received_ones() {
+        let mut packet_group = strict_group();
+        packet_group
+            .process_packet_ref(data_ref(5, false, &full_chunk()))
+            .unwrap();
+        let result = packet_group.process_packet_ref(data_ref(2, true, &[1]));
+        assert!(matches!(
+            result,
+            Err(PacketGroupError::ConflictingLastPacket {
+                packet_number: 2,
+                conflicts_with: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn test_lenient_chunks_accept_anything() {
+        let mut packet_group = PacketGroup::default();
+        packet_group
+            .process_packet_ref(data_ref(0, false, &[1]))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(9, false, &[1; 1025]))
+            .unwrap();
+        assert_eq!(packet_group.packets.len(), 2);
+        assert_eq!(packet_group.exact_file_size(), None);
+    }
+
+    #[test]
+    fn test_exact_file_size_known_when_last_packet_arrives() {
+        let mut packet_group = strict_group();
+        assert_eq!(packet_group.exact_file_size(), None);
+        packet_group
+            .process_packet_ref(data_ref(2, true, &[1, 2, 3]))
+            .unwrap();
+        // packets 0 and 1 haven't arrived, but their size is already known
+        assert_eq!(packet_group.exact_file_size(), Some(2 * 1024 + 3));
+    }
+
+    #[test]
+    fn test_strict_write_file() {
+        let mut packet_group = strict_group();
+        packet_group.file_name = Some(OsString::from("strict_test.txt"));
+        packet_group
+            .process_packet_ref(data_ref(1, true, b"end"))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(0, false, &full_chunk()))
+            .unwrap();
+
+        std::fs::create_dir_all("src").unwrap();
+        packet_group.write_file().unwrap();
+        let written = std::fs::metadata("src/strict_test.txt").unwrap().len();
+        assert_eq!(Some(written), packet_group.exact_file_size());
+        std::fs::remove_file("src/strict_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_strict_write_file_detects_size_mismatch() {
+        // chunks put in directly skip the strict checks
+        let mut packet_group = strict_group();
+        packet_group.file_name = Some(OsString::from("mismatch_test.txt"));
+        packet_group.expected_packet_count = Some(2);
+        packet_group.packets.insert(0, vec![1; 10]);
+        packet_group.packets.insert(1, vec![2; 10]);
+
+        std::fs::create_dir_all("src").unwrap();
+        let result = packet_group.write_file();
+        assert!(matches!(
+            result,
+            Err(PacketGroupError::SizeMismatch {
+                expected: 1034,
+                written: 20,
+                ..
+            })
+        ));
+        // the short file never shows up under its name
+        assert!(!std::path::Path::new("src/mismatch_test.txt").exists());
+    }
+
+    #[test]
+    fn test_streaming_writes_chunks_out_of_order() {
+        let mut packet_group = PacketGroup {
+            streaming: true,
+            ..Default::default()
+        };
+        std::fs::create_dir_all("src").unwrap();
+        packet_group
+            .process_packet_ref(data_ref(2, true, b"end"))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(0, false, &[0; 1024]))
+            .unwrap();
+        // duplicates are ignored, like the in-memory mode
+        packet_group
+            .process_packet_ref(data_ref(0, false, &[9; 1024]))
+            .unwrap();
+        packet_group
+            .process_packet(Packet::Data(Data {
+                file_id: 1,
+                packet_number: 1,
+                is_last_packet: false,
+                payload: vec![1; 1024],
+            }))
+            .unwrap();
+        packet_group.file_name = Some(OsString::from("stream_test.txt"));
+        assert!(packet_group.all_packets_received());
+        assert!(packet_group.validate().is_valid());
+        assert_eq!(packet_group.exact_file_size(), Some(2 * 1024 + 3));
+
+        let temp_path = packet_group
+            .stream
+            .as_ref()
+            .unwrap()
+            .temp_path()
+            .to_path_buf();
+        packet_group.write_file().unwrap();
+        let contents = std::fs::read("src/stream_test.txt").unwrap();
+        assert_eq!(contents.len(), 2 * 1024 + 3);
+        assert!(contents[..1024].iter().all(|&b| b == 0));
+        assert!(contents[1024..2048].iter().all(|&b| b == 1));
+        assert_eq!(&contents[2048..], b"end");
+        assert!(!temp_path.exists());
+        std::fs::remove_file("src/stream_test.txt").unwrap();
+    }
+
+    #[test]
+    fn test_streaming_applies_strict_chunk_rules() {
+        let mut packet_group = PacketGroup {
+            streaming: true,
+            ..Default::default()
+        };
+        let result = packet_group.process_packet_ref(data_ref(0, false, &[1; 10]));
+        assert!(matches!(result, Err(PacketGroupError::ShortChunk { .. })));
+        // nothing was accepted, so no temporary file was made
+        assert!(packet_group.stream.is_none());
+    }
+
+    #[test]
+    fn test_ack_reports_received_packets() {
+        let mut packet_group = PacketGroup::default();
+        assert_eq!(packet_group.ack(3).cumulative, 0);
+        assert!(packet_group.ack(3).selective.is_empty());
+
+        for packet_number in [0, 1, 3, 10] {
+            packet_group
+                .process_packet_ref(data_ref(packet_number, false, b"x"))
+                .unwrap();
+        }
+        packet_group.file_name = Some(OsString::from("ack_test.txt"));
+
+        let ack = packet_group.ack(3);
+        assert_eq!(ack.file_id, 3);
+        assert!(ack.has_header);
+        assert_eq!(ack.cumulative, 2);
+        // packets 3 to 10 are bits 0 to 7
+        assert_eq!(ack.selective, [0b1000_0001]);
+        let acked: Vec<u32> = (0..16).filter(|&n| ack.has_packet(n)).collect();
+        assert_eq!(acked, [0, 1, 3, 10]);
+    }
+
+    #[test]
+    fn test_parity_rebuilds_one_lost_chunk() {
+        let chunks: [&[u8]; 3] = [&[1; 1024], &[2; 1024], b"tail"];
+        let mut packet_group = strict_group();
+        // the short last chunk is the one lost, so its length and the last
+        // packet mark both come from the parity packet
+        packet_group
+            .process_packet(Packet::Parity(Parity::new(1, 0, &chunks, true)))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(0, false, chunks[0]))
+            .unwrap();
+        assert!(!packet_group.packets.contains_key(&2));
+        packet_group
+            .process_packet_ref(data_ref(1, false, chunks[1]))
+            .unwrap();
+
+        assert_eq!(packet_group.recovered, 1);
+        assert_eq!(packet_group.packets[&2], b"tail");
+        assert_eq!(packet_group.expected_packet_count, Some(3));
+        assert!(packet_group.all_packets_received());
+        assert!(packet_group.parity.is_empty());
+    }
+
+    #[test]
+    fn test_parity_waits_until_one_chunk_is_missing() {
+        let chunks: [&[u8]; 3] = [b"a", b"b", b"c"];
+        let mut packet_group = PacketGroup::default();
+        packet_group
+            .process_packet_ref(data_ref(0, false, chunks[0]))
+            .unwrap();
+        packet_group
+            .process_packet(Packet::Parity(Parity::new(1, 0, &chunks, false)))
+            .unwrap();
+        assert_eq!(packet_group.recovered, 0);
+        assert_eq!(packet_group.packets.len(), 1);
+        assert_eq!(packet_group.parity.len(), 1);
+
+        // once every chunk has arrived the parity packet is no longer needed
+        packet_group
+            .process_packet_ref(data_ref(1, false, chunks[1]))
+            .unwrap();
+        assert_eq!(packet_group.recovered, 1);
+        assert_eq!(packet_group.packets[&2], b"c");
+        assert!(packet_group.parity.is_empty());
+    }
+
+    #[test]
+    fn test_skipped_counts_packet_numbers_jumped_over() {
+        let mut packet_group = PacketGroup::default();
+        for packet_number in [1, 2, 5, 3, 4, 6] {
+            packet_group
+                .process_packet_ref(data_ref(packet_number, false, b"x"))
+                .unwrap();
+        }
+        // 0 before the first packet, then 3 and 4; filling them in later
+        // doesn't undo it
+        assert_eq!(packet_group.skipped, 3);
+        assert_eq!(packet_group.highest_packet_number, Some(6));
+    }
+
+    #[test]
+    fn test_write_file_past_16_bit_packet_numbers() {
+        // chunks can be any size without strict checks, which keeps this
+        // file small
+        let count = 0x1_0002;
+        let mut packet_group = PacketGroup::default();
+        for packet_number in 0..count {
+            let payload = [(packet_number % 251) as u8];
+            packet_group
+                .process_packet_ref(data_ref(
+                    packet_number,
+                    packet_number == count - 1,
+                    &payload,
+                ))
+                .unwrap();
+        }
+        packet_group.file_name = Some(OsString::from("wide_test.bin"));
+        assert_eq!(packet_group.expected_packet_count, Some(0x1_0002));
+        assert!(packet_group.all_packets_received());
+
+        std::fs::create_dir_all("src").unwrap();
+        packet_group.write_file().unwrap();
+        let written = std::fs::read("src/wide_test.bin").unwrap();
+        std::fs::remove_file("src/wide_test.bin").unwrap();
+        assert_eq!(written.len(), 0x1_0002);
+        assert_eq!(written[0x1_0001], (0x1_0001 % 251) as u8);
+    }
+
+    #[test]
+    fn test_write_file_restores_metadata() {
+        let mut packet_group = PacketGroup::default();
+        packet_group
+            .process_packet(Packet::Header(Header {
+                file_id: 1,
+                file_name: OsString::from("metadata_test.sh"),
+                expected_packet_count: 0,
+                metadata: Some(FileMetadata {
+                    size: 3,
+                    mtime: 1_000_000_000,
+                    mtime_nanos: 0,
+                    // setuid is never applied
+                    mode: 0o4500,
+                }),
+            }))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(0, true, b"abc"))
+            .unwrap();
+        assert!(packet_group.validate().is_valid());
+
+        std::fs::create_dir_all("src").unwrap();
+        packet_group.write_file().unwrap();
+        let written = std::fs::metadata("src/metadata_test.sh").unwrap();
+        let modified = written.modified().unwrap();
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+
+            written.permissions().mode() & 0o7777
+        };
+        std::fs::remove_file("src/metadata_test.sh").unwrap();
+        assert_eq!(
+            modified,
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000)
+        );
+        #[cfg(unix)]
+        assert_eq!(mode, 0o500);
+    }
+
+    #[test]
+    fn test_write_file_replaces_a_read_only_file() {
+        let mut packet_group = PacketGroup::default();
+        packet_group
+            .process_packet(Packet::Header(Header {
+                file_id: 1,
+                file_name: OsString::from("read_only_test.txt"),
+                expected_packet_count: 0,
+                metadata: Some(FileMetadata {
+                    size: 3,
+                    mtime: 1_000_000_000,
+                    mtime_nanos: 0,
+                    mode: 0o444,
+                }),
+            }))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(0, true, b"abc"))
+            .unwrap();
+
+        // the second run finds the first one's read-only copy in the way
+        std::fs::create_dir_all("src").unwrap();
+        packet_group.write_file().unwrap();
+        let result = packet_group.write_file();
+        let written = std::fs::read("src/read_only_test.txt").unwrap();
+        std::fs::remove_file("src/read_only_test.txt").unwrap();
+        result.unwrap();
+        assert_eq!(written, b"abc");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_replaces_a_symlink_instead_of_following_it() {
+        let outside =
+            std::env::temp_dir().join(format!("sfs_symlink_target_{}", std::process::id()));
+        std::fs::write(&outside, b"outside").unwrap();
+        std::fs::create_dir_all("src").unwrap();
+        let _ = std::fs::remove_file("src/symlink_test.txt");
+        std::os::unix::fs::symlink(&outside, "src/symlink_test.txt").unwrap();
+
+        let mut packet_group = PacketGroup::default();
+        packet_group
+            .process_packet(Packet::Header(Header {
+                file_id: 1,
+                file_name: OsString::from("symlink_test.txt"),
+                expected_packet_count: 0,
+                metadata: Some(FileMetadata {
+                    size: 3,
+                    mtime: 1_000_000_000,
+                    mtime_nanos: 0,
+                    mode: 0o600,
+                }),
+            }))
+            .unwrap();
+        packet_group
+            .process_packet_ref(data_ref(0, true, b"abc"))
+            .unwrap();
+        let result = packet_group.write_file();
+
+        let link = std::fs::symlink_metadata("src/symlink_test.txt").unwrap();
+        let written = std::fs::read("src/symlink_test.txt").unwrap();
+        let untouched = std::fs::read(&outside).unwrap();
+        let modified = std::fs::metadata(&outside).unwrap().modified().unwrap();
+        std::fs::remove_file("src/symlink_test.txt").unwrap();
+        std::fs::remove_file(&outside).unwrap();
+        result.unwrap();
+        assert!(link.file_type().is_file());
+        assert_eq!(written, b"abc");
+        assert_eq!(untouched, b"outside");
+        assert_ne!(
+            modified,
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000)
+        );
+    }
+
+    #[test]
+    fn test_write_file_checks_the_size_from_the_header() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("wrong_size_test.txt")),
+            metadata: Some(FileMetadata {
+                size: 4,
+                mtime: 0,
+                mtime_nanos: 0,
+                mode: 0o644,
+            }),
+            ..Default::default()
+        };
+        packet_group
+            .process_packet_ref(data_ref(0, true, b"abc"))
+            .unwrap();
+
+        let report = packet_group.validate();
+        assert!(report.is_complete());
+        assert!(!report.is_valid());
+        assert_eq!(report.wrong_size, Some((4, 3)));
+        assert_eq!(
+            report.to_string(),
+            "the header says 4 bytes, the chunks add up to 3"
+        );
+
+        std::fs::create_dir_all("src").unwrap();
+        assert!(matches!(
+            packet_group.write_file(),
+            Err(PacketGroupError::WrongSize {
+                expected: 4,
+                received: 3,
+            })
+        ));
+        assert!(!std::path::Path::new("src/wrong_size_test.txt").exists());
+    }
+
+    #[test]
+    fn test_write_file_creates_directories() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("tree_test/a\\b.txt")),
+            ..Default::default()
+        };
+        packet_group
+            .process_packet_ref(data_ref(0, true, b"leaf"))
+            .unwrap();
+
+        std::fs::create_dir_all("src").unwrap();
+        packet_group.write_file().unwrap();
+        let written = std::fs::read("src/tree_test/a/b.txt").unwrap();
+        std::fs::remove_dir_all("src/tree_test").unwrap();
+        assert_eq!(written, b"leaf");
+    }
+
+    #[test]
+    fn test_write_file_rejects_traversal() {
+        let absolute = std::env::temp_dir().join("sfs_traversal_test.txt");
+        let names = [
+            OsString::from("../traversal_test.txt"),
+            OsString::from("docs/../../traversal_test.txt"),
+            OsString::from("..\\traversal_test.txt"),
+            absolute.clone().into_os_string(),
+            OsString::from("."),
+        ];
+
+        std::fs::create_dir_all("src").unwrap();
+        for file_name in names {
+            let mut packet_group = PacketGroup {
+                file_name: Some(file_name.clone()),
+                ..Default::default()
+            };
+            packet_group
+                .process_packet_ref(data_ref(0, true, b"nope"))
+                .unwrap();
+
+            let result = packet_group.write_file();
+            assert!(
+                matches!(&result, Err(PacketGroupError::UnsafePath(name)) if *name == file_name),
+                "{file_name:?} gave {result:?}"
+            );
+        }
+        assert!(!std::path::Path::new("traversal_test.txt").exists());
+        assert!(!absolute.exists());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_write_file_rejects_drive_prefix() {
+        let mut packet_group = PacketGroup {
+            file_name: Some(OsString::from("C:traversal_test.txt")),
+            ..Default::default()
+        };
+        packet_group
+            .process_packet_ref(data_ref(0, true, b"nope"))
+            .unwrap();
+
+        std::fs::create_dir_all("src").unwrap();
+        assert!(matches!(
+            packet_group.write_file(),
+            Err(PacketGroupError::UnsafePath(_))
+        ));
+    }
 }
@@ -0,0 +1,237 @@
+use segmented_file_system_client::hello::Capabilities;
+use segmented_file_system_client::packet::{
+    append_checksum, Data, FileId, FileMetadata, Header, Manifest, Packet, PacketParser, Parity,
+    MAX_MANIFEST_PART_IDS, MAX_PACKET_SIZE, MAX_PAYLOAD_SIZE,
+};
+
+use proptest::prelude::*;
+use std::ffi::OsString;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // Any byte string is a valid name on Unix; elsewhere names only survive
+    // a round trip if they are already UTF-8
+    #[cfg(unix)]
+    fn file_name() -> impl Strategy<Value = OsString> {
+        use std::os::unix::ffi::OsStringExt;
+
+        prop::collection::vec(any::<u8>(), 1..=MAX_PACKET_SIZE - 2).prop_map(OsString::from_vec)
+    }
+
+    #[cfg(not(unix))]
+    fn file_name() -> impl Strategy<Value = OsString> {
+        "\\PC{1,200}".prop_map(OsString::from)
+    }
+
+    // file IDs that fit the legacy 1 byte format
+    fn narrow_file_id() -> impl Strategy<Value = FileId> {
+        any::<u8>().prop_map(FileId::from)
+    }
+
+    fn header(file_ids: impl Strategy<Value = FileId>) -> impl Strategy<Value = Header> {
+        (file_ids, file_name()).prop_map(|(file_id, file_name)| Header {
+            file_id,
+            file_name,
+            expected_packet_count: 0,
+            metadata: None,
+        })
+    }
+
+    // a header with metadata, the extended header format
+    fn metadata_header() -> impl Strategy<Value = Header> {
+        (
+            any::<FileId>(),
+            file_name(),
+            (any::<u64>(), any::<i64>(), any::<u32>(), any::<u32>()),
+        )
+            .prop_map(
+                |(file_id, file_name, (size, mtime, mtime_nanos, mode))| Header {
+                    file_id,
+                    file_name,
+                    expected_packet_count: 0,
+                    metadata: Some(FileMetadata {
+                        size,
+                        mtime,
+                        mtime_nanos,
+                        mode,
+                    }),
+                },
+            )
+    }
+
+    // `packet_numbers` picks between the legacy and the wide format
+    fn data(
+        file_ids: impl Strategy<Value = FileId>,
+        packet_numbers: impl Strategy<Value = u32>,
+    ) -> impl Strategy<Value = Data> {
+        (
+            file_ids,
+            packet_numbers,
+            any::<bool>(),
+            prop::collection::vec(any::<u8>(), 0..=MAX_PACKET_SIZE - 4),
+        )
+            .prop_map(|(file_id, packet_number, is_last_packet, payload)| Data {
+                file_id,
+                packet_number,
+                is_last_packet,
+                payload,
+            })
+    }
+
+    fn manifest() -> impl Strategy<Value = Manifest> {
+        prop::collection::vec(any::<u8>(), 1..=MAX_PACKET_SIZE - 1).prop_map(|file_ids| Manifest {
+            file_ids: file_ids.into_iter().map(FileId::from).collect(),
+            total: None,
+        })
+    }
+
+    // a part of a wide manifest
+    fn wide_manifest() -> impl Strategy<Value = Manifest> {
+        (
+            prop::collection::vec(any::<FileId>(), 1..=MAX_MANIFEST_PART_IDS),
+            any::<u32>(),
+        )
+            .prop_map(|(file_ids, total)| Manifest {
+                file_ids,
+                total: Some(total),
+            })
+    }
+
+    fn parity() -> impl Strategy<Value = Parity> {
+        (
+            (any::<FileId>(), any::<u32>(), any::<u8>()),
+            any::<bool>(),
+            any::<u16>(),
+            prop::collection::vec(any::<u8>(), 0..=MAX_PAYLOAD_SIZE),
+        )
+            .prop_map(
+                |((file_id, first_packet_number, count), is_last_group, length_xor, payload)| {
+                    Parity {
+                        file_id,
+                        first_packet_number,
+                        count,
+                        is_last_group,
+                        length_xor,
+                        payload,
+                    }
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn test_header_round_trip(header in header(narrow_file_id())) {
+            let bytes = header.to_bytes();
+            prop_assert_eq!(Header::try_from(bytes.as_slice()).unwrap(), header);
+        }
+
+        #[test]
+        fn test_data_round_trip(data in data(narrow_file_id(), any::<u32>())) {
+            let bytes = data.to_bytes();
+            prop_assert_eq!(Data::try_from(bytes.as_slice()).unwrap(), data);
+        }
+
+        #[test]
+        fn test_packet_round_trip(packet in prop_oneof![
+            header(narrow_file_id()).prop_map(Packet::Header),
+            data(narrow_file_id(), any::<u16>().prop_map(u32::from)).prop_map(Packet::Data),
+            manifest().prop_map(Packet::Manifest),
+        ]) {
+            let parser = PacketParser {
+                structured: true,
+                ..Default::default()
+            };
+            let bytes = packet.to_bytes();
+            prop_assert_eq!(Packet::from(parser.parse(&bytes).unwrap()), packet);
+        }
+
+        #[test]
+        fn test_wide_data_round_trip(
+            data in data(narrow_file_id(), u32::from(u16::MAX) + 1..=u32::MAX)
+        ) {
+            let parser = PacketParser {
+                capabilities: Capabilities::LARGE_FILES,
+                ..Default::default()
+            };
+            let bytes = data.to_bytes();
+            prop_assert_eq!(Packet::from(parser.parse(&bytes).unwrap()), Packet::Data(data));
+        }
+
+        #[test]
+        fn test_wide_file_id_round_trip(packet in prop_oneof![
+            header(any::<FileId>()).prop_map(Packet::Header),
+            data(any::<FileId>(), any::<u32>()).prop_map(Packet::Data),
+            wide_manifest().prop_map(Packet::Manifest),
+        ]) {
+            let parser = PacketParser {
+                capabilities: Capabilities::LARGE_FILES.union(Capabilities::WIDE_FILE_IDS),
+                structured: true,
+                ..Default::default()
+            };
+            let bytes = packet.to_bytes();
+            prop_assert_eq!(Packet::from(parser.parse(&bytes).unwrap()), packet);
+        }
+
+        #[test]
+        fn test_metadata_header_round_trip(header in metadata_header()) {
+            let parser = PacketParser {
+                capabilities: Capabilities::EXTENDED_HEADER
+                    .union(Capabilities::WIDE_FILE_IDS)
+                    .union(Capabilities::CHECKSUMS),
+                ..Default::default()
+            };
+            let mut bytes = header.to_bytes();
+            append_checksum(&mut bytes);
+            prop_assert_eq!(Packet::from(parser.parse(&bytes).unwrap()), Packet::Header(header));
+        }
+
+        #[test]
+        fn test_parity_round_trip(parity in parity()) {
+            let parser = PacketParser {
+                capabilities: Capabilities::PARITY
+                    .union(Capabilities::LARGE_FILES)
+                    .union(Capabilities::WIDE_FILE_IDS),
+                ..Default::default()
+            };
+            let bytes = parity.to_bytes();
+            prop_assert_eq!(Packet::from(parser.parse(&bytes).unwrap()), Packet::Parity(parity));
+        }
+
+        // the other direction: whatever parses encodes back to the same bytes,
+        // apart from the status byte since headers ignore the last packet bit
+        #[test]
+        fn test_parsed_datagrams_encode_back(
+            datagram in prop::collection::vec(any::<u8>(), 0..=MAX_PACKET_SIZE + 2)
+        ) {
+            let datagram = datagram.as_slice();
+            if let Ok(packet) = Packet::try_from(datagram) {
+                // on other platforms a non-UTF-8 name is re-encoded on purpose
+                if cfg!(unix) || matches!(packet, Packet::Data(_)) {
+                    prop_assert_eq!(&packet.to_bytes()[1..], &datagram[1..]);
+                }
+            }
+        }
+
+        #[test]
+        fn test_parser_never_panics(
+            datagram in prop::collection::vec(any::<u8>(), 0..2048),
+            fallback in prop_oneof![
+                Just("lossy"),
+                Just("escape"),
+                Just("reject"),
+            ],
+            capabilities in any::<u16>(),
+            structured in any::<bool>(),
+        ) {
+            let parser = PacketParser {
+                file_name_fallback: fallback.parse().unwrap(),
+                capabilities: Capabilities::from_bits(capabilities),
+                structured,
+            };
+            let _ = parser.parse(&datagram);
+        }
+    }
+}
@@ -4,10 +4,15 @@ use segmented_file_system_client::packet::Packet;
 #[cfg(test)]
 mod tests {
 
-    use segmented_file_system_client::packet::Header;
+    use segmented_file_system_client::hello::Capabilities;
+    use segmented_file_system_client::packet::{
+        append_checksum, percent_escape, Ack, Data, DataRef, Feedback, FileMetadata,
+        FileNameFallback, Header, HeaderRef, Manifest, ManifestRef, PacketParser, PacketRef,
+        Parity, ParityRef,
+    };
 
     use super::*;
-    use std::ffi::OsString;
+    use std::ffi::{OsStr, OsString};
 
     #[test]
     fn test_parse_header_packet() {
@@ -37,16 +42,103 @@ mod tests {
 
     #[test]
     fn test_invalid_packet_format() {
-        let raw_data: &[u8] = &[0, 1]; // Too short
+        let raw_data: &[u8] = &[0]; // Too short
         let result = Packet::try_from(raw_data);
         assert!(matches!(result, Err(PacketParseError::TooShort)));
     }
 
+    #[test]
+    fn test_empty_file_name() {
+        let raw_data: &[u8] = &[0, 1]; // Header with nothing after the file ID
+        let result = Packet::try_from(raw_data);
+        assert!(matches!(result, Err(PacketParseError::EmptyFileName)));
+    }
+
+    #[test]
+    fn test_short_data_packet() {
+        let raw_data: &[u8] = &[1, 1, 0]; // Missing the second packet number byte
+        let result = Packet::try_from(raw_data);
+        assert!(matches!(
+            result,
+            Err(PacketParseError::DataTooShort { len: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_oversized_datagram() {
+        let mut raw_data = vec![1, 1, 0, 1];
+        raw_data.extend(vec![b'x'; 1025]); // one byte too many
+        let result = Packet::try_from(raw_data.as_slice());
+        assert!(matches!(
+            result,
+            Err(PacketParseError::Oversized {
+                len: 1029,
+                max: 1028
+            })
+        ));
+    }
+
+    #[test]
+    fn test_reserved_bits_report_status_byte() {
+        // 0x05 and 0x06 combine the manifest bit with the others
+        for status in [0x05, 0x06, 0x08, 0x80, 0xFD] {
+            let raw_data: &[u8] = &[status, 1, 0, 0, b'x'];
+            let result = Packet::try_from(raw_data);
+            assert_eq!(
+                result.unwrap_err(),
+                PacketParseError::ReservedBitsSet(status)
+            );
+        }
+    }
+
+    #[cfg(unix)]
     #[test]
     fn test_invalid_utf8_in_header() {
+        use std::os::unix::ffi::OsStringExt;
+
+        // On Unix any bytes make a valid file name, so they are kept as-is
         let raw_data: &[u8] = &[0, 1, 0xFF, 0xFE, 0xFD]; // Invalid UTF-8 bytes
         let result = Packet::try_from(raw_data);
-        assert!(matches!(result, Err(PacketParseError::InvalidPacketFormat)));
+        if let Ok(Packet::Header(header)) = result {
+            assert_eq!(header.file_name, OsString::from_vec(vec![0xFF, 0xFE, 0xFD]));
+        } else {
+            panic!("Expected Header packet");
+        }
+    }
+
+    #[cfg(not(unix))]
+    #[test]
+    fn test_invalid_utf8_in_header() {
+        let raw_data: &[u8] = &[0, 1, 0xFF, 0xFE, 0xFD]; // Invalid UTF-8 bytes
+        let parser = PacketParser {
+            file_name_fallback: FileNameFallback::Reject,
+            ..Default::default()
+        };
+        let result = parser.parse(raw_data);
+        assert!(matches!(
+            result,
+            Err(PacketParseError::InvalidEncoding { offset: 2 })
+        ));
+
+        let parser = PacketParser {
+            file_name_fallback: FileNameFallback::PercentEscape,
+            ..Default::default()
+        };
+        if let Ok(PacketRef::Header(header)) = parser.parse(raw_data) {
+            assert_eq!(header.file_name, OsStr::new("%FF%FE%FD"));
+        } else {
+            panic!("Expected Header packet");
+        }
+
+        let parser = PacketParser {
+            file_name_fallback: FileNameFallback::Lossy,
+            ..Default::default()
+        };
+        if let Ok(PacketRef::Header(header)) = parser.parse(raw_data) {
+            assert_eq!(header.file_name, OsStr::new("\u{FFFD}\u{FFFD}\u{FFFD}"));
+        } else {
+            panic!("Expected Header packet");
+        }
     }
 
     #[test]
@@ -64,7 +156,7 @@ mod tests {
         if let Packet::Data(data) = packet {
             assert_eq!(data.file_id, 1);
             assert_eq!(data.packet_number, 0);
-            assert_eq!(data.is_last_packet, false);
+            assert!(!data.is_last_packet);
             assert!(data.payload.is_empty());
         } else {
             panic!("Expected Data packet");
@@ -93,7 +185,7 @@ mod tests {
         if let Packet::Data(data) = packet {
             assert_eq!(data.file_id, 1);
             assert_eq!(data.packet_number, 1);
-            assert_eq!(data.is_last_packet, false);
+            assert!(!data.is_last_packet);
             assert_eq!(data.payload.len(), 1024);
         } else {
             panic!("Expected Data packet");
@@ -110,7 +202,558 @@ mod tests {
                 file_id: 12,
                 file_name: "This file is lovely 💖".to_string().into(),
                 expected_packet_count: 0,
+                metadata: None,
             }
         );
     }
+
+    #[test]
+    fn test_parse_packet_ref_borrows_payload() {
+        let raw_data: &[u8] = &[3, 1, 0, 1, b'd', b'a', b't', b'a'];
+        let packet = PacketRef::try_from(raw_data).unwrap();
+        if let PacketRef::Data(data) = packet {
+            assert_eq!(data.file_id, 1);
+            assert_eq!(data.packet_number, 1);
+            assert!(data.is_last_packet);
+            assert_eq!(data.payload, b"data");
+            // The payload points into the original buffer rather than a copy
+            assert!(std::ptr::eq(data.payload.as_ptr(), raw_data[4..].as_ptr()));
+        } else {
+            panic!("Expected Data packet");
+        }
+    }
+
+    #[test]
+    fn test_parse_header_ref() {
+        let raw_data: &[u8] = &[0, 1, b't', b'e', b's', b't'];
+        let packet = PacketRef::try_from(raw_data).unwrap();
+        assert_eq!(
+            packet,
+            PacketRef::Header(HeaderRef {
+                file_id: 1,
+                file_name: OsStr::new("test").into(),
+                metadata: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_packet_ref_errors_match_packet() {
+        let too_short: &[u8] = &[0];
+        assert!(matches!(
+            PacketRef::try_from(too_short),
+            Err(PacketParseError::TooShort)
+        ));
+        let bad_status: &[u8] = &[0xFF, 1, 0, 0];
+        assert!(matches!(
+            PacketRef::try_from(bad_status),
+            Err(PacketParseError::ReservedBitsSet(0xFF))
+        ));
+    }
+
+    #[test]
+    fn test_owned_conversion_from_ref() {
+        let data_ref = DataRef {
+            file_id: 4,
+            packet_number: 9,
+            is_last_packet: false,
+            payload: b"abc",
+        };
+        let packet = Packet::from(PacketRef::Data(data_ref));
+        if let Packet::Data(data) = packet {
+            assert_eq!(data.file_id, 4);
+            assert_eq!(data.packet_number, 9);
+            assert!(!data.is_last_packet);
+            assert_eq!(data.payload, b"abc".to_vec());
+        } else {
+            panic!("Expected Data packet");
+        }
+    }
+
+    #[test]
+    fn test_percent_escape() {
+        assert_eq!(percent_escape(b"plain.txt"), "plain.txt");
+        assert_eq!(percent_escape(b"caf\xE9.txt"), "caf%E9.txt");
+        // A literal % is escaped too so the two can't be confused
+        assert_eq!(percent_escape(b"100%.txt"), "100%25.txt");
+        assert_eq!(percent_escape(&[0xF0, 0x9F, 0x92, 0x96, 0xFF]), "💖%FF");
+    }
+
+    #[test]
+    fn test_parse_file_name_fallback() {
+        assert_eq!("lossy".parse(), Ok(FileNameFallback::Lossy));
+        assert_eq!("escape".parse(), Ok(FileNameFallback::PercentEscape));
+        assert_eq!("reject".parse(), Ok(FileNameFallback::Reject));
+        assert!("bogus".parse::<FileNameFallback>().is_err());
+    }
+
+    #[test]
+    fn test_utf8_file_name_is_borrowed() {
+        let raw_data: &[u8] = &[0, 1, b't', b'e', b's', b't'];
+        let header = HeaderRef::try_from(raw_data).unwrap();
+        assert!(matches!(header.file_name, std::borrow::Cow::Borrowed(_)));
+
+        let parser = PacketParser {
+            file_name_fallback: FileNameFallback::Reject,
+            ..Default::default()
+        };
+        assert_eq!(parser.parse(raw_data).unwrap(), PacketRef::Header(header));
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let parser = PacketParser {
+            structured: true,
+            ..Default::default()
+        };
+        let raw_data: &[u8] = &[0x04, 7, 2, 200];
+        let packet = parser.parse(raw_data).unwrap();
+        assert_eq!(
+            packet,
+            PacketRef::Manifest(ManifestRef {
+                ids: &[7, 2, 200],
+                total: None,
+            })
+        );
+        assert_eq!(packet.file_id(), None);
+
+        let manifest = Manifest {
+            file_ids: vec![7, 2, 200],
+            total: None,
+        };
+        assert_eq!(Packet::from(packet), Packet::Manifest(manifest));
+        assert_eq!(
+            Manifest {
+                file_ids: vec![7, 2, 200],
+                total: None,
+            }
+            .to_bytes(),
+            raw_data
+        );
+
+        // a manifest lists at least one file
+        assert_eq!(
+            parser.parse(&[0x04][..]).unwrap_err(),
+            PacketParseError::TooShort
+        );
+
+        // a legacy server never sends one, so the bit is reserved until a
+        // server has replied to the hello
+        assert_eq!(
+            PacketRef::try_from(raw_data).unwrap_err(),
+            PacketParseError::ReservedBitsSet(0x04)
+        );
+    }
+
+    #[test]
+    fn test_checksums_capability() {
+        let parser = PacketParser {
+            capabilities: Capabilities::CHECKSUMS,
+            ..Default::default()
+        };
+        let mut datagram = vec![3, 1, 0, 2, b'h', b'i'];
+        append_checksum(&mut datagram);
+        assert_eq!(datagram.len(), 8);
+        assert_eq!(
+            parser.parse(&datagram).unwrap(),
+            PacketRef::Data(DataRef {
+                file_id: 1,
+                packet_number: 2,
+                is_last_packet: true,
+                payload: b"hi",
+            })
+        );
+
+        datagram[5] = b'o';
+        assert!(matches!(
+            parser.parse(&datagram),
+            Err(PacketParseError::BadChecksum { .. })
+        ));
+
+        // a full packet plus its checksum fits, one more byte doesn't
+        let mut full = vec![1, 1, 0, 0];
+        full.resize(1028, 0xAB);
+        append_checksum(&mut full);
+        assert!(parser.parse(&full).is_ok());
+        full.push(0);
+        assert_eq!(
+            parser.parse(&full).unwrap_err(),
+            PacketParseError::Oversized {
+                len: 1031,
+                max: 1030
+            }
+        );
+
+        // with parity a parity packet may be longer, but the limit reported
+        // is the one for the data packet that arrived, checksum included
+        let parser = PacketParser {
+            capabilities: Capabilities::CHECKSUMS.union(Capabilities::PARITY),
+            ..Default::default()
+        };
+        let mut data = vec![1, 1, 0, 0];
+        data.resize(1029, 0xAB);
+        append_checksum(&mut data);
+        assert_eq!(
+            parser.parse(&data).unwrap_err(),
+            PacketParseError::Oversized {
+                len: 1031,
+                max: 1030
+            }
+        );
+    }
+
+    #[test]
+    fn test_ack_round_trip() {
+        let ack = Ack {
+            file_id: 7,
+            has_header: true,
+            has_manifest: false,
+            cumulative: 3,
+            selective: vec![0b0000_0101],
+        };
+        let bytes = ack.to_bytes();
+        assert_eq!(bytes, [0x80, 7, 0x01, 0, 0, 0, 3, 0b0000_0101]);
+        assert_eq!(Ack::parse(&bytes).unwrap(), ack);
+
+        // 0, 1, 2 below the cumulative point, then 4 and 6 from the bitmap
+        let acked: Vec<u32> = (0..12).filter(|&n| ack.has_packet(n)).collect();
+        assert_eq!(acked, [0, 1, 2, 4, 6]);
+        assert!(!ack.has_packet(u32::MAX));
+    }
+
+    #[test]
+    fn test_feedback_round_trip() {
+        let feedback = Feedback { rate: 4500 };
+        let bytes = feedback.to_bytes();
+        assert_eq!(bytes, [0x81, 0, 0, 0x11, 0x94]);
+        assert_eq!(Feedback::parse(&bytes).unwrap(), feedback);
+
+        assert_eq!(
+            Feedback::parse(&bytes[..4]).unwrap_err(),
+            PacketParseError::TooShort
+        );
+        assert_eq!(
+            Feedback::parse(&[0x80, 0, 0, 0, 0]).unwrap_err(),
+            PacketParseError::ReservedBitsSet(0x80)
+        );
+        assert_eq!(
+            Feedback::parse(&[0x81, 0, 0, 0, 0, 0]).unwrap_err(),
+            PacketParseError::Oversized { len: 6, max: 5 }
+        );
+    }
+
+    #[test]
+    fn test_ack_parse_errors() {
+        assert_eq!(
+            Ack::parse(&[0x80, 1, 0, 0, 0, 0]).unwrap_err(),
+            PacketParseError::TooShort
+        );
+        // a data packet isn't an ack
+        assert_eq!(
+            Ack::parse(&[0x01, 1, 0, 0, 0, 0, 0]).unwrap_err(),
+            PacketParseError::ReservedBitsSet(0x01)
+        );
+        // neither is an ack with flags we don't know
+        assert!(Ack::parse(&[0x80, 1, 0x04, 0, 0, 0, 0]).is_err());
+        let mut oversized = vec![0x80, 1, 0, 0, 0, 0, 0];
+        oversized.resize(7 + 129, 0);
+        assert_eq!(
+            Ack::parse(&oversized).unwrap_err(),
+            PacketParseError::Oversized { len: 136, max: 135 }
+        );
+    }
+
+    #[test]
+    fn test_parity_round_trip() {
+        let parity = Parity::new(3, 10, &[b"abcd", b"ef", b"xyz"], true);
+        assert_eq!(parity.count, 3);
+        assert_eq!(parity.length_xor, 4 ^ 2 ^ 3);
+        assert_eq!(
+            parity.payload,
+            [b'a' ^ b'e' ^ b'x', b'b' ^ b'f' ^ b'y', b'c' ^ b'z', b'd']
+        );
+        assert_eq!(parity.packet_numbers(), 10..=12);
+
+        let bytes = parity.to_bytes();
+        assert_eq!(bytes[..7], [0x0A, 3, 0, 10, 3, 0, 4 ^ 2 ^ 3]);
+        let parser = PacketParser {
+            capabilities: Capabilities::PARITY.union(Capabilities::CHECKSUMS),
+            ..Default::default()
+        };
+        let mut datagram = bytes.clone();
+        append_checksum(&mut datagram);
+        assert_eq!(
+            parser.parse(&datagram).unwrap(),
+            PacketRef::Parity(ParityRef {
+                file_id: 3,
+                first_packet_number: 10,
+                count: 3,
+                is_last_group: true,
+                length_xor: 4 ^ 2 ^ 3,
+                payload: &bytes[7..],
+            })
+        );
+        assert_eq!(
+            Packet::from(parser.parse(&datagram).unwrap()),
+            Packet::Parity(parity)
+        );
+    }
+
+    #[test]
+    fn test_wide_packet_numbers_need_the_capability() {
+        let data = Data {
+            file_id: 3,
+            packet_number: 0x0001_0002,
+            is_last_packet: true,
+            payload: vec![9; 1024],
+        };
+        let bytes = data.to_bytes();
+        assert_eq!(&bytes[..6], [0x13, 3, 0, 1, 0, 2]);
+        assert_eq!(bytes.len(), 1030);
+        // numbers that fit keep the legacy format
+        let legacy = Data {
+            packet_number: 0xFFFF,
+            ..data
+        };
+        assert_eq!(&legacy.to_bytes()[..4], [0x03, 3, 0xFF, 0xFF]);
+
+        assert_eq!(
+            PacketParser::default().parse(&bytes[..10]).unwrap_err(),
+            PacketParseError::ReservedBitsSet(0x13)
+        );
+        let parser = PacketParser {
+            capabilities: Capabilities::LARGE_FILES,
+            ..Default::default()
+        };
+        let PacketRef::Data(parsed) = parser.parse(&bytes).unwrap() else {
+            panic!("not a data packet");
+        };
+        assert_eq!(parsed.packet_number, 0x0001_0002);
+        assert!(parsed.is_last_packet);
+        assert_eq!(parsed.payload.len(), 1024);
+
+        // only data and parity packets have packet numbers to widen
+        for status in [0x10, 0x14] {
+            assert_eq!(
+                parser.parse(&[status, 0, 0, 0, 1, 0]).unwrap_err(),
+                PacketParseError::ReservedBitsSet(status)
+            );
+        }
+        assert_eq!(
+            parser.parse(&bytes[..5]).unwrap_err(),
+            PacketParseError::DataTooShort { len: 5 }
+        );
+        let mut oversized = bytes.clone();
+        oversized.push(0);
+        assert_eq!(
+            parser.parse(&oversized).unwrap_err(),
+            PacketParseError::Oversized {
+                len: 1031,
+                max: 1030
+            }
+        );
+    }
+
+    #[test]
+    fn test_metadata_needs_the_extended_header() {
+        let header = Header {
+            file_id: 2,
+            file_name: "a".into(),
+            expected_packet_count: 0,
+            metadata: Some(FileMetadata {
+                size: 0x0102,
+                mtime: -1,
+                mtime_nanos: 7,
+                mode: 0o640,
+            }),
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(
+            bytes,
+            [
+                0x40, 2, 0, 0, 0, 0, 0, 0, 1, 2, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0,
+                0, 0, 7, 0, 0, 0x01, 0xA0, b'a'
+            ]
+        );
+        assert_eq!(
+            PacketParser::default().parse(&bytes).unwrap_err(),
+            PacketParseError::ReservedBitsSet(0x40)
+        );
+        let parser = PacketParser {
+            capabilities: Capabilities::EXTENDED_HEADER,
+            ..Default::default()
+        };
+        assert_eq!(
+            Packet::from(parser.parse(&bytes).unwrap()),
+            Packet::Header(header)
+        );
+        assert_eq!(
+            parser.parse(&bytes[..26]).unwrap_err(),
+            PacketParseError::EmptyFileName
+        );
+        assert_eq!(
+            parser.parse(&bytes[..20]).unwrap_err(),
+            PacketParseError::TooShort
+        );
+
+        // only headers carry metadata
+        assert_eq!(
+            parser.parse(&[0x41, 2, 0, 0]).unwrap_err(),
+            PacketParseError::ReservedBitsSet(0x41)
+        );
+
+        // a long name still fits next to the metadata
+        let mut long = bytes[..26].to_vec();
+        long.extend(std::iter::repeat_n(b'n', 1026));
+        assert!(parser.parse(&long).is_ok());
+        long.push(b'n');
+        assert_eq!(
+            parser.parse(&long).unwrap_err(),
+            PacketParseError::Oversized {
+                len: 1053,
+                max: 1052
+            }
+        );
+    }
+
+    #[test]
+    fn test_metadata_times_before_the_epoch() {
+        let metadata = FileMetadata {
+            size: 0,
+            mtime: -2,
+            mtime_nanos: 250_000_000,
+            mode: 0o644,
+        };
+        assert_eq!(
+            metadata.modified(),
+            std::time::UNIX_EPOCH.checked_sub(std::time::Duration::from_millis(1750))
+        );
+    }
+
+    #[test]
+    fn test_wide_file_ids_need_the_capability() {
+        let header = Header {
+            file_id: 0x0102_0304,
+            file_name: "wide".into(),
+            expected_packet_count: 0,
+            metadata: None,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes, [0x20, 1, 2, 3, 4, b'w', b'i', b'd', b'e']);
+        assert_eq!(
+            PacketParser::default().parse(&bytes).unwrap_err(),
+            PacketParseError::ReservedBitsSet(0x20)
+        );
+        let parser = PacketParser {
+            capabilities: Capabilities::WIDE_FILE_IDS,
+            structured: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Packet::from(parser.parse(&bytes).unwrap()),
+            Packet::Header(header)
+        );
+        assert_eq!(
+            parser.parse(&bytes[..5]).unwrap_err(),
+            PacketParseError::EmptyFileName
+        );
+
+        let data = Data {
+            file_id: 256,
+            packet_number: 1,
+            is_last_packet: false,
+            payload: vec![5; 1024],
+        };
+        let bytes = data.to_bytes();
+        assert_eq!(&bytes[..7], [0x21, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(bytes.len(), 1031);
+        assert_eq!(
+            Packet::from(parser.parse(&bytes).unwrap()),
+            Packet::Data(data)
+        );
+        assert_eq!(
+            parser.parse(&bytes[..6]).unwrap_err(),
+            PacketParseError::DataTooShort { len: 6 }
+        );
+
+        // a part of a wide manifest: the total, then 4 bytes per file ID
+        let manifest = Manifest {
+            file_ids: vec![1, 300],
+            total: Some(500),
+        };
+        let bytes = manifest.to_bytes();
+        assert_eq!(bytes, [0x24, 0, 0, 1, 0xF4, 0, 0, 0, 1, 0, 0, 1, 0x2C]);
+        let PacketRef::Manifest(parsed) = parser.parse(&bytes).unwrap() else {
+            panic!("not a manifest");
+        };
+        assert_eq!(parsed.file_ids().collect::<Vec<_>>(), [1, 300]);
+        assert_eq!(parsed.total, Some(500));
+        assert_eq!(
+            parser.parse(&bytes[..11]).unwrap_err(),
+            PacketParseError::PartialFileId { len: 11 }
+        );
+        assert_eq!(
+            parser.parse(&bytes[..5]).unwrap_err(),
+            PacketParseError::TooShort
+        );
+
+        // the client's acks widen the same way
+        let ack = Ack {
+            file_id: 70_000,
+            has_header: true,
+            cumulative: 3,
+            ..Default::default()
+        };
+        let bytes = ack.to_bytes();
+        assert_eq!(bytes, [0xA0, 0, 1, 0x11, 0x70, 0x01, 0, 0, 0, 3]);
+        assert_eq!(Ack::parse(&bytes).unwrap(), ack);
+        assert_eq!(
+            Ack::parse(&bytes[..9]).unwrap_err(),
+            PacketParseError::TooShort
+        );
+    }
+
+    #[test]
+    fn test_parity_needs_the_capability() {
+        let full = Parity::new(0, 0, &[&[7; 1024]], false).to_bytes();
+        assert_eq!(full.len(), 1031);
+        assert_eq!(
+            PacketParser::default().parse(&full[..7]).unwrap_err(),
+            PacketParseError::ReservedBitsSet(0x08)
+        );
+        assert_eq!(
+            PacketParser::default().parse(&full).unwrap_err(),
+            PacketParseError::Oversized {
+                len: 1031,
+                max: 1028
+            }
+        );
+
+        let parser = PacketParser {
+            capabilities: Capabilities::PARITY,
+            ..Default::default()
+        };
+        assert!(parser.parse(&full).is_ok());
+        // only the last packet bit may come with it, and a data packet is
+        // still limited to 1028 bytes
+        for status in [0x09, 0x0C, 0x18] {
+            assert_eq!(
+                parser.parse(&[status, 0, 0, 0, 1, 0, 0]).unwrap_err(),
+                PacketParseError::ReservedBitsSet(status)
+            );
+        }
+        let mut data = vec![1, 0, 0, 0];
+        data.resize(1029, 0);
+        assert_eq!(
+            parser.parse(&data).unwrap_err(),
+            PacketParseError::Oversized {
+                len: 1029,
+                max: 1028
+            }
+        );
+        assert_eq!(
+            parser.parse(&[0x08, 0, 0, 0, 1, 0]).unwrap_err(),
+            PacketParseError::TooShort
+        );
+    }
 }
@@ -0,0 +1,265 @@
+use segmented_file_system_client::file_manager::FileManager;
+use segmented_file_system_client::packet::Packet;
+use segmented_file_system_client::pcap::{
+    internet_checksum, server_datagrams, PcapDatagram, PcapReader, PcapWriter, LINKTYPE_ETHERNET,
+    LINKTYPE_LINUX_SLL, LINKTYPE_RAW,
+};
+
+use std::io::{self, Cursor};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn read_all(bytes: Vec<u8>) -> Vec<PcapDatagram> {
+        PcapReader::new(Cursor::new(bytes))
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    // A big-endian, nanosecond resolution pcap header, the way some non-x86
+    // capture hosts write it
+    fn big_endian_header(link_type: u32) -> Vec<u8> {
+        let mut header = vec![0xA1, 0xB2, 0x3C, 0x4D, 0, 2, 0, 4];
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&65535u32.to_be_bytes());
+        header.extend_from_slice(&link_type.to_be_bytes());
+        header
+    }
+
+    fn big_endian_record(seconds: u32, nanos: u32, frame: &[u8]) -> Vec<u8> {
+        let len = u32::try_from(frame.len()).unwrap();
+        let mut record = Vec::new();
+        record.extend_from_slice(&seconds.to_be_bytes());
+        record.extend_from_slice(&nanos.to_be_bytes());
+        record.extend_from_slice(&len.to_be_bytes());
+        record.extend_from_slice(&len.to_be_bytes());
+        record.extend_from_slice(frame);
+        record
+    }
+
+    // An IPv4 packet from 10.0.0.1:`source_port` to 10.0.0.2:7077. The
+    // checksums are left at zero, the reader doesn't check them.
+    fn ipv4_udp(protocol: u8, fragment: u16, source_port: u16, payload: &[u8]) -> Vec<u8> {
+        let udp_len = u16::try_from(8 + payload.len()).unwrap();
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&(20 + udp_len).to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&fragment.to_be_bytes());
+        packet.extend_from_slice(&[64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        packet.extend_from_slice(&source_port.to_be_bytes());
+        packet.extend_from_slice(&7077u16.to_be_bytes());
+        packet.extend_from_slice(&udp_len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn linux_sll(packet: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 14];
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(packet);
+        frame
+    }
+
+    #[test]
+    fn test_pcap_round_trip() {
+        let server = addr("127.0.0.1:6014");
+        let client = addr("127.0.0.1:7077");
+        let mut writer = PcapWriter::new(Vec::new(), server, client).unwrap();
+        writer
+            .record_at(Duration::from_micros(10), &[0, 1, b'a'])
+            .unwrap();
+        writer
+            .record_at(Duration::from_micros(1510), &[3, 1, 0, 0, b'x'])
+            .unwrap();
+
+        let bytes = writer.into_inner();
+        assert_eq!(&bytes[..4], &[0xD4, 0xC3, 0xB2, 0xA1]);
+        assert_eq!(
+            PcapReader::new(Cursor::new(bytes.clone()))
+                .unwrap()
+                .link_type(),
+            LINKTYPE_ETHERNET
+        );
+
+        let datagrams = read_all(bytes);
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[0].source, server);
+        assert_eq!(datagrams[0].destination, client);
+        assert_eq!(datagrams[0].data, vec![0, 1, b'a']);
+        assert_eq!(datagrams[1].data, vec![3, 1, 0, 0, b'x']);
+        assert_eq!(
+            datagrams[1].timestamp.checked_sub(datagrams[0].timestamp),
+            Some(Duration::from_micros(1500))
+        );
+    }
+
+    #[test]
+    fn test_pcap_writer_checksums_verify() {
+        let mut writer =
+            PcapWriter::new(Vec::new(), addr("10.1.2.3:6014"), addr("10.1.2.4:7077")).unwrap();
+        // odd length, so the UDP checksum has to pad
+        writer
+            .record_at(Duration::ZERO, b"\x00\x07odd.txt")
+            .unwrap();
+        let bytes = writer.into_inner();
+
+        // 24 byte file header, 16 byte record header, 14 byte Ethernet header
+        let ip = &bytes[54..];
+        assert_eq!(internet_checksum(&[&ip[..20]]), 0);
+
+        let udp = &ip[20..];
+        let mut pseudo_header = [0; 12];
+        pseudo_header[..8].copy_from_slice(&ip[12..20]);
+        pseudo_header[9] = 17;
+        pseudo_header[10..].copy_from_slice(&udp[4..6]);
+        assert_eq!(internet_checksum(&[&pseudo_header, udp]), 0);
+    }
+
+    #[test]
+    fn test_pcap_writer_maps_ipv6_addresses() {
+        let mut writer = PcapWriter::new(
+            Vec::new(),
+            addr("[::1]:6014"),
+            addr("[::ffff:10.0.0.9]:7077"),
+        )
+        .unwrap();
+        writer.record_at(Duration::ZERO, &[0, 1, b'a']).unwrap();
+
+        let datagrams = read_all(writer.into_inner());
+        assert_eq!(datagrams[0].source, addr("127.0.0.1:6014"));
+        assert_eq!(datagrams[0].destination, addr("10.0.0.9:7077"));
+    }
+
+    #[test]
+    fn test_pcap_reads_big_endian_linux_cooked_capture() {
+        let mut bytes = big_endian_header(LINKTYPE_LINUX_SLL);
+        let data = ipv4_udp(17, 0, 6014, &[0, 2, b'b']);
+        bytes.extend(big_endian_record(100, 250, &linux_sll(&data)));
+        // TCP and a non-first fragment are skipped
+        let tcp = ipv4_udp(6, 0, 6014, &[0, 3, b'c']);
+        bytes.extend(big_endian_record(101, 0, &linux_sll(&tcp)));
+        let fragment = ipv4_udp(17, 0x0010, 6014, &[0, 4, b'd']);
+        bytes.extend(big_endian_record(102, 0, &linux_sll(&fragment)));
+
+        let datagrams = read_all(bytes);
+        assert_eq!(
+            datagrams,
+            vec![PcapDatagram {
+                timestamp: Duration::new(100, 250),
+                source: addr("10.0.0.1:6014"),
+                destination: addr("10.0.0.2:7077"),
+                data: vec![0, 2, b'b'],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pcap_reads_raw_ipv6() {
+        let payload = [1, 5, 0, 0, b'z'];
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&13u16.to_be_bytes());
+        packet.extend_from_slice(&[17, 64]);
+        packet.extend_from_slice(&[0; 15]);
+        packet.push(1);
+        packet.extend_from_slice(&[0; 15]);
+        packet.push(2);
+        packet.extend_from_slice(&6014u16.to_be_bytes());
+        packet.extend_from_slice(&7077u16.to_be_bytes());
+        packet.extend_from_slice(&13u16.to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&payload);
+
+        let mut bytes = big_endian_header(LINKTYPE_RAW);
+        bytes.extend(big_endian_record(0, 0, &packet));
+
+        let datagrams = read_all(bytes);
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].source, addr("[::1]:6014"));
+        assert_eq!(datagrams[0].destination, addr("[::2]:7077"));
+        assert_eq!(datagrams[0].data, payload);
+    }
+
+    #[test]
+    fn test_pcap_skips_datagrams_cut_by_snaplen() {
+        let mut bytes = big_endian_header(LINKTYPE_RAW);
+        let mut packet = ipv4_udp(17, 0, 6014, &[0, 2, b'b', b'c']);
+        packet.pop();
+        bytes.extend(big_endian_record(0, 0, &packet));
+
+        assert!(read_all(bytes).is_empty());
+    }
+
+    #[test]
+    fn test_pcap_skips_packets_cut_inside_the_ip_header() {
+        let mut bytes = big_endian_header(LINKTYPE_RAW);
+        let packet = ipv4_udp(17, 0, 6014, &[0, 2, b'b']);
+        bytes.extend(big_endian_record(0, 0, &packet[..12]));
+        bytes.extend(big_endian_record(0, 0, &packet[..18]));
+
+        assert!(read_all(bytes).is_empty());
+    }
+
+    #[test]
+    fn test_pcap_rejects_bad_files() {
+        let result = PcapReader::new(Cursor::new(vec![0; 24]));
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        // 802.11 isn't something we can decode
+        let result = PcapReader::new(Cursor::new(big_endian_header(105)));
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = big_endian_header(LINKTYPE_RAW);
+        bytes.extend_from_slice(&[0; 10]);
+        let error = PcapReader::new(Cursor::new(bytes))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_server_datagrams_feed_file_manager() {
+        let mut bytes = big_endian_header(LINKTYPE_RAW);
+        // the client's hello goes the other way and must be left out
+        let mut hello = ipv4_udp(17, 0, 7077, &[0; 8]);
+        hello[22..24].copy_from_slice(&6014u16.to_be_bytes());
+        bytes.extend(big_endian_record(50, 0, &hello));
+        bytes.extend(big_endian_record(
+            50,
+            1_000,
+            &ipv4_udp(17, 0, 6014, b"\x00\x08pcap.txt"),
+        ));
+        bytes.extend(big_endian_record(
+            50,
+            3_000,
+            &ipv4_udp(17, 0, 6014, &[3, 8, 0, 0, b'p']),
+        ));
+
+        let reader = PcapReader::new(Cursor::new(bytes)).unwrap();
+        let mut file_manager = FileManager::default();
+        let mut offsets = Vec::new();
+        for datagram in server_datagrams(reader, 6014) {
+            let datagram = datagram.unwrap();
+            offsets.push(datagram.offset);
+            file_manager
+                .process_packet(Packet::try_from(datagram.data.as_slice()).unwrap())
+                .unwrap();
+        }
+
+        assert_eq!(offsets, vec![Duration::ZERO, Duration::from_micros(2)]);
+        assert!(file_manager.received_all_packets());
+        let group = file_manager.get_packet_group(8).unwrap();
+        assert_eq!(group.packets[&0], vec![b'p']);
+    }
+}
@@ -0,0 +1,73 @@
+use segmented_file_system_client::errors::PacketParseError;
+use segmented_file_system_client::quarantine::{Quarantine, INDEX_FILE_NAME};
+
+use std::fs;
+use std::path::PathBuf;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sfs-quarantine-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_save_writes_datagram_and_index() {
+        let dir = temp_dir("save");
+        let mut quarantine = Quarantine::open(&dir).unwrap();
+
+        let path = quarantine
+            .save(&[0xFF, 1, 2], &PacketParseError::ReservedBitsSet(0xFF))
+            .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![0xFF, 1, 2]);
+        assert_eq!(quarantine.saved(), 1);
+
+        let index = fs::read_to_string(dir.join(INDEX_FILE_NAME)).unwrap();
+        let fields: Vec<&str> = index.trim_end().split('\t').collect();
+        assert_eq!(fields.len(), 4);
+        assert!(fields[0].parse::<u128>().is_ok());
+        assert_eq!(fields[1], "3");
+        assert_eq!(dir.join(fields[2]), path);
+        assert_eq!(fields[3], "reserved bits set in status byte 0xff");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_each_datagram_gets_its_own_file() {
+        let dir = temp_dir("unique");
+        let mut quarantine = Quarantine::open(&dir).unwrap();
+
+        let first = quarantine.save(&[0], &PacketParseError::TooShort).unwrap();
+        let second = quarantine.save(&[1], &PacketParseError::TooShort).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(fs::read(first).unwrap(), vec![0]);
+        assert_eq!(fs::read(second).unwrap(), vec![1]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopening_appends_to_index() {
+        let dir = temp_dir("reopen");
+        Quarantine::open(&dir)
+            .unwrap()
+            .save(&[0], &PacketParseError::TooShort)
+            .unwrap();
+        let mut quarantine = Quarantine::open(&dir).unwrap();
+        assert_eq!(quarantine.saved(), 0);
+        quarantine
+            .save(&[0, 1], &PacketParseError::EmptyFileName)
+            .unwrap();
+
+        let index = fs::read_to_string(dir.join(INDEX_FILE_NAME)).unwrap();
+        assert_eq!(index.lines().count(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use segmented_file_system_client::capture::{CaptureWriter, CapturedDatagram};
+use segmented_file_system_client::file_manager::FileManager;
+use segmented_file_system_client::hello::{Capabilities, HelloReply};
+use segmented_file_system_client::packet::{append_checksum, PacketParser};
+use segmented_file_system_client::pcap::PcapWriter;
+use segmented_file_system_client::replay::{open_capture, replay_offline, serve, ReplayReport};
+
+use std::io;
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn captured(datagrams: &[&[u8]]) -> Vec<io::Result<CapturedDatagram>> {
+        datagrams
+            .iter()
+            .enumerate()
+            .map(|(i, data)| {
+                Ok(CapturedDatagram {
+                    offset: Duration::from_millis(i as u64),
+                    data: data.to_vec(),
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_replay_offline_assembles_files() {
+        let datagrams = captured(&[
+            &[1, 4, 0, 0, b'a'],
+            &[0xF0, 4],
+            b"\x00\x04replayed.txt",
+            &[3, 4, 0, 1, b'b'],
+            // after everything is complete, so never fed in
+            &[0xF0],
+        ]);
+
+        let mut file_manager = FileManager::default();
+        let report =
+            replay_offline(datagrams, &PacketParser::default(), &mut file_manager).unwrap();
+        assert_eq!(
+            report,
+            ReplayReport {
+                datagrams: 4,
+                parse_errors: 1,
+                rejected_packets: 0,
+                complete: true,
+            }
+        );
+
+        let group = file_manager.get_packet_group(4).unwrap();
+        assert_eq!(group.expected_packet_count, Some(2));
+        assert_eq!(group.packets[&0], vec![b'a']);
+        assert_eq!(group.packets[&1], vec![b'b']);
+    }
+
+    #[test]
+    fn test_replay_offline_reports_incomplete_capture() {
+        let datagrams = captured(&[&[1, 4, 0, 0, b'a']]);
+        let mut file_manager = FileManager::default();
+        let report =
+            replay_offline(datagrams, &PacketParser::default(), &mut file_manager).unwrap();
+        assert!(!report.complete);
+        assert_eq!(report.datagrams, 1);
+    }
+
+    #[test]
+    fn test_replay_offline_holds_packets_recorded_before_the_reply() {
+        let mut data = b"\x03\x04\x00\x00early".to_vec();
+        append_checksum(&mut data);
+        let mut header = b"\x00\x04early.txt".to_vec();
+        append_checksum(&mut header);
+        let reply = HelloReply {
+            version: 1,
+            capabilities: Capabilities::CHECKSUMS,
+        }
+        .to_bytes();
+        // the first reply was lost on the way; the repeat came after the burst
+        let datagrams = captured(&[&data, &header, &reply]);
+
+        let mut file_manager = FileManager::default();
+        let report =
+            replay_offline(datagrams, &PacketParser::default(), &mut file_manager).unwrap();
+        assert_eq!(report.parse_errors, 0);
+        assert!(report.complete);
+        let group = file_manager.get_packet_group(4).unwrap();
+        assert_eq!(group.packets[&0], b"early");
+    }
+
+    #[test]
+    fn test_replay_offline_stops_on_read_error() {
+        let mut datagrams = captured(&[&[1, 4, 0, 0, b'a']]);
+        datagrams.push(Err(io::ErrorKind::UnexpectedEof.into()));
+        let mut file_manager = FileManager::default();
+        let result = replay_offline(datagrams, &PacketParser::default(), &mut file_manager);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_serve_sends_capture_after_hello() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let datagrams = captured(&[&[1, 4, 0, 0, b'a'], b"\x00\x04served.txt"]);
+        let handle = thread::spawn(move || serve(&server, datagrams, true).unwrap());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server_addr).unwrap();
+        client.send(&[0; 1028]).unwrap();
+
+        let mut buf = [0; 1028];
+        let len = client.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[1, 4, 0, 0, b'a']);
+        let len = client.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x00\x04served.txt");
+        assert_eq!(handle.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_open_capture_detects_format() {
+        let dir = std::env::temp_dir();
+        let native = dir.join(format!("sfs-replay-{}.sfscap", std::process::id()));
+        let mut writer = CaptureWriter::create(&native).unwrap();
+        writer.record_at(Duration::ZERO, &[0, 1, b'n']).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let pcap = dir.join(format!("sfs-replay-{}.pcap", std::process::id()));
+        let mut writer = PcapWriter::create(
+            &pcap,
+            "127.0.0.1:6014".parse().unwrap(),
+            "127.0.0.1:7077".parse().unwrap(),
+        )
+        .unwrap();
+        writer.record_at(Duration::ZERO, &[0, 1, b'p']).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let datagrams: Vec<CapturedDatagram> = open_capture(&native, 6014)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(datagrams[0].data, vec![0, 1, b'n']);
+
+        let datagrams: Vec<CapturedDatagram> = open_capture(&pcap, 6014)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(datagrams[0].data, vec![0, 1, b'p']);
+        // nothing was sent from this port
+        assert_eq!(open_capture(&pcap, 1234).unwrap().count(), 0);
+
+        std::fs::remove_file(native).unwrap();
+        std::fs::remove_file(pcap).unwrap();
+    }
+}
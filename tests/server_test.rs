@@ -0,0 +1,363 @@
+use segmented_file_system_client::client::run_client;
+use segmented_file_system_client::config::ClientConfig;
+use segmented_file_system_client::file_manager::FileManager;
+use segmented_file_system_client::hello::{Capabilities, Hello, HelloReply};
+use segmented_file_system_client::packet::{Feedback, Packet, PacketParser, PacketRef};
+use segmented_file_system_client::server::{serve_session, ServedFile, ServerOptions};
+
+use std::ffi::OsString;
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn served(name: &str, contents: Vec<u8>) -> ServedFile {
+        ServedFile {
+            name: OsString::from(name),
+            contents,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn test_reliable_session_survives_loss() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ClientConfig {
+            remote_addr: server.local_addr().unwrap().to_string(),
+            local_port: 0,
+            ack_interval: Duration::from_millis(5),
+            ..Default::default()
+        };
+        let big: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+        let files = vec![
+            served("server_test_big.bin", big.clone()),
+            served("server_test_small.txt", b"small".to_vec()),
+        ];
+        let options = ServerOptions {
+            window: 8,
+            retransmit_timeout: Duration::from_millis(30),
+            loss: 0.3,
+            seed: 42,
+            ..Default::default()
+        };
+        let handle = thread::spawn(move || serve_session(&server, &files, &options).unwrap());
+
+        std::fs::create_dir_all("src").unwrap();
+        run_client(&config).unwrap();
+        let report = handle.join().unwrap();
+
+        assert_eq!(report.capabilities, Capabilities::SUPPORTED);
+        // a manifest, then a header and 49 data packets, then a header and 1
+        assert_eq!(report.packets, 1 + 50 + 2);
+        assert!(report.dropped > 0);
+        // every dropped packet had to be sent again for the client to finish
+        assert!(report.retransmitted >= report.dropped);
+
+        assert_eq!(std::fs::read("src/server_test_big.bin").unwrap(), big);
+        assert_eq!(
+            std::fs::read("src/server_test_small.txt").unwrap(),
+            b"small"
+        );
+        std::fs::remove_file("src/server_test_big.bin").unwrap();
+        std::fs::remove_file("src/server_test_small.txt").unwrap();
+    }
+
+    #[test]
+    fn test_reliable_reply_follows_the_window_until_acked() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let files = vec![served("reply.txt", b"reply".to_vec())];
+        let options = ServerOptions {
+            window: 2,
+            idle_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let handle = thread::spawn(move || serve_session(&server, &files, &options));
+
+        client
+            .send(&Hello::new(Capabilities::RETRANSMIT).to_bytes())
+            .unwrap();
+        let mut buf = [0; 2048];
+        // the first reply is "lost"; the window follows, then the reply again
+        let len = client.recv(&mut buf).unwrap();
+        assert!(HelloReply::parse(&buf[..len]).is_some());
+        let mut packets = 0;
+        loop {
+            let len = client.recv(&mut buf).unwrap();
+            if HelloReply::parse(&buf[..len]).is_some() {
+                break;
+            }
+            packets += 1;
+        }
+        assert_eq!(packets, 2);
+
+        // nothing is ever acked, so the server gives up
+        assert!(handle.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_legacy_hello_gets_plain_packets() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let files = vec![served("empty.txt", Vec::new())];
+        let handle =
+            thread::spawn(move || serve_session(&server, &files, &ServerOptions::default()));
+
+        client.send(&[0; 1028]).unwrap();
+        let mut buf = [0; 2048];
+        let mut packets = Vec::new();
+        for _ in 0..2 {
+            let len = client.recv(&mut buf).unwrap();
+            packets.push(Packet::from(
+                PacketParser::default().parse(&buf[..len]).unwrap(),
+            ));
+        }
+        let report = handle.join().unwrap().unwrap();
+
+        // no reply, no manifest, and an empty file is one empty last packet
+        assert_eq!(report.capabilities, Capabilities::NONE);
+        assert_eq!(report.packets, 2);
+        assert!(matches!(&packets[0], Packet::Header(header) if header.file_name == "empty.txt"));
+        assert!(
+            matches!(&packets[1], Packet::Data(data) if data.is_last_packet && data.payload.is_empty())
+        );
+    }
+
+    #[test]
+    fn test_parity_packets_cover_a_lost_chunk() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let contents: Vec<u8> = (0..3000u32).map(|i| (i % 7) as u8).collect();
+        let files = vec![served("parity.bin", contents.clone())];
+        let options = ServerOptions {
+            parity_group: Some(2),
+            ..Default::default()
+        };
+        let handle = thread::spawn(move || serve_session(&server, &files, &options));
+
+        client
+            .send(&Hello::new(Capabilities::PARITY).to_bytes())
+            .unwrap();
+        let mut buf = [0; 2048];
+        let len = client.recv(&mut buf).unwrap();
+        let parser = PacketParser {
+            capabilities: HelloReply::parse(&buf[..len]).unwrap().capabilities,
+            structured: true,
+            ..Default::default()
+        };
+        assert_eq!(parser.capabilities, Capabilities::PARITY);
+
+        // a manifest, a header, 3 data packets and 2 parity packets; data
+        // packet 1 goes missing
+        let mut file_manager = FileManager::default();
+        for _ in 0..7 {
+            let len = client.recv(&mut buf).unwrap();
+            let packet = parser.parse(&buf[..len]).unwrap();
+            if !matches!(packet, PacketRef::Data(data) if data.packet_number == 1) {
+                file_manager.process_packet_ref(packet).unwrap();
+            }
+        }
+        let report = handle.join().unwrap().unwrap();
+
+        assert_eq!(report.parity, 2);
+        assert_eq!(file_manager.recovered(), 1);
+        assert!(file_manager.received_all_packets());
+        let file_group = &file_manager.files[&0];
+        let rebuilt: Vec<u8> = (0..3)
+            .flat_map(|n| file_group.packets[&n].clone())
+            .collect();
+        assert_eq!(rebuilt, contents);
+    }
+
+    #[test]
+    fn test_flow_control_paces_to_the_latest_feedback() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let files = vec![served("paced.bin", vec![7; 10 * 1024])];
+        let options = ServerOptions {
+            window: 4,
+            ..Default::default()
+        };
+
+        // both are queued before the server starts, so the feedback is
+        // picked up before the first burst
+        let hello = Hello {
+            rate: Some(1_000_000),
+            ..Hello::new(Capabilities::FLOW_CONTROL)
+        };
+        client.send(&hello.to_bytes()).unwrap();
+        client.send(&Feedback { rate: 500 }.to_bytes()).unwrap();
+        let started = Instant::now();
+        let handle = thread::spawn(move || serve_session(&server, &files, &options));
+
+        let mut buf = [0; 2048];
+        // the reply, a manifest, a header and 10 data packets
+        for _ in 0..13 {
+            client.recv(&mut buf).unwrap();
+        }
+        let report = handle.join().unwrap().unwrap();
+
+        assert_eq!(report.capabilities, Capabilities::FLOW_CONTROL);
+        assert_eq!(report.feedback, 1);
+        assert_eq!(report.rate, Some(500));
+        // 12 packets 2ms apart
+        assert!(started.elapsed() >= Duration::from_millis(22));
+    }
+
+    #[test]
+    fn test_wide_file_ids_split_the_manifest() {
+        let files: Vec<ServedFile> = (0..300)
+            .map(|i| served(&format!("file_{i}.txt"), Vec::new()))
+            .collect();
+
+        // 300 files don't fit 1 byte file IDs
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let narrow = files.clone();
+        let handle =
+            thread::spawn(move || serve_session(&server, &narrow, &ServerOptions::default()));
+        client
+            .send(&Hello::new(Capabilities::NONE).to_bytes())
+            .unwrap();
+        let error = handle.join().unwrap().unwrap_err();
+        assert_eq!(error.to_string(), "300 files is more than 256");
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let options = ServerOptions {
+            window: 16,
+            ..Default::default()
+        };
+        let handle = thread::spawn(move || serve_session(&server, &files, &options));
+        client
+            .send(&Hello::new(Capabilities::WIDE_FILE_IDS).to_bytes())
+            .unwrap();
+        let mut buf = [0; 2048];
+        let len = client.recv(&mut buf).unwrap();
+        let parser = PacketParser {
+            capabilities: HelloReply::parse(&buf[..len]).unwrap().capabilities,
+            structured: true,
+            ..Default::default()
+        };
+
+        // a manifest in 2 parts, then a header and a data packet per file,
+        // with the reply repeated after every burst
+        let mut file_manager = FileManager::default();
+        let mut replies = 0;
+        let mut packets = 0;
+        while packets < 2 + 300 * 2 {
+            let len = client.recv(&mut buf).unwrap();
+            if HelloReply::parse(&buf[..len]).is_some() {
+                replies += 1;
+                continue;
+            }
+            file_manager
+                .process_packet_ref(parser.parse(&buf[..len]).unwrap())
+                .unwrap();
+            packets += 1;
+        }
+        let report = handle.join().unwrap().unwrap();
+
+        assert_eq!(report.packets, 602);
+        // every burst but the last is followed by a reply before the next one
+        assert_eq!(replies, 602 / 16);
+        assert_eq!(file_manager.manifest_total, Some(300));
+        assert!(file_manager.received_all_packets());
+        assert_eq!(
+            file_manager.files[&299].file_name,
+            Some(OsString::from("file_299.txt"))
+        );
+    }
+
+    #[test]
+    fn test_extended_header_carries_file_metadata() {
+        let path = std::env::temp_dir().join(format!("metadata_{}.txt", std::process::id()));
+        std::fs::write(&path, b"metadata").unwrap();
+        let modified = std::time::UNIX_EPOCH + Duration::new(1_500_000_000, 123_456_789);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let file = ServedFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let metadata = file.metadata.unwrap();
+        assert_eq!(metadata.size, 8);
+        assert_eq!(metadata.modified(), Some(modified));
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let files = vec![file];
+        let handle =
+            thread::spawn(move || serve_session(&server, &files, &ServerOptions::default()));
+        client
+            .send(&Hello::new(Capabilities::EXTENDED_HEADER).to_bytes())
+            .unwrap();
+        let mut buf = [0; 2048];
+        let len = client.recv(&mut buf).unwrap();
+        let parser = PacketParser {
+            capabilities: HelloReply::parse(&buf[..len]).unwrap().capabilities,
+            structured: true,
+            ..Default::default()
+        };
+        assert_eq!(parser.capabilities, Capabilities::EXTENDED_HEADER);
+
+        // a manifest, then the header
+        let mut packets = Vec::new();
+        for _ in 0..2 {
+            let len = client.recv(&mut buf).unwrap();
+            packets.push(Packet::from(parser.parse(&buf[..len]).unwrap()));
+        }
+        drop(client);
+        let _ = handle.join().unwrap();
+        assert!(matches!(
+            &packets[1],
+            Packet::Header(header) if header.metadata == Some(metadata)
+        ));
+    }
+
+    #[test]
+    fn test_open_tree_names_files_by_their_paths() {
+        let root = std::env::temp_dir().join(format!("sfs-tree-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("docs").join("a")).unwrap();
+        std::fs::write(root.join("docs").join("a").join("b.txt"), b"b").unwrap();
+        std::fs::write(root.join("docs").join("top.txt"), b"top").unwrap();
+
+        let files = ServedFile::open_tree(&root.join("docs")).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        let names: Vec<_> = files.iter().map(|file| file.name.clone()).collect();
+        assert_eq!(names, ["docs/a/b.txt", "docs/top.txt"]);
+        assert_eq!(files[1].contents, b"top");
+    }
+
+    #[test]
+    fn test_names_must_fit_in_a_header() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let files = vec![served(&"d/".repeat(600), Vec::new())];
+        let handle =
+            thread::spawn(move || serve_session(&server, &files, &ServerOptions::default()));
+        client
+            .send(&Hello::new(Capabilities::NONE).to_bytes())
+            .unwrap();
+        let error = handle.join().unwrap().unwrap_err();
+        assert!(
+            error.to_string().ends_with("1200 bytes is more than 1026"),
+            "{error}"
+        );
+    }
+}
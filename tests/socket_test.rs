@@ -0,0 +1,156 @@
+use segmented_file_system_client::socket::{
+    bind_socket, connect_pair, connect_to_server, effective_recv_buffer_size, server_addresses,
+    SocketOptions,
+};
+
+use std::net::{SocketAddr, UdpSocket};
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn loopback() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    #[test]
+    fn test_default_options_bind() {
+        let sock = bind_socket(loopback(), &SocketOptions::default()).unwrap();
+        assert!(sock.local_addr().unwrap().port() != 0);
+        assert!(effective_recv_buffer_size(&sock).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_recv_buffer_size_is_applied() {
+        let small = SocketOptions {
+            recv_buffer_size: Some(4096),
+            ..Default::default()
+        };
+        let large = SocketOptions {
+            recv_buffer_size: Some(64 * 1024),
+            ..Default::default()
+        };
+        let small_sock = bind_socket(loopback(), &small).unwrap();
+        let large_sock = bind_socket(loopback(), &large).unwrap();
+
+        // The kernel is free to round the request, but a bigger request
+        // should never get a smaller buffer
+        let small_granted = effective_recv_buffer_size(&small_sock).unwrap();
+        let large_granted = effective_recv_buffer_size(&large_sock).unwrap();
+        assert!(small_granted > 0);
+        assert!(large_granted >= small_granted);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reuse_port_allows_second_bind() {
+        let options = SocketOptions {
+            reuse_address: true,
+            reuse_port: true,
+            ..Default::default()
+        };
+        let first = bind_socket(loopback(), &options).unwrap();
+        let addr = first.local_addr().unwrap();
+        assert!(bind_socket(addr, &options).is_ok());
+    }
+
+    #[test]
+    fn test_bind_in_use_without_reuse_fails() {
+        let first = bind_socket(loopback(), &SocketOptions::default()).unwrap();
+        let addr = first.local_addr().unwrap();
+        assert!(bind_socket(addr, &SocketOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_dual_stack_ipv6_bind() {
+        let options = SocketOptions {
+            dual_stack: Some(true),
+            ..Default::default()
+        };
+        // Not every sandbox has IPv6, so only check the option when the bind works
+        if let Ok(sock) = bind_socket("[::]:0".parse().unwrap(), &options) {
+            assert!(sock.local_addr().unwrap().is_ipv6());
+            assert!(!socket2::SockRef::from(&sock).only_v6().unwrap());
+        }
+    }
+
+    // IPv6 may be disabled in some CI containers; those tests just return early
+    fn ipv6_loopback_server() -> Option<UdpSocket> {
+        UdpSocket::bind("[::1]:0").ok()
+    }
+
+    #[test]
+    fn test_connect_derives_ipv4_bind_address() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let remote = server.local_addr().unwrap().to_string();
+
+        let sock = connect_to_server(&remote, None, 0, &SocketOptions::default()).unwrap();
+        assert!(sock.local_addr().unwrap().is_ipv4());
+        assert_eq!(sock.peer_addr().unwrap(), server.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_connect_derives_ipv6_bind_address() {
+        let Some(server) = ipv6_loopback_server() else {
+            return;
+        };
+        let remote = server.local_addr().unwrap().to_string();
+
+        let sock = connect_to_server(&remote, None, 0, &SocketOptions::default()).unwrap();
+        assert!(sock.local_addr().unwrap().is_ipv6());
+
+        sock.send(b"hello").unwrap();
+        let mut buf = [0; 16];
+        let (len, from) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(from, sock.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_connect_picks_local_address_matching_remote_family() {
+        let Some(server) = ipv6_loopback_server() else {
+            return;
+        };
+        let remote = server.local_addr().unwrap().to_string();
+
+        let sock =
+            connect_to_server(&remote, Some("[::1]:0"), 0, &SocketOptions::default()).unwrap();
+        assert!(sock.local_addr().unwrap().is_ipv6());
+
+        // An IPv4 local address can't reach an IPv6 remote, so nothing is tried
+        let result = connect_to_server(&remote, Some("127.0.0.1:0"), 0, &SocketOptions::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_server_addresses_resolves_hostname() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        // localhost may resolve to ::1 first, but the server's address must be
+        // among the candidates, paired with a local address of its family
+        let addrs = server_addresses(&format!("localhost:{port}"), None, 0).unwrap();
+        let &(local, remote) = addrs
+            .iter()
+            .find(|(_, remote)| *remote == server.local_addr().unwrap())
+            .unwrap();
+        assert!(local.is_ipv4());
+
+        let sock = connect_pair(local, remote, &SocketOptions::default()).unwrap();
+        sock.send(b"hello").unwrap();
+        let mut buf = [0; 16];
+        let (len, from) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(from, sock.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_server_addresses_skips_families_without_local_address() {
+        let result = server_addresses("[::1]:9", Some("127.0.0.1:0"), 0);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            std::io::ErrorKind::AddrNotAvailable
+        );
+    }
+}